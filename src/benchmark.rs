@@ -0,0 +1,94 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Measures how much is sent to the terminal.
+
+use tetris::bot;
+use tetris::config::Colours;
+use tetris::config::Glyphs;
+use tetris::model::Board;
+use tetris::model::Game;
+use tetris::model::PieceFactory;
+use tetris::registry::Registry;
+
+use crate::layout::Layout;
+use crate::render::Renderer;
+use crate::terminal::Terminal;
+use crate::theme::Theme;
+use crate::view;
+
+/// Sends the frame, then all of it again, and returns the bytes each
+/// took.
+fn frame_bytes(screen: &Terminal<Vec<u8>>) -> (usize, usize) {
+    screen.present();
+    let changed = screen.take_output().len();
+    screen.redraw_all();
+    screen.present();
+    (changed, screen.take_output().len())
+}
+
+/// Lets the bot play and tells how many bytes the frames take to send
+/// to the terminal, as they are and if they were sent in full.
+pub fn redraw() {
+    let screen = Terminal::with_writer(vec![], 256, (80, 24));
+    let theme = Theme::new(
+        Glyphs::Blocks,
+        Colours::Ansi256,
+        &Registry::default(),
+        &screen,
+    );
+    let mut g = Game::new(Board::new(10, 20), PieceFactory::with_seed(1));
+    let l = Layout::new(
+        10,
+        20,
+        1,
+        g.preview_size(),
+        theme.geometry(),
+        false,
+        screen.size(),
+    )
+    .expect("the board fits on 80x24");
+    view::reset(&screen, &theme, &l);
+    let weights = bot::Weights::default();
+    let (mut frames, mut changed, mut full) = (0, 0, 0);
+    let mut draw = |g: &Game| {
+        view::draw_in_win(g, &screen, &theme, &l);
+        let (c, f) = frame_bytes(&screen);
+        frames += 1;
+        changed += c;
+        full += f;
+    };
+    for _ in 0..200 {
+        let p = match bot::best_placement(&g, &weights) {
+            Some(p) if !g.game_over => p,
+            _ => break,
+        };
+        // Like a player, a key press at a time.
+        for _ in 0..p.rotation {
+            g.up();
+            draw(&g);
+        }
+        while g.pos.0 != p.x {
+            let x = g.pos.0;
+            if x < p.x {
+                g.right();
+            } else {
+                g.left();
+            }
+            draw(&g);
+            if g.pos.0 == x {
+                break;
+            }
+        }
+        g.fall();
+        g.events.clear();
+        draw(&g);
+    }
+    println!(
+        "{} frames, {} bytes a frame with only the changes, {} in full",
+        frames,
+        changed / frames,
+        full / frames
+    );
+}
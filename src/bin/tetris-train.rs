@@ -0,0 +1,412 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tunes the bot's weights offline with the cross-entropy method.
+//!
+//! Every generation a population of weights is sampled from a normal
+//! distribution, each candidate plays the same seeded games, and the
+//! distribution is moved towards the candidates that cleared the most
+//! lines.
+
+#![forbid(unsafe_code)]
+
+use std::cmp::Ordering;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use tetris::bot::{self, Features, Weights, FEATURES, FEATURE_NAMES};
+use tetris::model::{Board, Game, PieceFactory};
+
+const CHECKPOINT_VERSION: u32 = 1;
+
+struct Options {
+    generations: usize,
+    population: usize,
+    elite: usize,
+    games: usize,
+    pieces: usize,
+    threads: usize,
+    seed: u64,
+    checkpoint: PathBuf,
+    resume: bool,
+}
+
+/// The distribution and the last evaluated population.
+#[derive(Debug, PartialEq)]
+struct State {
+    generation: usize,
+    mean: Features,
+    sd: Features,
+    population: Vec<(Weights, f64)>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: tetris-train [--generations N] [--population N] [--elite N] \
+         [--games N] [--pieces N] [--threads N] [--seed N] \
+         [--checkpoint FILE] [--resume]"
+    );
+    std::process::exit(1);
+}
+
+fn parse_args() -> Options {
+    let mut o = Options {
+        generations: 20,
+        population: 50,
+        elite: 10,
+        games: 8,
+        pieces: 1000,
+        threads: 4,
+        seed: 0,
+        checkpoint: PathBuf::from("tetris-train.checkpoint"),
+        resume: false,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--resume" {
+            o.resume = true;
+            continue;
+        }
+        let value = args.next().unwrap_or_else(|| usage());
+        let number = || value.parse::<usize>().unwrap_or_else(|_| usage());
+        match arg.as_str() {
+            "--generations" => o.generations = number(),
+            "--population" => o.population = number(),
+            "--elite" => o.elite = number(),
+            "--games" => o.games = number(),
+            "--pieces" => o.pieces = number(),
+            "--threads" => o.threads = number().max(1),
+            "--seed" => o.seed = value.parse().unwrap_or_else(|_| usage()),
+            "--checkpoint" => o.checkpoint = PathBuf::from(&value),
+            _ => usage(),
+        }
+    }
+    if o.elite == 0 || o.elite > o.population || o.games == 0 {
+        usage();
+    }
+    o
+}
+
+fn write_numbers(out: &mut String, name: &str, numbers: &[f64]) {
+    out.push_str(name);
+    for n in numbers {
+        out.push(' ');
+        out.push_str(&n.to_string());
+    }
+    out.push('\n');
+}
+
+fn checkpoint_text(s: &State) -> String {
+    let mut out = format!("tetris-train {}\n", CHECKPOINT_VERSION);
+    out.push_str(&format!("generation {}\n", s.generation));
+    write_numbers(&mut out, "mean", &s.mean);
+    write_numbers(&mut out, "sd", &s.sd);
+    for (w, fitness) in &s.population {
+        let mut numbers = vec![*fitness];
+        numbers.extend_from_slice(&w.0);
+        write_numbers(&mut out, "candidate", &numbers);
+    }
+    out
+}
+
+fn save_checkpoint(o: &Options, s: &State) -> std::io::Result<()> {
+    let out = checkpoint_text(s);
+    // Write and rename so a crash never leaves half a checkpoint.
+    let tmp = o.checkpoint.with_extension("tmp");
+    fs::File::create(&tmp)?.write_all(out.as_bytes())?;
+    fs::rename(&tmp, &o.checkpoint)
+}
+
+fn parse_features(numbers: &[f64]) -> Result<Features, String> {
+    if numbers.len() != FEATURES {
+        return Err(format!(
+            "expected {} numbers, got {}",
+            FEATURES,
+            numbers.len()
+        ));
+    }
+    let mut f = [0.0; FEATURES];
+    f.copy_from_slice(numbers);
+    Ok(f)
+}
+
+fn parse_checkpoint(text: &str) -> Result<State, String> {
+    let mut lines = text.lines();
+    if lines.next() != Some(&format!("tetris-train {}", CHECKPOINT_VERSION)) {
+        return Err("not a version 1 checkpoint".to_string());
+    }
+    let mut s = State {
+        generation: 0,
+        mean: [0.0; FEATURES],
+        sd: [0.0; FEATURES],
+        population: vec![],
+    };
+    for line in lines {
+        let mut words = line.split_whitespace();
+        let key = words.next().unwrap_or("");
+        let numbers = words
+            .map(|w| w.parse::<f64>().map_err(|e| format!("{}: {}", line, e)))
+            .collect::<Result<Vec<f64>, String>>()?;
+        match key {
+            "generation" if numbers.len() == 1 => s.generation = numbers[0] as usize,
+            "mean" => s.mean = parse_features(&numbers)?,
+            "sd" => s.sd = parse_features(&numbers)?,
+            "candidate" if !numbers.is_empty() => {
+                let w = Weights(parse_features(&numbers[1..])?);
+                s.population.push((w, numbers[0]));
+            }
+            _ => return Err(format!("unknown line: {}", line)),
+        }
+    }
+    Ok(s)
+}
+
+fn load_checkpoint(o: &Options) -> Result<State, String> {
+    let text = fs::read_to_string(&o.checkpoint).map_err(|e| e.to_string())?;
+    parse_checkpoint(&text)
+}
+
+/// Samples a standard normally distributed number (Box-Muller).
+fn normal(rng: &mut StdRng) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+/// Plays one game and returns the number of cleared lines.
+fn play_game(w: &Weights, seed: u64, pieces: usize) -> usize {
+    let mut g = Game::new(Board::new(10, 20), PieceFactory::with_seed(seed));
+    bot::play(&mut g, w, pieces);
+    g.lines as usize
+}
+
+/// Plays every candidate's games, spread over `o.threads` threads.
+///
+/// Returns the cleared lines per game for every candidate.
+fn evaluate(o: &Options, candidates: Vec<Weights>, seeds: Vec<u64>) -> Vec<Vec<usize>> {
+    let candidates = Arc::new(candidates);
+    let seeds = Arc::new(seeds);
+    let next = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = mpsc::channel();
+    let mut workers = vec![];
+    for _ in 0..o.threads {
+        let (candidates, seeds, next, tx) = (
+            Arc::clone(&candidates),
+            Arc::clone(&seeds),
+            Arc::clone(&next),
+            tx.clone(),
+        );
+        let pieces = o.pieces;
+        workers.push(thread::spawn(move || loop {
+            let i = next.fetch_add(1, atomic::Ordering::Relaxed);
+            if i >= candidates.len() {
+                break;
+            }
+            let lines = seeds
+                .iter()
+                .map(|&seed| play_game(&candidates[i], seed, pieces))
+                .collect::<Vec<_>>();
+            if tx.send((i, lines)).is_err() {
+                break;
+            }
+        }));
+    }
+    drop(tx);
+
+    let mut results = vec![vec![]; candidates.len()];
+    for (i, lines) in rx {
+        results[i] = lines;
+    }
+    for w in workers {
+        w.join().expect("worker thread panicked");
+    }
+    results
+}
+
+fn mean(v: &[f64]) -> f64 {
+    v.iter().sum::<f64>() / v.len() as f64
+}
+
+fn std_dev(v: &[f64]) -> f64 {
+    let m = mean(v);
+    (v.iter().map(|x| (x - m) * (x - m)).sum::<f64>() / v.len() as f64).sqrt()
+}
+
+fn report(generation: usize, lines: &[Vec<usize>], order: &[usize]) {
+    let best = lines[order[0]]
+        .iter()
+        .map(|&l| l as f64)
+        .collect::<Vec<_>>();
+    let all = lines
+        .iter()
+        .flatten()
+        .map(|&l| l as f64)
+        .collect::<Vec<_>>();
+    println!(
+        "generation {:3}: lines/game best {:8.1} (min {}, max {}, sd {:.1}), population {:8.1} (sd {:.1})",
+        generation,
+        mean(&best),
+        best.iter().cloned().fold(f64::INFINITY, f64::min),
+        best.iter().cloned().fold(0.0, f64::max),
+        std_dev(&best),
+        mean(&all),
+        std_dev(&all),
+    );
+}
+
+/// The distribution before the first generation.
+fn first_state() -> State {
+    State {
+        generation: 0,
+        mean: Weights::default().0,
+        sd: [5.0; FEATURES],
+        population: vec![],
+    }
+}
+
+/// Samples, plays and ranks a generation and moves the distribution.
+///
+/// Every generation has its own random numbers, so a resumed run
+/// samples the same populations as one that never stopped.
+fn run_generation(o: &Options, state: &mut State) {
+    let mut rng = StdRng::seed_from_u64(o.seed ^ state.generation as u64);
+    let candidates = (0..o.population)
+        .map(|_| {
+            let mut w = [0.0; FEATURES];
+            for (i, w) in w.iter_mut().enumerate() {
+                *w = state.mean[i] + state.sd[i] * normal(&mut rng);
+            }
+            Weights(w)
+        })
+        .collect::<Vec<_>>();
+    // All candidates of a generation get the same pieces.
+    let seeds = (0..o.games)
+        .map(|i| o.seed + (state.generation * o.games + i) as u64)
+        .collect::<Vec<_>>();
+
+    let lines = evaluate(o, candidates.clone(), seeds);
+    let fitness = lines
+        .iter()
+        .map(|l| mean(&l.iter().map(|&l| l as f64).collect::<Vec<_>>()))
+        .collect::<Vec<_>>();
+    let mut order = (0..candidates.len()).collect::<Vec<_>>();
+    // f64::total_cmp needs a newer Rust than the crate's.
+    order.sort_by(|&a, &b| {
+        fitness[b]
+            .partial_cmp(&fitness[a])
+            .unwrap_or(Ordering::Equal)
+    });
+
+    report(state.generation, &lines, &order);
+
+    // Move the distribution to the elite, with some decreasing
+    // extra noise to not converge too early.
+    let noise = (4.0 - state.generation as f64 / 10.0).max(0.0);
+    for i in 0..FEATURES {
+        let elite = order[..o.elite]
+            .iter()
+            .map(|&c| candidates[c].0[i])
+            .collect::<Vec<_>>();
+        state.mean[i] = mean(&elite);
+        state.sd[i] = (std_dev(&elite).powi(2) + noise).sqrt();
+    }
+    state.population = order
+        .iter()
+        .map(|&c| (candidates[c].clone(), fitness[c]))
+        .collect();
+    state.generation += 1;
+}
+
+fn main() {
+    let o = parse_args();
+
+    let mut state = if o.resume {
+        load_checkpoint(&o).unwrap_or_else(|e| {
+            eprintln!("Can't resume from {}: {}", o.checkpoint.display(), e);
+            std::process::exit(1);
+        })
+    } else {
+        first_state()
+    };
+
+    while state.generation < o.generations {
+        run_generation(&o, &mut state);
+        if let Err(e) = save_checkpoint(&o, &state) {
+            eprintln!("Can't write {}: {}", o.checkpoint.display(), e);
+        }
+    }
+
+    println!("Weights:");
+    for (name, w) in FEATURE_NAMES.iter().zip(state.mean.iter()) {
+        println!("  {:20} {}", name, w);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn checkpoints_round_trip() {
+        let mut w = Weights::default();
+        w.0[0] = -0.125;
+        let s = State {
+            generation: 3,
+            mean: Weights::default().0,
+            sd: [0.5; FEATURES],
+            population: vec![(w, 12.5), (Weights::default(), 0.0)],
+        };
+        let text = checkpoint_text(&s);
+        assert!(text.starts_with("tetris-train 1\ngeneration 3\n"));
+        assert_eq!(parse_checkpoint(&text), Ok(s));
+    }
+
+    #[test]
+    fn resumed_runs_are_the_same() {
+        let o = Options {
+            generations: 2,
+            population: 6,
+            elite: 2,
+            games: 2,
+            pieces: 30,
+            threads: 2,
+            seed: 5,
+            checkpoint: PathBuf::new(),
+            resume: false,
+        };
+        let mut uninterrupted = first_state();
+        run_generation(&o, &mut uninterrupted);
+        run_generation(&o, &mut uninterrupted);
+
+        let mut first = first_state();
+        run_generation(&o, &mut first);
+        let mut resumed = parse_checkpoint(&checkpoint_text(&first)).unwrap();
+        run_generation(&o, &mut resumed);
+        assert_eq!(resumed, uninterrupted);
+    }
+
+    #[test]
+    fn bad_checkpoints() {
+        assert_eq!(
+            parse_checkpoint("tetris-train 2\n"),
+            Err("not a version 1 checkpoint".to_string())
+        );
+        assert_eq!(
+            parse_checkpoint("tetris-train 1\nmean 1 2"),
+            Err(format!("expected {} numbers, got 2", FEATURES))
+        );
+        assert!(parse_checkpoint("tetris-train 1\nsd 1 x 3").is_err());
+        assert_eq!(
+            parse_checkpoint("tetris-train 1\nbest 1"),
+            Err("unknown line: best 1".to_string())
+        );
+    }
+}
@@ -0,0 +1,220 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! A bot that plays by trying every drop of the current piece and
+//! picking the one whose resulting board scores best.
+//!
+//! A board is scored as the weighted sum of a few features, the ones
+//! from Pierre Dellacherie's well known player.
+
+use crate::model::Board;
+use crate::model::Game;
+use crate::model::Piece;
+
+pub const FEATURES: usize = 6;
+
+pub const FEATURE_NAMES: [&str; FEATURES] = [
+    "landing_height",
+    "rows_cleared",
+    "row_transitions",
+    "column_transitions",
+    "holes",
+    "well_sums",
+];
+
+pub type Features = [f64; FEATURES];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Weights(pub Features);
+
+/// Where to drop a piece.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Placement {
+    pub rotation: usize,
+    pub x: i32,
+    pub value: f64,
+}
+
+impl Default for Weights {
+    /// The weights found by El-Tetris.
+    fn default() -> Self {
+        Weights([
+            -4.500_158_825_082_766,
+            3.418_126_810_139_269_4,
+            -3.217_888_286_848_775_3,
+            -9.348_695_305_445_199,
+            -7.899_265_427_351_652,
+            -3.385_597_224_726_362_6,
+        ])
+    }
+}
+
+impl Weights {
+    pub fn eval(&self, f: &Features) -> f64 {
+        self.0.iter().zip(f.iter()).map(|(w, f)| w * f).sum()
+    }
+}
+
+fn is_filled(board: &Board, x: i32, y: i32) -> bool {
+    if x < 0 || x >= i32::from(board.width()) || y >= i32::from(board.height()) {
+        return true;
+    }
+    if y < 0 {
+        return false;
+    }
+    board.is_set(x, y)
+}
+
+/// Computes the features of a board where a piece has landed.
+///
+/// `landing_height` is the height of the middle of the landed piece,
+/// counted from the floor.
+pub fn features(board: &Board, landing_height: f64, rows_cleared: usize) -> Features {
+    let width = i32::from(board.width());
+    let height = i32::from(board.height());
+
+    let mut row_transitions = 0;
+    for y in 0..height {
+        for x in 0..=width {
+            if is_filled(board, x - 1, y) != is_filled(board, x, y) {
+                row_transitions += 1;
+            }
+        }
+    }
+
+    let mut column_transitions = 0;
+    let mut holes = 0;
+    let mut well_sums = 0;
+    for x in 0..width {
+        let mut covered = false;
+        let mut well = 0;
+        for y in 0..=height {
+            let filled = is_filled(board, x, y);
+            if filled != is_filled(board, x, y - 1) {
+                column_transitions += 1;
+            }
+            if y == height {
+                break;
+            }
+            if filled {
+                covered = true;
+                well = 0;
+            } else {
+                if covered {
+                    holes += 1;
+                }
+                if is_filled(board, x - 1, y) && is_filled(board, x + 1, y) {
+                    well += 1;
+                    well_sums += well;
+                } else {
+                    well = 0;
+                }
+            }
+        }
+    }
+
+    [
+        landing_height,
+        rows_cleared as f64,
+        f64::from(row_transitions),
+        f64::from(column_transitions),
+        f64::from(holes),
+        f64::from(well_sums),
+    ]
+}
+
+/// Finds the best place to drop the game's current piece.
+pub fn best_placement(g: &Game, w: &Weights) -> Option<Placement> {
+    let mut empty = g.board.clone();
+    empty.clear(&g.piece, g.pos.0, g.pos.1);
+    let width = usize::from(empty.width());
+    let height = f64::from(empty.height());
+
+    let mut best: Option<Placement> = None;
+    let mut p: Piece = g.piece.clone();
+    for rotation in 0..p.rotations() {
+        p.set_rotation(rotation);
+        let start_y = g.pos.1.max(i32::from(p.curr().offset().1));
+        for x in 0..i32::from(empty.width()) {
+            let mut y = start_y;
+            if !empty.fits(&p, x, y) {
+                continue;
+            }
+            while empty.fits(&p, x, y + 1) {
+                y += 1;
+            }
+            let mut b = empty.clone();
+            let cells = b.get_indexes(&p, x, y);
+            let middle = cells.iter().map(|i| (i / width) as f64).sum::<f64>() / cells.len() as f64;
+            b.draw(&p, x, y);
+            let rows_cleared = b.remove_full_lines().len();
+            let value = w.eval(&features(&b, height - middle, rows_cleared));
+            if best.as_ref().map_or(true, |b| value > b.value) {
+                best = Some(Placement { rotation, x, value });
+            }
+        }
+    }
+    best
+}
+
+/// Lets the bot play until the game is over or `max_pieces` pieces
+/// have been dropped.
+///
+/// Returns the number of dropped pieces.
+pub fn play(g: &mut Game, w: &Weights, max_pieces: usize) -> usize {
+    let mut pieces = 0;
+    while !g.game_over && pieces < max_pieces {
+        match best_placement(g, w) {
+            Some(p) => {
+                g.place(p.rotation, p.x);
+            }
+            None => g.fall(),
+        }
+        g.events.clear();
+        pieces += 1;
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::*;
+    use crate::model::PieceFactory;
+
+    #[test]
+    fn empty_board_features() {
+        let b = Board::new(10, 20);
+        let f = features(&b, 0.0, 0);
+        assert_eq!(f[2], 40.0);
+        assert_eq!(f[3], 10.0);
+        assert_eq!(f[4], 0.0);
+        assert_eq!(f[5], 0.0);
+    }
+
+    #[test]
+    fn holes_and_wells() {
+        let mut b = Board::new(4, 4);
+        // .X..
+        // ....
+        // .X.X
+        // XX.X
+        for i in [1, 9, 11, 12, 13, 15] {
+            b.map[i] = crate::model::PieceType::Z;
+        }
+        let f = features(&b, 0.0, 0);
+        assert_eq!(f[4], 1.0);
+        // Column 2 is a well two deep, column 0 has two wells one deep.
+        assert_eq!(f[5], 1.0 + 2.0 + 1.0 + 1.0);
+    }
+
+    #[test]
+    fn bot_clears_lines() {
+        let b = Board::new(10, 20);
+        let mut g = Game::new(b, PieceFactory::with_seed(1));
+        let pieces = play(&mut g, &Weights::default(), 100);
+        assert_eq!(pieces, 100);
+        assert!(!g.game_over);
+        assert!(g.lines > 20);
+    }
+}
@@ -0,0 +1,703 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The player's settings, kept in a TOML file in the XDG config
+//! directory.
+//!
+//! ```toml
+//! [game]
+//! # Gravity this many times faster, or "sonic".
+//! soft_drop = 20
+//! # Full rows stay this many milliseconds before they are removed.
+//! line_clear_delay = 300
+//! # No animations and no line clear delay.
+//! fast = false
+//!
+//! [sound]
+//! muted = false
+//! # From 0 to 10.
+//! volume = 7
+//! # Background music, with the music feature.
+//! music = true
+//!
+//! [display]
+//! # auto, ascii, blocks or half-blocks.
+//! glyphs = "auto"
+//! # auto, basic, 256 or truecolour.
+//! colours = "auto"
+//! # The panel with the pieces per second and such.
+//! stats = false
+//!
+//! [keys]
+//! move_left = ["h", "Left"]
+//! hard_drop = ["space"]
+//! pause = ["p", "Esc", "F1"]
+//! ```
+//!
+//! Actions that aren't in the file keep their default keys. A key can
+//! only be bound to one action.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::model::SoftDrop;
+use crate::paths;
+use crate::sound;
+
+/// Something the player can do in a game.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    RotateCw,
+    RotateCcw,
+    Rotate180,
+    SoftDrop,
+    HardDrop,
+    Hold,
+    Pause,
+    Restart,
+    Export,
+    Stats,
+    Mute,
+    Quit,
+}
+
+/// A key on the keyboard.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    Left,
+    Right,
+    Up,
+    Down,
+    Enter,
+    Esc,
+    Tab,
+    Backspace,
+    Delete,
+    Insert,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    F(u8),
+}
+
+/// How the cells and borders are drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Glyphs {
+    /// Blocks if the terminal takes UTF-8, else ASCII.
+    Auto,
+    Ascii,
+    /// Full blocks and box drawing borders.
+    Blocks,
+    /// Two rows in each line of the terminal, with half blocks.
+    HalfBlocks,
+}
+
+/// The colours the cells are drawn with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Colours {
+    /// The best the terminal can show.
+    Auto,
+    /// The 8 basic colours, the L is white.
+    Basic,
+    Ansi256,
+    /// The exact colours, if the terminal can change its palette.
+    TrueColour,
+}
+
+/// The keys bound to each action.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bindings {
+    keys: Vec<(Action, Vec<Key>)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub soft_drop: SoftDrop,
+    pub line_clear_delay: Duration,
+    /// Skip the animations and the line clear delay.
+    pub fast: bool,
+    pub muted: bool,
+    pub volume: u8,
+    pub music: bool,
+    pub glyphs: Glyphs,
+    pub colours: Colours,
+    /// Show the stats panel.
+    pub stats: bool,
+    pub bindings: Bindings,
+}
+
+/// The line clear delay unless the settings say otherwise.
+pub const LINE_CLEAR_DELAY: Duration = Duration::from_millis(300);
+
+const NAMED_KEYS: [(Key, &str); 15] = [
+    (Key::Char(' '), "space"),
+    (Key::Left, "Left"),
+    (Key::Right, "Right"),
+    (Key::Up, "Up"),
+    (Key::Down, "Down"),
+    (Key::Enter, "Enter"),
+    (Key::Esc, "Esc"),
+    (Key::Tab, "Tab"),
+    (Key::Backspace, "Backspace"),
+    (Key::Delete, "Delete"),
+    (Key::Insert, "Insert"),
+    (Key::Home, "Home"),
+    (Key::End, "End"),
+    (Key::PageUp, "PageUp"),
+    (Key::PageDown, "PageDown"),
+];
+
+impl Glyphs {
+    pub const ALL: [Glyphs; 4] = [
+        Glyphs::Auto,
+        Glyphs::Ascii,
+        Glyphs::Blocks,
+        Glyphs::HalfBlocks,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Glyphs::Auto => "auto",
+            Glyphs::Ascii => "ascii",
+            Glyphs::Blocks => "blocks",
+            Glyphs::HalfBlocks => "half-blocks",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Glyphs> {
+        Glyphs::ALL.iter().copied().find(|g| g.name() == name)
+    }
+}
+
+impl Colours {
+    pub const ALL: [Colours; 4] = [
+        Colours::Auto,
+        Colours::Basic,
+        Colours::Ansi256,
+        Colours::TrueColour,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Colours::Auto => "auto",
+            Colours::Basic => "basic",
+            Colours::Ansi256 => "256",
+            Colours::TrueColour => "truecolour",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Colours> {
+        Colours::ALL.iter().copied().find(|c| c.name() == name)
+    }
+}
+
+/// Where the settings are kept.
+pub fn default_path() -> Option<PathBuf> {
+    paths::config_dir().map(|d| d.join("config.toml"))
+}
+
+impl Action {
+    pub const ALL: [Action; 14] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::RotateCw,
+        Action::RotateCcw,
+        Action::Rotate180,
+        Action::SoftDrop,
+        Action::HardDrop,
+        Action::Hold,
+        Action::Pause,
+        Action::Restart,
+        Action::Export,
+        Action::Stats,
+        Action::Mute,
+        Action::Quit,
+    ];
+
+    /// The action's name in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::RotateCw => "rotate_cw",
+            Action::RotateCcw => "rotate_ccw",
+            Action::Rotate180 => "rotate_180",
+            Action::SoftDrop => "soft_drop",
+            Action::HardDrop => "hard_drop",
+            Action::Hold => "hold",
+            Action::Pause => "pause",
+            Action::Restart => "restart",
+            Action::Export => "export",
+            Action::Stats => "stats",
+            Action::Mute => "mute",
+            Action::Quit => "quit",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.iter().copied().find(|a| a.name() == name)
+    }
+
+    /// The action in words, for the menus.
+    pub fn describe(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::RotateCw => "Rotate clockwise",
+            Action::RotateCcw => "Rotate counter-clockwise",
+            Action::Rotate180 => "Rotate 180",
+            Action::SoftDrop => "Soft drop",
+            Action::HardDrop => "Hard drop",
+            Action::Hold => "Hold",
+            Action::Pause => "Pause",
+            Action::Restart => "Restart",
+            Action::Export => "Export position",
+            Action::Stats => "Stats on/off",
+            Action::Mute => "Sound on/off",
+            Action::Quit => "Quit",
+        }
+    }
+}
+
+impl Key {
+    /// Parses a key's name, like "h", "space", "Left" or "F1".
+    pub fn parse(s: &str) -> Result<Key, String> {
+        let mut chars = s.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            if !c.is_control() && c != ' ' {
+                return Ok(Key::Char(c));
+            }
+        }
+        if let Some((k, _)) = NAMED_KEYS
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(s))
+        {
+            return Ok(*k);
+        }
+        match s.strip_prefix('F').or_else(|| s.strip_prefix('f')) {
+            Some(n) => match n.parse::<u8>() {
+                Ok(n) if (1..=12).contains(&n) => Ok(Key::F(n)),
+                _ => Err(format!("unknown key: {}", s)),
+            },
+            None => Err(format!("unknown key: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((_, name)) = NAMED_KEYS.iter().find(|(k, _)| k == self) {
+            return write!(f, "{}", name);
+        }
+        match self {
+            Key::Char(c) => write!(f, "{}", c),
+            Key::F(n) => write!(f, "F{}", n),
+            _ => unreachable!("all other keys are named"),
+        }
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let keys = |a: Action| -> Vec<Key> {
+            match a {
+                Action::MoveLeft => vec![Key::Char('h'), Key::Left],
+                Action::MoveRight => vec![Key::Char('l'), Key::Right],
+                Action::RotateCw => vec![Key::Char('k'), Key::Up],
+                Action::RotateCcw => vec![Key::Char('u')],
+                Action::Rotate180 => vec![Key::Char('i')],
+                Action::SoftDrop => vec![Key::Char('j'), Key::Down],
+                Action::HardDrop => vec![Key::Char(' ')],
+                Action::Hold => vec![Key::Char('c')],
+                Action::Pause => vec![Key::Char('p'), Key::Esc],
+                Action::Restart => vec![Key::Char('r')],
+                Action::Export => vec![Key::Char('e')],
+                Action::Stats => vec![Key::Char('s')],
+                Action::Mute => vec![Key::Char('m')],
+                Action::Quit => vec![Key::Char('q')],
+            }
+        };
+        Bindings {
+            keys: Action::ALL.iter().map(|a| (*a, keys(*a))).collect(),
+        }
+    }
+}
+
+impl Bindings {
+    /// The action the key is bound to.
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.keys
+            .iter()
+            .find(|(_, keys)| keys.contains(&key))
+            .map(|(a, _)| *a)
+    }
+
+    pub fn keys(&self, action: Action) -> &[Key] {
+        self.keys
+            .iter()
+            .find(|(a, _)| *a == action)
+            .map_or(&[], |(_, keys)| keys)
+    }
+
+    fn keys_mut(&mut self, action: Action) -> &mut Vec<Key> {
+        let i = self
+            .keys
+            .iter()
+            .position(|(a, _)| *a == action)
+            .expect("every action has keys");
+        &mut self.keys[i].1
+    }
+
+    /// Binds one more key to the action, unless another action uses it.
+    pub fn bind(&mut self, action: Action, key: Key) -> Result<(), String> {
+        match self.action(key) {
+            Some(a) if a == action => Ok(()),
+            Some(a) => Err(format!("{} is already bound to {}", key, a.name())),
+            None => {
+                self.keys_mut(action).push(key);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn unbind(&mut self, action: Action) {
+        self.keys_mut(action).clear();
+    }
+
+    /// Describes the keys that are bound to more than one action.
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut conflicts = vec![];
+        for (i, (a, keys)) in self.keys.iter().enumerate() {
+            for key in keys {
+                for (b, _) in self.keys[i + 1..].iter().filter(|(_, k)| k.contains(key)) {
+                    conflicts.push(format!(
+                        "{} is bound to both {} and {}",
+                        key,
+                        a.name(),
+                        b.name()
+                    ));
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            soft_drop: SoftDrop::default(),
+            line_clear_delay: LINE_CLEAR_DELAY,
+            fast: false,
+            muted: false,
+            volume: sound::DEFAULT_VOLUME,
+            music: true,
+            glyphs: Glyphs::Auto,
+            colours: Colours::Auto,
+            stats: false,
+            bindings: Bindings::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The line clear delay to play with.
+    pub fn clear_delay(&self) -> Duration {
+        if self.fast {
+            Duration::ZERO
+        } else {
+            self.line_clear_delay
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let value = text.parse::<toml::Value>().map_err(|e| e.to_string())?;
+        let mut config = Config::default();
+        match value.get("game") {
+            Some(toml::Value::Table(t)) => {
+                if let Some(v) = t.get("soft_drop") {
+                    config.soft_drop = match v {
+                        toml::Value::String(s) if s == "sonic" => SoftDrop::Sonic,
+                        toml::Value::Integer(n) if (1..=1000).contains(n) => {
+                            SoftDrop::Factor(*n as u32)
+                        }
+                        _ => return Err(format!("bad soft_drop: {}", v)),
+                    }
+                }
+                if let Some(v) = t.get("line_clear_delay") {
+                    config.line_clear_delay = match v {
+                        toml::Value::Integer(n) if (0..=2000).contains(n) => {
+                            Duration::from_millis(*n as u64)
+                        }
+                        _ => return Err(format!("bad line_clear_delay: {}", v)),
+                    }
+                }
+                if let Some(v) = t.get("fast") {
+                    config.fast = v.as_bool().ok_or_else(|| format!("bad fast: {}", v))?;
+                }
+            }
+            Some(_) => return Err("game must be a table".to_string()),
+            None => (),
+        }
+        match value.get("sound") {
+            Some(toml::Value::Table(t)) => {
+                if let Some(v) = t.get("muted") {
+                    config.muted = v.as_bool().ok_or_else(|| format!("bad muted: {}", v))?;
+                }
+                if let Some(v) = t.get("music") {
+                    config.music = v.as_bool().ok_or_else(|| format!("bad music: {}", v))?;
+                }
+                if let Some(v) = t.get("volume") {
+                    config.volume = match v {
+                        toml::Value::Integer(n)
+                            if (0..=i64::from(sound::MAX_VOLUME)).contains(n) =>
+                        {
+                            *n as u8
+                        }
+                        _ => return Err(format!("bad volume: {}", v)),
+                    }
+                }
+            }
+            Some(_) => return Err("sound must be a table".to_string()),
+            None => (),
+        }
+        match value.get("display") {
+            Some(toml::Value::Table(t)) => {
+                if let Some(v) = t.get("glyphs") {
+                    config.glyphs = v
+                        .as_str()
+                        .and_then(Glyphs::from_name)
+                        .ok_or_else(|| format!("bad glyphs: {}", v))?;
+                }
+                if let Some(v) = t.get("colours") {
+                    config.colours = v
+                        .as_str()
+                        .and_then(Colours::from_name)
+                        .ok_or_else(|| format!("bad colours: {}", v))?;
+                }
+                if let Some(v) = t.get("stats") {
+                    config.stats = v.as_bool().ok_or_else(|| format!("bad stats: {}", v))?;
+                }
+            }
+            Some(_) => return Err("display must be a table".to_string()),
+            None => (),
+        }
+        let keys = match value.get("keys") {
+            Some(toml::Value::Table(t)) => t,
+            Some(_) => return Err("keys must be a table".to_string()),
+            None => return Ok(config),
+        };
+        for (name, value) in keys {
+            let action =
+                Action::from_name(name).ok_or_else(|| format!("unknown action: {}", name))?;
+            let names = match value {
+                toml::Value::String(s) => vec![s.as_str()],
+                toml::Value::Array(a) => a
+                    .iter()
+                    .map(|v| {
+                        v.as_str()
+                            .ok_or_else(|| format!("{}: keys must be strings", name))
+                    })
+                    .collect::<Result<_, _>>()?,
+                _ => return Err(format!("{}: expected a list of keys", name)),
+            };
+            *config.bindings.keys_mut(action) = names
+                .into_iter()
+                .map(Key::parse)
+                .collect::<Result<_, _>>()?;
+        }
+        match config.bindings.conflicts().first() {
+            Some(c) => Err(c.clone()),
+            None => Ok(config),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut keys = toml::value::Table::new();
+        for a in Action::ALL {
+            let names = self
+                .bindings
+                .keys(a)
+                .iter()
+                .map(|k| toml::Value::String(k.to_string()))
+                .collect();
+            keys.insert(a.name().to_string(), toml::Value::Array(names));
+        }
+        let mut game = toml::value::Table::new();
+        let soft_drop = match self.soft_drop {
+            SoftDrop::Sonic => toml::Value::String("sonic".to_string()),
+            SoftDrop::Factor(f) => toml::Value::Integer(i64::from(f)),
+        };
+        game.insert("soft_drop".to_string(), soft_drop);
+        game.insert(
+            "line_clear_delay".to_string(),
+            toml::Value::Integer(self.line_clear_delay.as_millis() as i64),
+        );
+        game.insert("fast".to_string(), toml::Value::Boolean(self.fast));
+        let mut sound = toml::value::Table::new();
+        sound.insert("muted".to_string(), toml::Value::Boolean(self.muted));
+        sound.insert("music".to_string(), toml::Value::Boolean(self.music));
+        sound.insert(
+            "volume".to_string(),
+            toml::Value::Integer(i64::from(self.volume)),
+        );
+        let mut display = toml::value::Table::new();
+        display.insert(
+            "glyphs".to_string(),
+            toml::Value::String(self.glyphs.name().to_string()),
+        );
+        display.insert(
+            "colours".to_string(),
+            toml::Value::String(self.colours.name().to_string()),
+        );
+        display.insert("stats".to_string(), toml::Value::Boolean(self.stats));
+        let mut table = toml::value::Table::new();
+        table.insert("game".to_string(), toml::Value::Table(game));
+        table.insert("display".to_string(), toml::Value::Table(display));
+        table.insert("sound".to_string(), toml::Value::Table(sound));
+        table.insert("keys".to_string(), toml::Value::Table(keys));
+        toml::to_string(&toml::Value::Table(table)).expect("a table can be written")
+    }
+
+    /// Loads the settings, or the defaults if there is no file.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Saves the settings, without ever leaving a half written file
+    /// behind.
+    pub fn save_to(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        let mut f = fs::File::create(&tmp)?;
+        f.write_all(self.to_text().as_bytes())?;
+        f.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::*;
+
+    #[test]
+    fn key_names() {
+        for (name, key) in [
+            ("h", Key::Char('h')),
+            ("H", Key::Char('H')),
+            ("space", Key::Char(' ')),
+            ("Left", Key::Left),
+            ("PageDown", Key::PageDown),
+            ("F12", Key::F(12)),
+            ("Esc", Key::Esc),
+        ] {
+            assert_eq!(Key::parse(name), Ok(key));
+            assert_eq!(key.to_string(), name);
+        }
+        assert_eq!(Key::parse("left"), Ok(Key::Left));
+        assert!(Key::parse("F13").is_err());
+        assert!(Key::parse("hello").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut c = Config::default();
+        c.bindings.bind(Action::MoveLeft, Key::Left).unwrap();
+        c.bindings.bind(Action::Hold, Key::F(2)).unwrap();
+        assert_eq!(Config::parse(&c.to_text()), Ok(c));
+    }
+
+    #[test]
+    fn soft_drop() {
+        let c = Config::parse("[game]\nsoft_drop = \"sonic\"\n").unwrap();
+        assert_eq!(c.soft_drop, SoftDrop::Sonic);
+        assert_eq!(Config::parse(&c.to_text()), Ok(c));
+        let c = Config::parse("[game]\nsoft_drop = 5\n").unwrap();
+        assert_eq!(c.soft_drop, SoftDrop::Factor(5));
+        assert!(Config::parse("[game]\nsoft_drop = 0\n").is_err());
+        assert!(Config::parse("[game]\nsoft_drop = \"fast\"\n").is_err());
+    }
+
+    #[test]
+    fn fast_has_no_delay() {
+        let c = Config::parse("[game]\nline_clear_delay = 500\n").unwrap();
+        assert_eq!(c.clear_delay(), Duration::from_millis(500));
+        let c = Config::parse("[game]\nline_clear_delay = 500\nfast = true\n").unwrap();
+        assert_eq!(c.clear_delay(), Duration::ZERO);
+        assert_eq!(Config::parse(&c.to_text()), Ok(c));
+        assert!(Config::parse("[game]\nline_clear_delay = -1\n").is_err());
+        assert!(Config::parse("[game]\nfast = 1\n").is_err());
+    }
+
+    #[test]
+    fn sound() {
+        let c = Config::parse("[sound]\nmuted = true\nvolume = 3\nmusic = false\n").unwrap();
+        assert!(c.muted);
+        assert!(!c.music);
+        assert_eq!(c.volume, 3);
+        assert_eq!(Config::parse(&c.to_text()), Ok(c));
+        assert!(Config::parse("[sound]\nvolume = 11\n").is_err());
+    }
+
+    #[test]
+    fn display() {
+        let c =
+            Config::parse("[display]\nglyphs = \"half-blocks\"\ncolours = \"256\"\nstats = true\n")
+                .unwrap();
+        assert_eq!(c.glyphs, Glyphs::HalfBlocks);
+        assert_eq!(c.colours, Colours::Ansi256);
+        assert!(c.stats);
+        assert_eq!(Config::parse(&c.to_text()), Ok(c));
+        assert!(Config::parse("[display]\nglyphs = \"emoji\"\n").is_err());
+        assert!(Config::parse("[display]\ncolours = 256\n").is_err());
+        assert!(Config::parse("[display]\nstats = \"yes\"\n").is_err());
+    }
+
+    #[test]
+    fn missing_actions_keep_their_keys() {
+        let c = Config::parse("[keys]\nhold = [\"Tab\"]\nquit = \"Q\"\n").unwrap();
+        assert_eq!(c.bindings.keys(Action::Hold), &[Key::Tab]);
+        assert_eq!(c.bindings.action(Key::Char('Q')), Some(Action::Quit));
+        assert_eq!(c.bindings.action(Key::Char('h')), Some(Action::MoveLeft));
+        assert_eq!(c.bindings.action(Key::Char('q')), None);
+    }
+
+    #[test]
+    fn conflicts() {
+        let e = Config::parse("[keys]\nhold = [\"h\"]\n").unwrap_err();
+        assert_eq!(e, "h is bound to both move_left and hold");
+        assert!(Config::parse("[keys]\nhold = [\"c\", \"c\"]\n").is_ok());
+
+        let mut b = Bindings::default();
+        assert!(b.bind(Action::Hold, Key::Char('h')).is_err());
+        b.unbind(Action::MoveLeft);
+        assert!(b.bind(Action::Hold, Key::Char('h')).is_ok());
+        assert!(b.conflicts().is_empty());
+    }
+
+    #[test]
+    fn bad_configs() {
+        assert!(Config::parse("[keys]\njump = [\"w\"]\n").is_err());
+        assert!(Config::parse("[keys]\nhold = [\"Meta\"]\n").is_err());
+        assert!(Config::parse("[keys]\nhold = 1\n").is_err());
+        assert!(Config::parse("keys = 1\n").is_err());
+        assert!(Config::parse("[keys\n").is_err());
+        assert_eq!(Config::parse(""), Ok(Config::default()));
+    }
+}
@@ -0,0 +1,177 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The curses backend.
+
+use std::cell::RefCell;
+
+use pancurses::Window;
+
+use crate::render::Colour;
+use crate::render::Input;
+use crate::render::Renderer;
+use crate::render::Style;
+
+/// The true colours are put in the palette from here on.
+const FIRST_RGB: i16 = 16;
+
+pub struct Curses {
+    win: Window,
+    /// The colour pairs set up so far, from pair 1 on.
+    pairs: RefCell<Vec<(i16, i16)>>,
+    /// The true colours put in the palette so far, from FIRST_RGB on.
+    rgb: RefCell<Vec<(u8, u8, u8)>>,
+}
+
+/// Asks the terminal to tell when it gets or loses focus, terminals
+/// that can't just ignore it.
+fn set_focus_events(on: bool) {
+    use std::io::Write;
+
+    let mut out = std::io::stdout();
+    let _ = write!(out, "\x1b[?1004{}", if on { 'h' } else { 'l' });
+    let _ = out.flush();
+}
+
+impl Curses {
+    /// Inits the curses.
+    pub fn new() -> Self {
+        let win = pancurses::initscr();
+        win.nodelay(true);
+        win.scrollok(false);
+        pancurses::cbreak();
+        pancurses::noecho();
+        pancurses::curs_set(0);
+
+        win.keypad(true);
+        pancurses::start_color();
+        set_focus_events(true);
+
+        Curses {
+            win,
+            pairs: RefCell::new(vec![]),
+            rgb: RefCell::new(vec![]),
+        }
+    }
+
+    /// The palette's number for the colour, true colours are put in
+    /// the palette the first time they are used.
+    fn colour(&self, c: Colour) -> i16 {
+        let (r, g, b) = match c {
+            Colour::Index(n) => return i16::from(n),
+            Colour::Rgb(r, g, b) => (r, g, b),
+        };
+        let mut rgb = self.rgb.borrow_mut();
+        if let Some(i) = rgb.iter().position(|c| *c == (r, g, b)) {
+            return FIRST_RGB + i as i16;
+        }
+        let n = FIRST_RGB + rgb.len() as i16;
+        if i32::from(n) >= pancurses::COLORS() {
+            return pancurses::COLOR_WHITE;
+        }
+        let scale = |c: u8| (i32::from(c) * 1000 / 255) as i16;
+        pancurses::init_color(n, scale(r), scale(g), scale(b));
+        rgb.push((r, g, b));
+        n
+    }
+
+    /// The colour pair for the style, set up the first time it is
+    /// asked for. Pair 0 if the terminal has no more pairs.
+    fn pair(&self, style: Style) -> i16 {
+        let colours = (self.colour(style.fg), self.colour(style.bg));
+        let mut pairs = self.pairs.borrow_mut();
+        if let Some(i) = pairs.iter().position(|p| *p == colours) {
+            return i as i16 + 1;
+        }
+        let n = pairs.len() as i16 + 1;
+        if i32::from(n) >= pancurses::COLOR_PAIRS() {
+            return 0;
+        }
+        pancurses::init_pair(n, colours.0, colours.1);
+        pairs.push(colours);
+        n
+    }
+}
+
+fn input(i: pancurses::Input) -> Option<Input> {
+    use pancurses::Input as C;
+
+    Some(match i {
+        C::Character(c) => Input::Character(c),
+        C::KeyUp => Input::KeyUp,
+        C::KeyDown => Input::KeyDown,
+        C::KeyLeft => Input::KeyLeft,
+        C::KeyRight => Input::KeyRight,
+        C::KeyEnter => Input::KeyEnter,
+        C::KeyBackspace => Input::KeyBackspace,
+        C::KeyDC => Input::KeyDC,
+        C::KeyIC => Input::KeyIC,
+        C::KeyHome => Input::KeyHome,
+        C::KeyEnd => Input::KeyEnd,
+        C::KeyPPage => Input::KeyPPage,
+        C::KeyNPage => Input::KeyNPage,
+        C::KeyF1 => Input::KeyF(1),
+        C::KeyF2 => Input::KeyF(2),
+        C::KeyF3 => Input::KeyF(3),
+        C::KeyF4 => Input::KeyF(4),
+        C::KeyF5 => Input::KeyF(5),
+        C::KeyF6 => Input::KeyF(6),
+        C::KeyF7 => Input::KeyF(7),
+        C::KeyF8 => Input::KeyF(8),
+        C::KeyF9 => Input::KeyF(9),
+        C::KeyF10 => Input::KeyF(10),
+        C::KeyF11 => Input::KeyF(11),
+        C::KeyF12 => Input::KeyF(12),
+        C::KeyResize => Input::KeyResize,
+        _ => return None,
+    })
+}
+
+impl Renderer for Curses {
+    fn size(&self) -> (i32, i32) {
+        let (lines, columns) = self.win.get_max_yx();
+        (columns, lines)
+    }
+
+    fn colours(&self) -> i32 {
+        pancurses::COLORS()
+    }
+
+    fn can_change_colour(&self) -> bool {
+        pancurses::can_change_color()
+    }
+
+    fn erase(&self) {
+        self.win.erase();
+    }
+
+    fn put(&self, x: i32, y: i32, s: &str, style: Style) {
+        let (columns, _) = self.size();
+        // Curses would go on on the next line.
+        let s = s
+            .chars()
+            .take((columns - x).max(0) as usize)
+            .collect::<String>();
+        self.win.color_set(self.pair(style));
+        self.win.mvaddstr(y, x, s);
+    }
+
+    fn clear_line(&self, x: i32, y: i32) {
+        self.win.mv(y, x);
+        self.win.clrtoeol();
+    }
+
+    fn present(&self) {
+        self.win.refresh();
+    }
+
+    fn read_key(&self) -> Option<Input> {
+        self.win.getch().and_then(input)
+    }
+
+    fn end(&self) {
+        set_focus_events(false);
+        pancurses::endwin();
+    }
+}
@@ -0,0 +1,265 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Finesse is placing every piece with as few key presses as possible.
+//!
+//! The fewest presses are found with a breadth first search on an empty
+//! board, from where a piece spawns to where the player left it.
+
+use std::collections::HashSet;
+use std::collections::VecDeque;
+
+use crate::model::spawn_pos;
+use crate::model::Board;
+use crate::model::Lock;
+use crate::model::Move;
+use crate::model::Piece;
+
+/// How well a piece was placed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finesse {
+    /// Key presses that weren't needed.
+    pub faults: usize,
+    /// The shortest way to place the piece, without the drop.
+    pub optimal: Vec<Move>,
+}
+
+/// Keeps track of the player's finesse during a session.
+pub struct Trainer {
+    /// Start a new game as soon as a fault is made.
+    pub restart_on_fault: bool,
+    pub pieces: usize,
+    pub perfect: usize,
+    pub faults: usize,
+    /// The last checked piece.
+    pub last: Option<Finesse>,
+}
+
+fn is_finesse_key(m: &Move) -> bool {
+    matches!(
+        m,
+        Move::Left | Move::Right | Move::RotateCw | Move::RotateCcw | Move::Rotate180
+    )
+}
+
+/// Finds the fewest moves that take a newly spawned piece to the
+/// given rotation and column on an empty board `width` wide.
+pub fn optimal_moves(piece: &Piece, width: u16, rotation: usize, x: i32) -> Option<Vec<Move>> {
+    let mut p = piece.clone();
+    // Rotations with the same shape in the same columns, like the two
+    // flat ones of an S, end up the same after the drop.
+    let place = |p: &Piece, x: i32| (p.curr().blocks(), x - i32::from(p.curr().offset().0));
+    p.set_rotation(rotation);
+    let target = place(&p, x);
+    // Low enough for every rotation to fit.
+    let y = (0..p.rotations())
+        .map(|r| {
+            p.set_rotation(r);
+            i32::from(p.curr().offset().1)
+        })
+        .max()
+        .unwrap_or(0);
+    let board = Board::new(width, y as u16 + 4);
+
+    p.set_rotation(0);
+    let start = (0, spawn_pos(&board, &p).0);
+    let mut visited = HashSet::new();
+    visited.insert(start);
+    let mut queue = VecDeque::new();
+    queue.push_back((start, vec![]));
+    while let Some(((r, px), moves)) = queue.pop_front() {
+        p.set_rotation(r);
+        if place(&p, px) == target {
+            return Some(moves);
+        }
+        let n = p.rotations();
+        for m in [
+            Move::Left,
+            Move::Right,
+            Move::RotateCw,
+            Move::RotateCcw,
+            Move::Rotate180,
+        ] {
+            let next = match m {
+                Move::Left => (r, px - 1),
+                Move::Right => (r, px + 1),
+                Move::RotateCw => ((r + 1) % n, px),
+                Move::RotateCcw => ((r + n - 1) % n, px),
+                _ => ((r + 2) % n, px),
+            };
+            p.set_rotation(next.0);
+            if visited.contains(&next) || !board.fits(&p, next.1, y) {
+                continue;
+            }
+            visited.insert(next);
+            let mut moves = moves.clone();
+            moves.push(m);
+            queue.push_back((next, moves));
+        }
+    }
+    None
+}
+
+/// Compares how a piece was placed with the fewest needed key presses.
+pub fn analyse(lock: &Lock, width: u16) -> Option<Finesse> {
+    let optimal = optimal_moves(&lock.piece, width, lock.piece.rotation(), lock.pos.0)?;
+    let pressed = lock.moves.iter().filter(|m| is_finesse_key(m)).count();
+    Some(Finesse {
+        faults: pressed.saturating_sub(optimal.len()),
+        optimal,
+    })
+}
+
+/// Describes moves in words, like "left left cw".
+pub fn describe(moves: &[Move]) -> String {
+    if moves.is_empty() {
+        return "drop".to_string();
+    }
+    moves
+        .iter()
+        .map(|m| match m {
+            Move::Left => "left",
+            Move::Right => "right",
+            Move::RotateCw => "cw",
+            Move::RotateCcw => "ccw",
+            Move::Rotate180 => "180",
+            Move::SoftDrop => "down",
+            Move::HardDrop => "drop",
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Trainer {
+    pub fn new(restart_on_fault: bool) -> Self {
+        Trainer {
+            restart_on_fault,
+            pieces: 0,
+            perfect: 0,
+            faults: 0,
+            last: None,
+        }
+    }
+
+    /// Checks a stuck piece on a board `width` wide.
+    ///
+    /// Returns true if the piece was placed with a finesse fault.
+    pub fn check(&mut self, lock: &Lock, width: u16) -> bool {
+        let f = match analyse(lock, width) {
+            Some(f) => f,
+            None => return false,
+        };
+        let fault = f.faults > 0;
+        self.pieces += 1;
+        self.faults += f.faults;
+        if !fault {
+            self.perfect += 1;
+        }
+        self.last = Some(f);
+        fault
+    }
+
+    /// The share of pieces placed without faults, in percent.
+    pub fn accuracy(&self) -> f64 {
+        if self.pieces == 0 {
+            return 100.0;
+        }
+        100.0 * self.perfect as f64 / self.pieces as f64
+    }
+
+    pub fn report(&self) -> String {
+        format!(
+            "Finesse: {} of {} pieces without faults ({:.1}%), {} faults in total.",
+            self.perfect,
+            self.pieces,
+            self.accuracy(),
+            self.faults
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::finesse::*;
+    use crate::model::Event;
+    use crate::model::Game;
+    use crate::model::PieceFactory;
+
+    fn new_game() -> Game {
+        Game::new(Board::new(10, 20), PieceFactory::with_seed(3))
+    }
+
+    fn check(t: &mut Trainer, g: &mut Game) -> bool {
+        let mut fault = false;
+        for e in g.events.drain(..) {
+            if let Event::Locked(lock) = e {
+                fault |= t.check(&lock, 10);
+            }
+        }
+        fault
+    }
+
+    #[test]
+    fn straight_drop_is_perfect() {
+        let mut g = new_game();
+        let mut t = Trainer::new(false);
+        g.fall();
+        assert!(!check(&mut t, &mut g));
+        assert_eq!(t.last.as_ref().unwrap().faults, 0);
+        assert_eq!(t.last.as_ref().unwrap().optimal, vec![]);
+        assert_eq!(t.perfect, 1);
+    }
+
+    #[test]
+    fn wasted_moves_are_faults() {
+        let mut g = new_game();
+        let mut t = Trainer::new(false);
+        g.left();
+        g.right();
+        g.left();
+        g.fall();
+        assert!(check(&mut t, &mut g));
+        let f = t.last.clone().unwrap();
+        assert_eq!(f.optimal, vec![Move::Left]);
+        assert_eq!(f.faults, 2);
+        assert_eq!(t.faults, 2);
+        assert_eq!(t.accuracy(), 0.0);
+        // Nothing new got stuck.
+        assert!(!check(&mut t, &mut g));
+        assert_eq!(t.pieces, 1);
+    }
+
+    #[test]
+    fn optimal_moves_to_the_wall() {
+        let p = crate::model::get_pieces()[0].clone();
+        let moves = optimal_moves(&p, 10, 0, 1).unwrap();
+        assert_eq!(moves, vec![Move::Left; 4]);
+        assert_eq!(describe(&moves), "left left left left");
+        assert!(optimal_moves(&p, 10, 0, 10).is_none());
+    }
+
+    #[test]
+    fn half_turned_s_lands_the_same() {
+        let s = crate::model::get_pieces()[2].clone();
+        assert_eq!(s.piece_type, crate::model::PieceType::S);
+        assert_eq!(optimal_moves(&s, 10, 2, 5), Some(vec![]));
+        assert_eq!(optimal_moves(&s, 10, 1, 5), Some(vec![Move::RotateCw]));
+    }
+
+    #[test]
+    fn moves_count_from_the_spawn() {
+        let mut o = crate::model::get_pieces()[0].clone();
+        o.spawn = (-3, 0);
+        assert_eq!(optimal_moves(&o, 10, 0, 2), Some(vec![]));
+        assert_eq!(optimal_moves(&o, 10, 0, 1), Some(vec![Move::Left]));
+
+        let factory = PieceFactory::with_pieces(vec![o], 1);
+        let mut g = Game::new(Board::new(10, 20), factory);
+        let mut t = Trainer::new(false);
+        g.left();
+        g.fall();
+        assert!(!check(&mut t, &mut g));
+        assert_eq!(t.last.unwrap().optimal, vec![Move::Left]);
+    }
+}
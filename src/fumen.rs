@@ -0,0 +1,843 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reading and writing fumen (v115) strings, the format used by the
+//! fumen editor and TETR.IO to share positions.
+//!
+//! A fumen is a list of pages. Every page has a field 10 wide and 23
+//! high with a garbage row below it, maybe a piece, a comment and some
+//! flags. Everything is written as little endian base 64 numbers:
+//!
+//! * The field as a run length encoded difference to the field the
+//!   last page left behind. An unchanged field is followed by the
+//!   number of following pages that also leave it unchanged.
+//! * The piece and the flags, in one number.
+//! * The comment, when it changes, four escaped characters per number.
+//!
+//! When the lock flag is set, the piece is put on the field and full
+//! lines are cleared before the next page.
+
+use crate::model::Board;
+use crate::model::Game;
+use crate::model::Piece;
+use crate::model::PieceFactory;
+use crate::model::PieceType;
+
+pub const FIELD_WIDTH: usize = 10;
+/// The height of the field, without the garbage row.
+pub const FIELD_HEIGHT: usize = 23;
+const FIELD_BLOCKS: usize = (FIELD_HEIGHT + 1) * FIELD_WIDTH;
+
+const TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const COMMENT_TABLE: &[u8] = b" !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+const COMMENT_BASE: u32 = COMMENT_TABLE.len() as u32 + 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rotation {
+    Spawn,
+    Right,
+    Reverse,
+    Left,
+}
+
+/// A piece as fumen sees it, with its rotation centre at x, y counted
+/// from the left and the bottom of the field.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FumenPiece {
+    pub piece_type: PieceType,
+    pub rotation: Rotation,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Flags {
+    /// Put the piece on the field before the next page.
+    pub lock: bool,
+    /// Raise the garbage row into the field after locking.
+    pub rise: bool,
+    /// Mirror the field after locking.
+    pub mirror: bool,
+    /// Use the guideline colours, only used on the first page.
+    pub colour: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Page {
+    /// The field, top row first, with the garbage row last.
+    pub field: Vec<PieceType>,
+    pub piece: Option<FumenPiece>,
+    pub comment: String,
+    pub flags: Flags,
+}
+
+fn type_to_number(t: PieceType) -> u32 {
+    match t {
+        PieceType::NONE => 0,
+        PieceType::I => 1,
+        PieceType::L => 2,
+        PieceType::O => 3,
+        PieceType::Z => 4,
+        PieceType::T => 5,
+        PieceType::J => 6,
+        PieceType::S => 7,
+        // Garbage, fumen has no other pieces and `from_game` turns them
+        // away.
+        _ => 8,
+    }
+}
+
+fn number_to_type(n: u32) -> Result<PieceType, String> {
+    Ok(match n {
+        0 => PieceType::NONE,
+        1 => PieceType::I,
+        2 => PieceType::L,
+        3 => PieceType::O,
+        4 => PieceType::Z,
+        5 => PieceType::T,
+        6 => PieceType::J,
+        7 => PieceType::S,
+        8 => PieceType::GARBAGE,
+        _ => return Err(format!("bad block: {}", n)),
+    })
+}
+
+fn rotation_to_number(r: Rotation) -> u32 {
+    match r {
+        Rotation::Reverse => 0,
+        Rotation::Right => 1,
+        Rotation::Spawn => 2,
+        Rotation::Left => 3,
+    }
+}
+
+fn number_to_rotation(n: u32) -> Rotation {
+    match n % 4 {
+        0 => Rotation::Reverse,
+        1 => Rotation::Right,
+        2 => Rotation::Spawn,
+        _ => Rotation::Left,
+    }
+}
+
+/// The fumen field index of x, y counted from the bottom left.
+fn index(x: i32, y: i32) -> Option<usize> {
+    // y == -1 is the garbage row.
+    if !(0..FIELD_WIDTH as i32).contains(&x) || !(-1..FIELD_HEIGHT as i32).contains(&y) {
+        return None;
+    }
+    Some((FIELD_HEIGHT as i32 - 1 - y) as usize * FIELD_WIDTH + x as usize)
+}
+
+impl FumenPiece {
+    /// The positions of the piece's blocks, counted from the bottom
+    /// left of the field.
+    pub fn blocks(&self) -> Vec<(i32, i32)> {
+        let blocks: [(i32, i32); 4] = match self.piece_type {
+            PieceType::I => [(0, 0), (-1, 0), (1, 0), (2, 0)],
+            PieceType::T => [(0, 0), (-1, 0), (1, 0), (0, 1)],
+            PieceType::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+            PieceType::L => [(0, 0), (-1, 0), (1, 0), (1, 1)],
+            PieceType::J => [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+            PieceType::S => [(0, 0), (-1, 0), (0, 1), (1, 1)],
+            PieceType::Z => [(0, 0), (1, 0), (0, 1), (-1, 1)],
+            _ => return vec![],
+        };
+        blocks
+            .iter()
+            .map(|&(x, y)| match self.rotation {
+                Rotation::Spawn => (x, y),
+                Rotation::Right => (y, -x),
+                Rotation::Reverse => (-x, -y),
+                Rotation::Left => (-y, x),
+            })
+            .map(|(x, y)| (self.x + x, self.y + y))
+            .collect()
+    }
+
+    /// Fumen stores some pieces' positions offset from their centres,
+    /// this is the difference.
+    fn position_fix(&self) -> (i32, i32) {
+        match (self.piece_type, self.rotation) {
+            (PieceType::O, Rotation::Left) => (1, -1),
+            (PieceType::O, Rotation::Reverse) => (1, 0),
+            (PieceType::O, Rotation::Spawn) => (0, -1),
+            (PieceType::I, Rotation::Reverse) => (1, 0),
+            (PieceType::I, Rotation::Left) => (0, -1),
+            (PieceType::S, Rotation::Spawn) => (0, -1),
+            (PieceType::S, Rotation::Right) => (-1, 0),
+            (PieceType::Z, Rotation::Spawn) => (0, -1),
+            (PieceType::Z, Rotation::Left) => (1, 0),
+            _ => (0, 0),
+        }
+    }
+}
+
+/// Reads base 64 numbers.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn read(&mut self, digits: usize) -> Result<u32, String> {
+        let mut value = 0;
+        let mut factor = 1;
+        for _ in 0..digits {
+            let c = *self.data.get(self.pos).ok_or("the fumen ended too early")?;
+            let digit = TABLE
+                .iter()
+                .position(|&t| t == c)
+                .ok_or_else(|| format!("bad character: {}", c as char))?;
+            value += digit as u32 * factor;
+            factor *= 64;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+}
+
+fn write(out: &mut Vec<u8>, mut value: u32, digits: usize) {
+    for _ in 0..digits {
+        out.push(TABLE[(value % 64) as usize]);
+        value /= 64;
+    }
+}
+
+/// Escapes like javascript's escape(), which fumen uses for comments.
+fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() || "@*_+-./".contains(c) {
+            out.push(c);
+        } else if (c as u32) < 256 {
+            out.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            for u in c.encode_utf16(&mut [0; 2]) {
+                out.push_str(&format!("%u{:04X}", u));
+            }
+        }
+    }
+    out
+}
+
+/// The reverse of escape.
+fn unescape(s: &str) -> String {
+    let mut units = vec![];
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let hex = |digits: &str| u16::from_str_radix(digits, 16).ok();
+        if rest.starts_with("%u") && rest.len() >= 6 {
+            if let Some(u) = rest.get(2..6).and_then(hex) {
+                units.push(u);
+                rest = &rest[6..];
+                continue;
+            }
+        }
+        if c == '%' && rest.len() >= 3 {
+            if let Some(u) = rest.get(1..3).and_then(hex) {
+                units.push(u);
+                rest = &rest[3..];
+                continue;
+            }
+        }
+        units.extend(c.encode_utf16(&mut [0; 2]).iter());
+        rest = &rest[c.len_utf8()..];
+    }
+    String::from_utf16_lossy(&units)
+}
+
+fn empty_field() -> Vec<PieceType> {
+    vec![PieceType::NONE; FIELD_BLOCKS]
+}
+
+/// Puts the piece on the field, clears full lines and raises and
+/// mirrors as the flags say.
+fn lock(field: &mut [PieceType], piece: &Option<FumenPiece>, flags: &Flags) {
+    if let Some(p) = piece {
+        for (x, y) in p.blocks() {
+            if let Some(i) = index(x, y) {
+                field[i] = p.piece_type;
+            }
+        }
+    }
+    let playfield = FIELD_HEIGHT * FIELD_WIDTH;
+    let mut rows = field[..playfield]
+        .chunks(FIELD_WIDTH)
+        .filter(|row| row.contains(&PieceType::NONE))
+        .flat_map(|row| row.iter().cloned())
+        .collect::<Vec<_>>();
+    let mut cleared = vec![PieceType::NONE; playfield - rows.len()];
+    cleared.append(&mut rows);
+    field[..playfield].copy_from_slice(&cleared);
+
+    if flags.rise {
+        field.copy_within(FIELD_WIDTH.., 0);
+        for t in &mut field[playfield..] {
+            *t = PieceType::NONE;
+        }
+    }
+    if flags.mirror {
+        for row in field[..playfield].chunks_mut(FIELD_WIDTH) {
+            row.reverse();
+        }
+    }
+}
+
+/// Decodes a fumen string, or an URL with one in it.
+pub fn decode(s: &str) -> Result<Vec<Page>, String> {
+    let start = s.find("115@").ok_or("not a v115 fumen")?;
+    let data = s[start + 4..]
+        .bytes()
+        .filter(|&c| c != b'?')
+        .collect::<Vec<_>>();
+    let mut r = Reader {
+        data: &data,
+        pos: 0,
+    };
+
+    let mut pages = vec![];
+    let mut prev_field = empty_field();
+    let mut prev_comment = String::new();
+    let mut repeat = 0;
+    while !r.is_empty() {
+        let mut field = prev_field.clone();
+        if repeat > 0 {
+            repeat -= 1;
+        } else {
+            let mut i = 0;
+            while i < FIELD_BLOCKS {
+                let v = r.read(2)? as usize;
+                let (diff, count) = (v / FIELD_BLOCKS, v % FIELD_BLOCKS + 1);
+                if diff == 8 && count == FIELD_BLOCKS {
+                    repeat = r.read(1)?;
+                }
+                if i + count > FIELD_BLOCKS {
+                    return Err("too many blocks in field".to_string());
+                }
+                for t in &mut field[i..i + count] {
+                    *t = number_to_type((type_to_number(*t) + diff as u32).wrapping_sub(8))?;
+                }
+                i += count;
+            }
+        }
+
+        let mut v = r.read(3)?;
+        let piece_type = number_to_type(v % 8)?;
+        v /= 8;
+        let rotation = number_to_rotation(v % 4);
+        v /= 4;
+        let pos = (v % FIELD_BLOCKS as u32) as i32;
+        v /= FIELD_BLOCKS as u32;
+        let flag = |v: &mut u32| {
+            let f = *v % 2 == 1;
+            *v /= 2;
+            f
+        };
+        let rise = flag(&mut v);
+        let mirror = flag(&mut v);
+        let colour = flag(&mut v);
+        let has_comment = flag(&mut v);
+        let lock_flag = !flag(&mut v);
+
+        let piece = match piece_type {
+            PieceType::NONE => None,
+            PieceType::GARBAGE => return Err("a garbage piece".to_string()),
+            _ => {
+                let mut p = FumenPiece {
+                    piece_type,
+                    rotation,
+                    x: pos % FIELD_WIDTH as i32,
+                    y: FIELD_HEIGHT as i32 - pos / FIELD_WIDTH as i32 - 1,
+                };
+                let (dx, dy) = p.position_fix();
+                p.x += dx;
+                p.y += dy;
+                Some(p)
+            }
+        };
+
+        if has_comment {
+            let len = r.read(2)? as usize;
+            let mut escaped = String::new();
+            for _ in 0..(len + 3) / 4 {
+                let mut v = r.read(5)?;
+                for _ in 0..4 {
+                    if let Some(&c) = COMMENT_TABLE.get((v % COMMENT_BASE) as usize) {
+                        escaped.push(c as char);
+                    }
+                    v /= COMMENT_BASE;
+                }
+            }
+            escaped.truncate(len);
+            prev_comment = unescape(&escaped);
+        }
+
+        let page = Page {
+            field,
+            piece,
+            comment: prev_comment.clone(),
+            flags: Flags {
+                lock: lock_flag,
+                rise,
+                mirror,
+                colour,
+            },
+        };
+        prev_field = page.field.clone();
+        if page.flags.lock {
+            lock(&mut prev_field, &page.piece, &page.flags);
+        }
+        pages.push(page);
+    }
+    if pages.is_empty() {
+        return Err("no pages".to_string());
+    }
+    Ok(pages)
+}
+
+pub fn encode(pages: &[Page]) -> String {
+    let mut out = vec![];
+    let mut prev_field = empty_field();
+    let mut prev_comment = String::new();
+    // Where the count of repeated unchanged fields is, if the last
+    // field was unchanged.
+    let mut repeat_at: Option<usize> = None;
+    for page in pages {
+        let diffs = page
+            .field
+            .iter()
+            .zip(prev_field.iter())
+            .map(|(&t, &p)| type_to_number(t) + 8 - type_to_number(p))
+            .collect::<Vec<_>>();
+        let changed = diffs.iter().any(|&d| d != 8);
+        match repeat_at {
+            Some(i) if !changed && out[i] != TABLE[63] => {
+                let n = TABLE.iter().position(|&c| c == out[i]).unwrap_or(0);
+                out[i] = TABLE[n + 1];
+            }
+            _ => {
+                let mut i = 0;
+                while i < FIELD_BLOCKS {
+                    let count = diffs[i..].iter().take_while(|&&d| d == diffs[i]).count();
+                    write(
+                        &mut out,
+                        diffs[i] * FIELD_BLOCKS as u32 + count as u32 - 1,
+                        2,
+                    );
+                    i += count;
+                }
+                repeat_at = None;
+                if !changed {
+                    repeat_at = Some(out.len());
+                    write(&mut out, 0, 1);
+                }
+            }
+        }
+
+        let has_comment = page.comment != prev_comment;
+        let (t, r, pos) = match &page.piece {
+            Some(p) => {
+                let (dx, dy) = p.position_fix();
+                let (x, y) = (p.x - dx, p.y - dy);
+                (
+                    type_to_number(p.piece_type),
+                    rotation_to_number(p.rotation),
+                    ((FIELD_HEIGHT as i32 - y - 1) * FIELD_WIDTH as i32 + x) as u32,
+                )
+            }
+            None => (0, 0, 0),
+        };
+        let mut flags = 0;
+        for f in [
+            !page.flags.lock,
+            has_comment,
+            page.flags.colour,
+            page.flags.mirror,
+            page.flags.rise,
+        ] {
+            flags = flags * 2 + u32::from(f);
+        }
+        write(
+            &mut out,
+            ((flags * FIELD_BLOCKS as u32 + pos) * 4 + r) * 8 + t,
+            3,
+        );
+
+        if has_comment {
+            let escaped = escape(&page.comment)
+                .bytes()
+                .take(4095)
+                .map(|c| COMMENT_TABLE.iter().position(|&t| t == c).unwrap_or(0) as u32)
+                .collect::<Vec<_>>();
+            write(&mut out, escaped.len() as u32, 2);
+            for chunk in escaped.chunks(4) {
+                let v = chunk.iter().rev().fold(0, |v, &c| v * COMMENT_BASE + c);
+                write(&mut out, v, 5);
+            }
+            prev_comment = page.comment.clone();
+        }
+
+        prev_field = page.field.clone();
+        if page.flags.lock {
+            lock(&mut prev_field, &page.piece, &page.flags);
+        }
+    }
+
+    // The fumen editor breaks long strings with question marks.
+    let data = String::from_utf8(out).expect("only base 64 characters");
+    let mut s = String::from("v115@");
+    for (i, c) in data.chars().enumerate() {
+        if i >= 42 && (i - 42) % 47 == 0 {
+            s.push('?');
+        }
+        s.push(c);
+    }
+    s
+}
+
+/// The board cells, counted from the top left, of a fumen piece on a
+/// board `height` high.
+fn board_cells(p: &FumenPiece, height: u16) -> Vec<(i32, i32)> {
+    let mut cells = p
+        .blocks()
+        .iter()
+        .map(|&(x, y)| (x, i32::from(height) - 1 - y))
+        .collect::<Vec<_>>();
+    cells.sort_unstable();
+    cells
+}
+
+fn piece_cells(b: &Board, p: &Piece, x: i32, y: i32) -> Vec<(i32, i32)> {
+    let mut cells = b.cells(p, x, y);
+    cells.sort_unstable();
+    cells
+}
+
+impl Page {
+    pub fn new(field: Vec<PieceType>) -> Self {
+        Page {
+            field,
+            piece: None,
+            comment: String::new(),
+            flags: Flags {
+                lock: true,
+                rise: false,
+                mirror: false,
+                colour: true,
+            },
+        }
+    }
+
+    /// The page's field, without the piece, at the bottom of a board
+    /// `height` high.
+    pub fn board(&self, height: u16) -> Result<Board, String> {
+        let height = usize::from(height);
+        let top = FIELD_HEIGHT.saturating_sub(height);
+        if self.field[..top * FIELD_WIDTH]
+            .iter()
+            .any(|&t| t != PieceType::NONE)
+        {
+            return Err(format!("the field is higher than {} rows", height));
+        }
+        let mut b = Board::new(FIELD_WIDTH as u16, height as u16);
+        let rows = FIELD_HEIGHT - top;
+        let start = (height - rows) * FIELD_WIDTH;
+        b.map[start..].copy_from_slice(&self.field[top * FIELD_WIDTH..FIELD_HEIGHT * FIELD_WIDTH]);
+        Ok(b)
+    }
+
+    /// Finds our piece, rotation and position that covers the same
+    /// cells as the page's piece on a board `height` high.
+    pub fn game_piece(&self, height: u16) -> Option<(Piece, (i32, i32))> {
+        let fp = self.piece.as_ref()?;
+        let wanted = board_cells(fp, height);
+        let mut p = PieceFactory::new().piece(fp.piece_type)?;
+        let b = Board::new(FIELD_WIDTH as u16, height);
+        for r in 0..p.rotations() {
+            p.set_rotation(r);
+            for y in 0..i32::from(height) {
+                for x in 0..FIELD_WIDTH as i32 {
+                    if b.fits(&p, x, y) && piece_cells(&b, &p, x, y) == wanted {
+                        return Some((p, (x, y)));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// A page with the game's board and current piece.
+    pub fn from_game(g: &Game) -> Result<Page, String> {
+        let b = &g.board;
+        let height = usize::from(b.height());
+        if usize::from(b.width()) != FIELD_WIDTH {
+            return Err(format!("fumen boards are {} wide", FIELD_WIDTH));
+        }
+        let custom = |t: &PieceType| !t.is_built_in();
+        if custom(&g.piece.piece_type) || b.map.iter().any(custom) {
+            return Err("fumen only has the seven usual pieces".to_string());
+        }
+        let mut board = b.clone();
+        board.clear(&g.piece, g.pos.0, g.pos.1);
+        let skip = height.saturating_sub(FIELD_HEIGHT);
+        if board.map[..skip * FIELD_WIDTH]
+            .iter()
+            .any(|&t| t != PieceType::NONE)
+        {
+            return Err(format!("fumen boards are {} high", FIELD_HEIGHT));
+        }
+        let mut field = empty_field();
+        let rows = height - skip;
+        let start = (FIELD_HEIGHT - rows) * FIELD_WIDTH;
+        field[start..FIELD_HEIGHT * FIELD_WIDTH].copy_from_slice(&board.map[skip * FIELD_WIDTH..]);
+
+        let mut page = Page::new(field);
+        if g.game_over {
+            return Ok(page);
+        }
+        let wanted = piece_cells(b, &g.piece, g.pos.0, g.pos.1);
+        for rotation in [
+            Rotation::Spawn,
+            Rotation::Right,
+            Rotation::Reverse,
+            Rotation::Left,
+        ] {
+            for y in 0..height as i32 {
+                for x in 0..FIELD_WIDTH as i32 {
+                    let p = FumenPiece {
+                        piece_type: g.piece.piece_type,
+                        rotation,
+                        x,
+                        y,
+                    };
+                    if board_cells(&p, height as u16) == wanted {
+                        page.piece = Some(p);
+                        return Ok(page);
+                    }
+                }
+            }
+        }
+        Err("the piece isn't on the fumen field".to_string())
+    }
+}
+
+/// The game's position as a fumen string.
+pub fn export(g: &Game) -> Result<String, String> {
+    let mut page = Page::from_game(g)?;
+    page.comment = format!("Score {}, {} lines", g.score, g.lines);
+    Ok(encode(&[page]))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fumen::*;
+
+    const EMPTY: &str = "v115@vhAAgH";
+    const TETRIS_READY: &str = "v115@9gI8AeI8AeI8AeI8KeAgH";
+
+    #[test]
+    fn empty_field() {
+        let pages = decode(EMPTY).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0], Page::new(super::empty_field()));
+        assert_eq!(encode(&pages), EMPTY);
+    }
+
+    #[test]
+    fn garbage_well() {
+        let pages = decode(TETRIS_READY).unwrap();
+        assert_eq!(pages.len(), 1);
+        let b = pages[0].board(20).unwrap();
+        for y in 0..16 {
+            for x in 0..10 {
+                assert!(!b.is_set(x, y));
+            }
+        }
+        for y in 16..20 {
+            for x in 0..9 {
+                assert_eq!(b.map[x + y * 10], PieceType::GARBAGE);
+            }
+            assert!(!b.is_set(9, y as i32));
+        }
+        assert_eq!(encode(&pages), TETRIS_READY);
+    }
+
+    #[test]
+    fn pieces_and_comments() {
+        // A T at the bottom on the first page.
+        let s = "v115@vhAVQJ";
+        let pages = decode(s).unwrap();
+        let p = pages[0].piece.clone().unwrap();
+        assert_eq!(p.piece_type, PieceType::T);
+        assert_eq!(p.rotation, Rotation::Spawn);
+        assert_eq!((p.x, p.y), (4, 0));
+        assert_eq!(encode(&pages), s);
+
+        // A page with only the comment "4".
+        let s = "v115@vhAAgWBAUAAAA";
+        let pages = decode(s).unwrap();
+        assert_eq!(pages[0].comment, "4");
+        assert_eq!(encode(&pages), s);
+    }
+
+    #[test]
+    fn pages_round_trip() {
+        let mut pages = vec![];
+        let mut page = Page::new(super::empty_field());
+        page.comment = "Hej på dig, 100% \u{1f600}".to_string();
+        for (i, (t, r)) in [
+            (PieceType::I, Rotation::Spawn),
+            (PieceType::O, Rotation::Left),
+            (PieceType::S, Rotation::Right),
+            (PieceType::Z, Rotation::Reverse),
+            (PieceType::L, Rotation::Left),
+        ]
+        .iter()
+        .enumerate()
+        {
+            page.piece = Some(FumenPiece {
+                piece_type: *t,
+                rotation: *r,
+                x: 2 + i as i32,
+                y: 1 + i as i32,
+            });
+            pages.push(page.clone());
+            page.flags.colour = false;
+        }
+        // Repeated fields, without pieces.
+        page.piece = None;
+        page.comment.clear();
+        for _ in 0..70 {
+            pages.push(page.clone());
+        }
+        // The fields follow from the locked pieces.
+        let s = encode(&pages);
+        let decoded = decode(&s).unwrap();
+        assert_eq!(decoded.len(), pages.len());
+        for (d, p) in decoded.iter().zip(pages.iter()) {
+            assert_eq!(d.piece, p.piece);
+            assert_eq!(d.comment, p.comment);
+        }
+        assert_eq!(encode(&decoded), s);
+        assert!(s.contains('?'));
+    }
+
+    #[test]
+    fn lines_are_cleared_between_pages() {
+        let mut field = super::empty_field();
+        for x in 0..6 {
+            field[FIELD_WIDTH * (FIELD_HEIGHT - 1) + x] = PieceType::GARBAGE;
+        }
+        let mut page = Page::new(field);
+        page.piece = Some(FumenPiece {
+            piece_type: PieceType::I,
+            rotation: Rotation::Spawn,
+            x: 7,
+            y: 0,
+        });
+        let mut next = Page::new(super::empty_field());
+        next.flags.colour = false;
+        let pages = decode(&encode(&[page, next])).unwrap();
+        assert_eq!(pages[1].field, super::empty_field());
+    }
+
+    #[test]
+    fn game_round_trip() {
+        let mut g = Game::new(Board::new(10, 20), PieceFactory::with_seed(5));
+        g.draw();
+        crate::bot::play(&mut g, &crate::bot::Weights::default(), 20);
+        for _ in 0..3 {
+            g.down();
+        }
+        let s = export(&g).unwrap();
+        let page = decode(&s).unwrap().remove(0);
+        let mut b = page.board(20).unwrap();
+        let (p, (x, y)) = page.game_piece(20).unwrap();
+        assert_eq!(p.piece_type, g.piece.piece_type);
+        b.draw(&p, x, y);
+        assert_eq!(b.map, g.board.map);
+        assert!(page.comment.starts_with("Score"));
+    }
+
+    #[test]
+    fn every_piece_exports() {
+        for &t in PieceType::PIECES.iter() {
+            let mut g = Game::new(Board::new(10, 20), PieceFactory::from_sequence(vec![t]));
+            g.draw();
+            for r in 0..4 {
+                g.soft_drop();
+                g.soft_drop();
+                let page = decode(&export(&g).unwrap()).unwrap().remove(0);
+                let (p, (x, y)) = page.game_piece(20).unwrap();
+                assert_eq!(p.piece_type, t);
+                assert_eq!(
+                    piece_cells(&g.board, &p, x, y),
+                    piece_cells(&g.board, &g.piece, g.pos.0, g.pos.1),
+                    "{:?} {}",
+                    t,
+                    r
+                );
+                g.up();
+            }
+        }
+    }
+
+    #[test]
+    fn every_piece_imports() {
+        for &t in PieceType::PIECES.iter() {
+            for rotation in [
+                Rotation::Spawn,
+                Rotation::Right,
+                Rotation::Reverse,
+                Rotation::Left,
+            ] {
+                let mut page = Page::new(super::empty_field());
+                let fp = FumenPiece {
+                    piece_type: t,
+                    rotation,
+                    x: 4,
+                    y: 10,
+                };
+                page.piece = Some(fp.clone());
+                let page = decode(&encode(&[page])).unwrap().remove(0);
+                let (p, pos) = page.game_piece(20).unwrap();
+                assert_eq!(p.piece_type, t);
+                let mut g = Game::new(Board::new(10, 20), PieceFactory::from_sequence(vec![t]));
+                g.board = Board::new(10, 20);
+                g.piece = p;
+                g.pos = pos;
+                g.draw();
+                assert_eq!(
+                    piece_cells(&g.board, &g.piece, pos.0, pos.1),
+                    board_cells(&fp, 20),
+                    "{:?} {:?}",
+                    t,
+                    rotation
+                );
+                let back = Page::from_game(&g).unwrap().piece.unwrap();
+                assert_eq!(board_cells(&back, 20), board_cells(&fp, 20));
+            }
+        }
+    }
+
+    #[test]
+    fn bad_fumens() {
+        assert!(decode("v110@vhAAgH").is_err());
+        assert!(decode("v115@").is_err());
+        assert!(decode("v115@vhAAg").is_err());
+        assert!(decode("v115@vh!AgH").is_err());
+
+        // A block in the top row doesn't fit a board 20 high.
+        let mut field = super::empty_field();
+        field[0] = PieceType::GARBAGE;
+        let pages = decode(&encode(&[Page::new(field)])).unwrap();
+        assert!(pages[0].board(20).is_err());
+        assert!(pages[0].board(23).is_ok());
+    }
+}
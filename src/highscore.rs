@@ -0,0 +1,413 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The high score tables, one per game mode and board size.
+//!
+//! They are kept in one text file in the data directory, with one tab
+//! separated entry per line. Several games can end at the same time,
+//! so the file is only changed while holding a lock file, by writing a
+//! new file and renaming it over the old one.
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crate::paths;
+
+/// Entries kept per table.
+pub const TABLE_SIZE: usize = 10;
+
+const HEADER: &str = "tetris-highscores 1";
+const MAX_NAME_LEN: usize = 16;
+
+/// How long to wait for another game to release the lock.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+/// A lock older than this was left behind by a crashed game.
+const STALE_LOCK: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub mode: String,
+    pub width: u16,
+    pub height: u16,
+    pub name: String,
+    pub score: u32,
+    pub lines: u16,
+    pub level: u16,
+    pub seconds: u64,
+    /// As YYYY-MM-DD.
+    pub date: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HighScores {
+    pub entries: Vec<Entry>,
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    paths::data_dir().map(|d| d.join("highscores"))
+}
+
+/// Today's date, in UTC.
+pub fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    date_from_days((secs / 86400) as i64)
+}
+
+/// The date of a day counted from 1970-01-01, from Howard Hinnant's
+/// civil_from_days.
+fn date_from_days(days: i64) -> String {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+/// Removes what would break the file format from a player's name.
+pub fn clean_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LEN)
+        .collect::<String>();
+    let name = name.trim();
+    if name.is_empty() {
+        "Anonymous".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Formats seconds as minutes:seconds.
+pub fn format_time(seconds: u64) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+impl Entry {
+    fn parse(line: &str) -> Option<Entry> {
+        let f = line.split('\t').collect::<Vec<_>>();
+        if f.len() != 9 {
+            return None;
+        }
+        Some(Entry {
+            mode: f[0].to_string(),
+            width: f[1].parse().ok()?,
+            height: f[2].parse().ok()?,
+            name: clean_name(f[3]),
+            score: f[4].parse().ok()?,
+            lines: f[5].parse().ok()?,
+            level: f[6].parse().ok()?,
+            seconds: f[7].parse().ok()?,
+            date: f[8].to_string(),
+        })
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.mode,
+            self.width,
+            self.height,
+            clean_name(&self.name),
+            self.score,
+            self.lines,
+            self.level,
+            self.seconds,
+            self.date
+        )
+    }
+
+    fn same_table(&self, mode: &str, width: u16, height: u16) -> bool {
+        self.mode == mode && self.width == width && self.height == height
+    }
+}
+
+impl HighScores {
+    /// Reads the entries, skipping lines that can't be read.
+    ///
+    /// Returns None if it isn't a high score file at all.
+    pub fn parse(text: &str) -> Option<HighScores> {
+        let mut lines = text.lines();
+        if lines.next()? != HEADER {
+            return None;
+        }
+        Some(HighScores {
+            entries: lines.filter_map(Entry::parse).collect(),
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{}\n", HEADER);
+        for e in &self.entries {
+            out.push_str(&e.to_line());
+            out.push('\n');
+        }
+        out
+    }
+
+    /// The best entries for a mode and board size, best first.
+    pub fn table(&self, mode: &str, width: u16, height: u16) -> Vec<&Entry> {
+        let mut t = self
+            .entries
+            .iter()
+            .filter(|e| e.same_table(mode, width, height))
+            .collect::<Vec<_>>();
+        // Stable, so the oldest of equal scores stays first.
+        t.sort_by_key(|e| std::cmp::Reverse(e.score));
+        t.truncate(TABLE_SIZE);
+        t
+    }
+
+    /// Would the score get into the table?
+    pub fn qualifies(&self, mode: &str, width: u16, height: u16, score: u32) -> bool {
+        let t = self.table(mode, width, height);
+        t.len() < TABLE_SIZE || t.last().map_or(true, |e| score > e.score)
+    }
+
+    /// Adds an entry and drops the ones pushed out of its table.
+    pub fn add(&mut self, entry: Entry) {
+        let (mode, width, height) = (entry.mode.clone(), entry.width, entry.height);
+        self.entries.push(entry);
+        let (mut table, others): (Vec<_>, Vec<_>) = self
+            .entries
+            .drain(..)
+            .partition(|e| e.same_table(&mode, width, height));
+        // Stable, as in `table`.
+        table.sort_by_key(|e| std::cmp::Reverse(e.score));
+        table.truncate(TABLE_SIZE);
+        self.entries = others;
+        self.entries.extend(table);
+    }
+
+    /// Loads the high scores, or none if there is no file yet.
+    ///
+    /// A file that isn't a high score file is moved away, so it isn't
+    /// overwritten, and none are returned.
+    pub fn load(path: &Path) -> io::Result<HighScores> {
+        let text = match fs::read(path) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HighScores::default()),
+            Err(e) => return Err(e),
+        };
+        match HighScores::parse(&text) {
+            Some(h) => Ok(h),
+            None => {
+                fs::rename(path, path.with_extension("corrupt"))?;
+                Ok(HighScores::default())
+            }
+        }
+    }
+}
+
+/// Holds the lock file while alive.
+pub(crate) struct Lock {
+    path: PathBuf,
+}
+
+impl Lock {
+    /// Takes the lock for the file at `path`, waiting for a while if
+    /// another game has it.
+    pub(crate) fn take(path: &Path) -> io::Result<Lock> {
+        let path = path.with_extension("lock");
+        let start = SystemTime::now();
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(Lock { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let age = fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|t| t.elapsed().ok());
+                    if age.map_or(false, |age| age > STALE_LOCK) {
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if start.elapsed().map_or(true, |t| t > LOCK_TIMEOUT) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("{} is locked", path.display()),
+                        ));
+                    }
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Adds an entry to the high score file.
+///
+/// Returns all the high scores, including the new one.
+pub fn record(path: &Path, entry: Entry) -> io::Result<HighScores> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let _lock = Lock::take(path)?;
+    // Read again, another game might have added scores since.
+    let mut h = HighScores::load(path)?;
+    h.add(entry);
+    let tmp = path.with_extension("tmp");
+    let mut f = fs::File::create(&tmp)?;
+    f.write_all(h.to_text().as_bytes())?;
+    f.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(h)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::highscore::*;
+
+    fn entry(name: &str, score: u32) -> Entry {
+        Entry {
+            mode: "marathon".to_string(),
+            width: 10,
+            height: 20,
+            name: name.to_string(),
+            score,
+            lines: 3,
+            level: 1,
+            seconds: 75,
+            date: "2022-05-01".to_string(),
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("tetris-highscore-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("highscores")
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(date_from_days(0), "1970-01-01");
+        assert_eq!(date_from_days(19_144), "2022-06-01");
+        assert_eq!(date_from_days(11_016), "2000-02-29");
+        assert_eq!(format_time(75), "1:15");
+    }
+
+    #[test]
+    fn tables_are_kept_apart_and_short() {
+        let mut h = HighScores::default();
+        for i in 0..15 {
+            h.add(entry("a", i * 10));
+        }
+        let mut other = entry("b", 5);
+        other.mode = "finesse".to_string();
+        h.add(other.clone());
+        let mut wide = entry("c", 1);
+        wide.width = 12;
+        h.add(wide);
+
+        let t = h.table("marathon", 10, 20);
+        assert_eq!(t.len(), TABLE_SIZE);
+        assert_eq!(t[0].score, 140);
+        assert_eq!(t[9].score, 50);
+        assert_eq!(h.table("finesse", 10, 20), vec![&other]);
+        assert_eq!(h.table("marathon", 12, 20).len(), 1);
+        assert_eq!(h.entries.len(), TABLE_SIZE + 2);
+
+        assert!(!h.qualifies("marathon", 10, 20, 50));
+        assert!(h.qualifies("marathon", 10, 20, 51));
+        assert!(h.qualifies("finesse", 10, 20, 0));
+    }
+
+    #[test]
+    fn equal_entries_are_kept_short() {
+        let mut h = HighScores::default();
+        for _ in 0..TABLE_SIZE + 5 {
+            h.add(entry("a", 100));
+        }
+        assert_eq!(h.entries.len(), TABLE_SIZE);
+        assert_eq!(h.table("marathon", 10, 20).len(), TABLE_SIZE);
+        assert!(!h.qualifies("marathon", 10, 20, 100));
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut h = HighScores::default();
+        h.add(entry("Tab\there", 100));
+        h.add(entry("  ", 50));
+        let h2 = HighScores::parse(&h.to_text()).unwrap();
+        assert_eq!(h2.entries[0].name, "Tabhere");
+        assert_eq!(h2.entries[1].name, "Anonymous");
+        assert_eq!(h2.to_text(), h.to_text());
+    }
+
+    #[test]
+    fn bad_lines_are_skipped() {
+        let text = format!(
+            "{}\nmarathon\t10\t20\tx\tlots\t1\t1\t1\t2022-01-01\nhalf a line\n{}\n",
+            HEADER,
+            entry("ok", 7).to_line()
+        );
+        let h = HighScores::parse(&text).unwrap();
+        assert_eq!(h.entries, vec![entry("ok", 7)]);
+        assert!(HighScores::parse("garbage").is_none());
+    }
+
+    #[test]
+    fn record_to_file() {
+        let path = temp_path("record");
+        record(&path, entry("a", 10)).unwrap();
+        let h = record(&path, entry("b", 20)).unwrap();
+        assert_eq!(h.table("marathon", 10, 20).len(), 2);
+        assert_eq!(HighScores::load(&path).unwrap(), h);
+        assert!(!path.with_extension("lock").exists());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn corrupt_file_is_kept() {
+        let path = temp_path("corrupt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, [0xff, 0, 1, 2]).unwrap();
+        let h = record(&path, entry("a", 10)).unwrap();
+        assert_eq!(h.entries.len(), 1);
+        assert!(path.with_extension("corrupt").exists());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn concurrent_records() {
+        let path = temp_path("concurrent");
+        let threads = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || record(&path, entry("t", i)).unwrap())
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+        let h = HighScores::load(&path).unwrap();
+        assert_eq!(h.entries.len(), 8);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
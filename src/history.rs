@@ -0,0 +1,534 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Every finished game, to see how the player gets better.
+//!
+//! The games are kept in a text file in the data directory, one tab
+//! separated entry per line, oldest first. A game is added by
+//! appending its line while holding the high scores' kind of lock.
+//! The history can be exported as CSV or JSON.
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::highscore;
+use crate::highscore::Lock;
+use crate::model::Game;
+use crate::paths;
+use crate::stats;
+
+const HEADER: &str = "tetris-history 1";
+const FIELDS: usize = 17;
+
+/// The games compared with the ones before them.
+const RECENT: usize = 10;
+/// The games listed on the stats screen.
+const LISTED: usize = 5;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// As YYYY-MM-DD.
+    pub date: String,
+    pub mode: String,
+    /// The seed the pieces came from.
+    pub seed: u64,
+    pub width: u16,
+    pub height: u16,
+    pub score: u32,
+    pub lines: u16,
+    /// The time played, in milliseconds.
+    pub millis: u64,
+    pub pieces: u32,
+    pub keys: u32,
+    pub attack: u32,
+    pub faults: u32,
+    /// The singles, doubles, triples and tetrises.
+    pub clears: [u32; 4],
+    /// The T-spins that cleared lines.
+    pub tspins: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct History {
+    pub entries: Vec<Entry>,
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    paths::data_dir().map(|d| d.join("history"))
+}
+
+/// Quotes a CSV field if it needs it.
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl Entry {
+    /// The entry for a finished game of the named mode.
+    pub fn new(g: &Game, mode: &str) -> Entry {
+        let s = &g.stats;
+        Entry {
+            date: highscore::today(),
+            mode: mode.to_string(),
+            seed: g.seed(),
+            width: g.board.width(),
+            height: g.board.height(),
+            score: g.score,
+            lines: g.lines,
+            millis: g.time.as_millis() as u64,
+            pieces: s.pieces,
+            keys: s.keys,
+            attack: s.attack,
+            faults: s.faults,
+            clears: s.clears,
+            tspins: s.tspins,
+        }
+    }
+
+    pub fn time(&self) -> Duration {
+        Duration::from_millis(self.millis)
+    }
+
+    /// Pieces per second.
+    pub fn pps(&self) -> f64 {
+        stats::per_second(self.pieces, self.time())
+    }
+
+    /// Attack per minute.
+    pub fn apm(&self) -> f64 {
+        60.0 * stats::per_second(self.attack, self.time())
+    }
+
+    /// Keys per piece.
+    pub fn kpp(&self) -> f64 {
+        if self.pieces == 0 {
+            return 0.0;
+        }
+        f64::from(self.keys) / f64::from(self.pieces)
+    }
+
+    fn parse(line: &str) -> Option<Entry> {
+        let f = line.split('\t').collect::<Vec<_>>();
+        if f.len() != FIELDS {
+            return None;
+        }
+        let n = |i: usize| f[i].parse::<u32>().ok();
+        Some(Entry {
+            date: f[0].to_string(),
+            mode: f[1].to_string(),
+            seed: f[2].parse().ok()?,
+            width: f[3].parse().ok()?,
+            height: f[4].parse().ok()?,
+            score: n(5)?,
+            lines: f[6].parse().ok()?,
+            millis: f[7].parse().ok()?,
+            pieces: n(8)?,
+            keys: n(9)?,
+            attack: n(10)?,
+            faults: n(11)?,
+            clears: [n(12)?, n(13)?, n(14)?, n(15)?],
+            tspins: n(16)?,
+        })
+    }
+
+    fn to_line(&self) -> String {
+        // The mode is written by the game, but a tab would break the
+        // line.
+        let mode = self.mode.replace(|c: char| c.is_control(), " ");
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.date,
+            mode,
+            self.seed,
+            self.width,
+            self.height,
+            self.score,
+            self.lines,
+            self.millis,
+            self.pieces,
+            self.keys,
+            self.attack,
+            self.faults,
+            self.clears[0],
+            self.clears[1],
+            self.clears[2],
+            self.clears[3],
+            self.tspins
+        )
+    }
+}
+
+/// A line of the trends: its name, what it averages and the decimals
+/// it's shown with.
+type Trend = (&'static str, fn(&Entry) -> f64, usize);
+
+/// The average of something over some games, or None without games.
+fn average(entries: &[Entry], f: impl Fn(&Entry) -> f64) -> Option<f64> {
+    if entries.is_empty() {
+        return None;
+    }
+    Some(entries.iter().map(f).sum::<f64>() / entries.len() as f64)
+}
+
+fn best(entries: &[Entry], f: impl Fn(&Entry) -> f64) -> f64 {
+    entries.iter().map(f).fold(0.0, f64::max)
+}
+
+impl History {
+    /// Reads the entries, skipping lines that can't be read.
+    ///
+    /// Returns None if it isn't a history file at all.
+    pub fn parse(text: &str) -> Option<History> {
+        let mut lines = text.lines();
+        if lines.next()? != HEADER {
+            return None;
+        }
+        Some(History {
+            entries: lines.filter_map(Entry::parse).collect(),
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{}\n", HEADER);
+        for e in &self.entries {
+            out.push_str(&e.to_line());
+            out.push('\n');
+        }
+        out
+    }
+
+    /// The games as CSV, with a header and the rates worked out.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from(
+            "date,mode,seed,width,height,score,lines,seconds,pieces,pps,keys,kpp,\
+             attack,apm,faults,singles,doubles,triples,tetrises,tspins\n",
+        );
+        for e in &self.entries {
+            out.push_str(&format!(
+                "{},{},{},{},{},{},{},{:.3},{},{:.3},{},{:.3},{},{:.3},{},{},{},{},{},{}\n",
+                csv_field(&e.date),
+                csv_field(&e.mode),
+                e.seed,
+                e.width,
+                e.height,
+                e.score,
+                e.lines,
+                e.time().as_secs_f64(),
+                e.pieces,
+                e.pps(),
+                e.keys,
+                e.kpp(),
+                e.attack,
+                e.apm(),
+                e.faults,
+                e.clears[0],
+                e.clears[1],
+                e.clears[2],
+                e.clears[3],
+                e.tspins
+            ));
+        }
+        out
+    }
+
+    /// The games as a JSON array of objects, one game per line. The
+    /// seed is a string, it's too big for a double.
+    pub fn to_json(&self) -> String {
+        let games = self
+            .entries
+            .iter()
+            .map(|e| {
+                format!(
+                    "  {{\"date\": {}, \"mode\": {}, \"seed\": \"{}\", \"width\": {}, \
+                     \"height\": {}, \"score\": {}, \"lines\": {}, \"seconds\": {:.3}, \
+                     \"pieces\": {}, \"pps\": {:.3}, \"keys\": {}, \"kpp\": {:.3}, \
+                     \"attack\": {}, \"apm\": {:.3}, \"faults\": {}, \"singles\": {}, \
+                     \"doubles\": {}, \"triples\": {}, \"tetrises\": {}, \"tspins\": {}}}",
+                    json_string(&e.date),
+                    json_string(&e.mode),
+                    e.seed,
+                    e.width,
+                    e.height,
+                    e.score,
+                    e.lines,
+                    e.time().as_secs_f64(),
+                    e.pieces,
+                    e.pps(),
+                    e.keys,
+                    e.kpp(),
+                    e.attack,
+                    e.apm(),
+                    e.faults,
+                    e.clears[0],
+                    e.clears[1],
+                    e.clears[2],
+                    e.clears[3],
+                    e.tspins
+                )
+            })
+            .collect::<Vec<_>>();
+        if games.is_empty() {
+            return "[]\n".to_string();
+        }
+        format!("[\n{}\n]\n", games.join(",\n"))
+    }
+
+    /// The personal bests, the last games against the ones before
+    /// them, and the last games, for the stats screen.
+    pub fn report(&self) -> Vec<String> {
+        let all = &self.entries[..];
+        if all.is_empty() {
+            return vec!["No games finished yet.".to_string()];
+        }
+        let seconds = all.iter().map(|e| e.millis / 1000).sum::<u64>();
+        let mut lines = vec![
+            format!(
+                "Games {}, played for {}",
+                all.len(),
+                highscore::format_time(seconds)
+            ),
+            format!(
+                "Best score {}, lines {}, PPS {:.2}, APM {:.1}",
+                best(all, |e| f64::from(e.score)),
+                best(all, |e| f64::from(e.lines)),
+                best(all, Entry::pps),
+                best(all, Entry::apm)
+            ),
+            String::new(),
+        ];
+
+        let split = all.len().saturating_sub(RECENT);
+        let (before, recent) = all.split_at(split);
+        lines.push(format!(
+            "{:8} {:>10} {:>10}",
+            "",
+            format!("Last {}", recent.len()),
+            "Before"
+        ));
+        let rows: [Trend; 5] = [
+            ("Score", |e| f64::from(e.score), 0),
+            ("Lines", |e| f64::from(e.lines), 0),
+            ("PPS", Entry::pps, 2),
+            ("APM", Entry::apm, 1),
+            ("KPP", Entry::kpp, 2),
+        ];
+        for (name, f, decimals) in rows {
+            let shown = |v: Option<f64>| match v {
+                Some(v) => format!("{:.*}", decimals, v),
+                None => "-".to_string(),
+            };
+            lines.push(format!(
+                "{:8} {:>10} {:>10}",
+                name,
+                shown(average(recent, f)),
+                shown(average(before, f))
+            ));
+        }
+
+        lines.push(String::new());
+        let best_pps = best(all, Entry::pps);
+        for e in all.iter().rev().take(LISTED) {
+            // A bar as long as the PPS, against the best.
+            let bar = if best_pps > 0.0 {
+                "#".repeat((10.0 * e.pps() / best_pps).round() as usize)
+            } else {
+                String::new()
+            };
+            lines.push(format!(
+                "{} {:9} {:>7} {:>4} {:5.2} {}",
+                e.date,
+                e.mode,
+                e.score,
+                e.lines,
+                e.pps(),
+                bar
+            ));
+        }
+        lines
+    }
+
+    /// Loads the history, or none if there is no file yet.
+    ///
+    /// A file that isn't a history file is moved away, so it isn't
+    /// added to, and none is returned.
+    pub fn load(path: &Path) -> io::Result<History> {
+        let text = match fs::read(path) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(History::default()),
+            Err(e) => return Err(e),
+        };
+        match History::parse(&text) {
+            Some(h) => Ok(h),
+            None => {
+                fs::rename(path, path.with_extension("corrupt"))?;
+                Ok(History::default())
+            }
+        }
+    }
+}
+
+/// Adds a finished game to the history file.
+pub fn record(path: &Path, entry: &Entry) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let _lock = Lock::take(path)?;
+    // A file that isn't a history is moved away first.
+    History::load(path)?;
+    let new = !path.exists();
+    let mut f = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)?;
+    let mut text = String::new();
+    if new {
+        text.push_str(HEADER);
+        text.push('\n');
+    }
+    text.push_str(&entry.to_line());
+    text.push('\n');
+    f.write_all(text.as_bytes())?;
+    f.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::*;
+
+    fn entry(score: u32, seconds: u64) -> Entry {
+        Entry {
+            date: "2022-05-01".to_string(),
+            mode: "marathon".to_string(),
+            seed: 42,
+            width: 10,
+            height: 20,
+            score,
+            lines: 12,
+            millis: seconds * 1000,
+            pieces: 60,
+            keys: 180,
+            attack: 6,
+            faults: 3,
+            clears: [4, 2, 0, 1],
+            tspins: 0,
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("tetris-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("history")
+    }
+
+    #[test]
+    fn rates() {
+        let e = entry(100, 30);
+        assert!((e.pps() - 2.0).abs() < 1e-9);
+        assert!((e.apm() - 12.0).abs() < 1e-9);
+        assert!((e.kpp() - 3.0).abs() < 1e-9);
+        assert_eq!(entry(100, 0).pps(), 0.0);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut odd = entry(5, 10);
+        odd.mode = "tab\there".to_string();
+        let h = History {
+            entries: vec![entry(100, 30), odd],
+        };
+        let h2 = History::parse(&h.to_text()).unwrap();
+        assert_eq!(h2.entries[0], h.entries[0]);
+        assert_eq!(h2.entries[1].mode, "tab here");
+        let text = format!("{}half a line\n", h.to_text());
+        assert_eq!(History::parse(&text).unwrap().entries.len(), 2);
+        assert!(History::parse("garbage").is_none());
+    }
+
+    #[test]
+    fn exports() {
+        let mut quoted = entry(5, 10);
+        quoted.mode = "a \"b\", c".to_string();
+        let h = History {
+            entries: vec![entry(100, 30), quoted],
+        };
+        let csv = h.to_csv();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("date,mode,seed,"));
+        assert_eq!(
+            lines[1],
+            "2022-05-01,marathon,42,10,20,100,12,30.000,60,2.000,180,3.000,\
+             6,12.000,3,4,2,0,1,0"
+        );
+        assert!(lines[2].starts_with("2022-05-01,\"a \"\"b\"\", c\",42,"));
+
+        let json = h.to_json();
+        assert!(json.starts_with("[\n  {\"date\": \"2022-05-01\", \"mode\": \"marathon\","));
+        assert!(json.contains("\"mode\": \"a \\\"b\\\", c\""));
+        assert!(json.contains("\"seed\": \"42\","));
+        assert!(json.contains("\"pps\": 2.000,"));
+        assert!(json.ends_with("}\n]\n"));
+        assert_eq!(History::default().to_json(), "[]\n");
+    }
+
+    #[test]
+    fn report_compares_the_last_games() {
+        assert_eq!(History::default().report().len(), 1);
+        let h = History {
+            entries: (0..12).map(|i| entry(100 * i, 30)).collect(),
+        };
+        let r = h.report();
+        assert_eq!(r[0], "Games 12, played for 6:00");
+        assert_eq!(r[1], "Best score 1100, lines 12, PPS 2.00, APM 12.0");
+        assert_eq!(r[3], "            Last 10     Before");
+        assert_eq!(r[4], "Score           650         50");
+        // The newest game first.
+        assert!(r[10].starts_with("2022-05-01 marathon     1100   12  2.00 ##########"));
+        assert_eq!(r.len(), 10 + LISTED);
+    }
+
+    #[test]
+    fn record_to_file() {
+        let path = temp_path("record");
+        record(&path, &entry(10, 5)).unwrap();
+        record(&path, &entry(20, 5)).unwrap();
+        let h = History::load(&path).unwrap();
+        assert_eq!(h.entries, vec![entry(10, 5), entry(20, 5)]);
+        assert!(!path.with_extension("lock").exists());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn corrupt_file_is_kept() {
+        let path = temp_path("corrupt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "not a history\n").unwrap();
+        record(&path, &entry(10, 5)).unwrap();
+        assert_eq!(History::load(&path).unwrap().entries.len(), 1);
+        assert!(path.with_extension("corrupt").exists());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
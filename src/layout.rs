@@ -0,0 +1,302 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Where the board and the panels beside it go on the screen.
+//!
+//! The board is drawn inside a border with the panels to its right,
+//! sharing the board's right border, and the stats to its left when
+//! they are shown:
+//!
+//! ```text
+//!         +--------------------+
+//! stats   |                    +--------+
+//!         |                    | lines  |
+//!         |                    +--------+
+//!         |                    | score  |
+//!         |                    +--------+
+//!         |                    | next   |
+//!         |                    | pieces |
+//!         |                    +--------+
+//!         |                    | hold   |
+//!         |                    +--------+
+//!         |                    |
+//!         |                    | finesse
+//!         |                    | and puzzle
+//!         +--------------------+
+//! export
+//! ```
+//!
+//! The whole of it is centred on the screen. The stats are left out
+//! when there is no room for them.
+
+use crate::theme::Geometry;
+
+/// The inside width of the panels, unless the pieces need more.
+const PANEL_WIDTH: i32 = 8;
+/// The finesse and puzzle texts get this wide before they are cut,
+/// when there is room for it.
+const INFO_WIDTH: i32 = 30;
+const FINESSE_LINES: i32 = 3;
+const PUZZLE_LINES: i32 = 4;
+pub const STATS_WIDTH: i32 = 13;
+pub const STATS_LINES: i32 = 12;
+
+/// A part of the screen, in columns and lines.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    /// The first column right of it.
+    pub fn right(&self) -> i32 {
+        self.x + self.width
+    }
+
+    /// The first line below it.
+    pub fn bottom(&self) -> i32 {
+        self.y + self.height
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout {
+    /// The board, inside its border.
+    pub board: Rect,
+    /// The inside of the panels, from the lines to the held piece.
+    pub panel: Rect,
+    pub lines: Rect,
+    pub score: Rect,
+    /// A box for each piece in the queue.
+    pub queue: Vec<Rect>,
+    pub hold: Rect,
+    pub finesse: Rect,
+    pub puzzle: Rect,
+    pub export: Rect,
+    pub stats: Option<Rect>,
+    /// The cells of the queue's boxes, as wide as they are high.
+    pub piece_size: u8,
+    /// The rows of the hold box.
+    pub hold_rows: u8,
+}
+
+impl Layout {
+    /// Lays out a `width` by `height` board with `queue` next pieces,
+    /// in boxes as big as `PieceFactory::preview_size` says, and the
+    /// `stats` if there is room, on a `screen` of columns and lines. When it doesn't fit, the smallest screen it fits on is
+    /// returned instead.
+    pub fn new(
+        width: u16,
+        height: u16,
+        queue: usize,
+        piece_box: (u8, u8),
+        geometry: Geometry,
+        stats: bool,
+        screen: (i32, i32),
+    ) -> Result<Layout, (i32, i32)> {
+        let (board_width, board_height) = (geometry.columns(width), geometry.lines(height));
+        let (piece_size, hold_rows) = piece_box;
+        let (piece_width, piece_height) = (
+            geometry.columns(u16::from(piece_size)),
+            geometry.lines(u16::from(piece_size)),
+        );
+        let panel_width = PANEL_WIDTH.max(piece_width);
+        let hold_height = geometry.lines(u16::from(hold_rows));
+        let queue_height = queue as i32 * piece_height;
+
+        // The lines, score, queue and hold with lines between them, the
+        // panels start a line below the board's top.
+        let panel_height = 5 + queue_height + hold_height;
+        let info_y = 2 + panel_height + 2;
+        let info_height = FINESSE_LINES + PUZZLE_LINES;
+        let export_y = (board_height + 1).max(info_y + info_height);
+
+        let min_width = 1 + board_width + 1 + panel_width + 1;
+        let min_height = 1 + export_y + 1;
+        let (screen_width, screen_height) = screen;
+        if screen_width < min_width || screen_height < min_height {
+            return Err((min_width, min_height));
+        }
+
+        let wanted = min_width.max(1 + board_width + 1 + 1 + INFO_WIDTH);
+        // The stats and a space before the board's border.
+        let stats_width = STATS_WIDTH + 1;
+        let stats = stats && screen_width >= stats_width + min_width;
+        let (offset, room) = if stats {
+            (stats_width, screen_width - stats_width)
+        } else {
+            (0, screen_width)
+        };
+        let left = if room >= wanted {
+            offset + (room - wanted) / 2
+        } else {
+            offset + (room - min_width) / 2
+        };
+        let top = (screen_height - min_height) / 2;
+
+        let board = Rect {
+            x: left + 1,
+            y: top + 1,
+            width: board_width,
+            height: board_height,
+        };
+        let panel = Rect {
+            x: board.right() + 1,
+            y: board.y + 2,
+            width: panel_width,
+            height: panel_height,
+        };
+        let row = |y: i32| Rect {
+            x: panel.x + 1,
+            y,
+            width: panel_width - 1,
+            height: 1,
+        };
+        let lines = row(panel.y);
+        let score = row(panel.y + 2);
+        let piece_x = panel.x + (panel_width - piece_width) / 2;
+        let queue = (0..queue as i32)
+            .map(|i| Rect {
+                x: piece_x,
+                y: score.bottom() + 1 + i * piece_height,
+                width: piece_width,
+                height: piece_height,
+            })
+            .collect::<Vec<_>>();
+        let hold = Rect {
+            x: piece_x,
+            y: score.bottom() + 1 + queue_height + 1,
+            width: piece_width,
+            height: hold_height,
+        };
+        let info_x = board.right() + 1;
+        let info_width = INFO_WIDTH.min(screen_width - info_x);
+        let finesse = Rect {
+            x: info_x,
+            y: board.y + info_y,
+            width: info_width,
+            height: FINESSE_LINES,
+        };
+        let puzzle = Rect {
+            x: info_x,
+            y: finesse.bottom(),
+            width: info_width,
+            height: PUZZLE_LINES,
+        };
+        let export = Rect {
+            x: 0,
+            y: board.y + export_y,
+            width: screen_width,
+            height: 1,
+        };
+        let stats = Some(Rect {
+            x: left - stats_width,
+            y: board.y,
+            width: STATS_WIDTH,
+            height: STATS_LINES,
+        })
+        .filter(|r| stats && r.bottom() <= screen_height);
+        Ok(Layout {
+            board,
+            panel,
+            lines,
+            score,
+            queue,
+            hold,
+            finesse,
+            puzzle,
+            export,
+            stats,
+            piece_size,
+            hold_rows,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::*;
+
+    const BLOCKS: Geometry = Geometry { half_blocks: false };
+    const HALF_BLOCKS: Geometry = Geometry { half_blocks: true };
+    const BOX: (u8, u8) = (4, 2);
+
+    #[test]
+    fn fits_the_classic_terminal() {
+        let l = Layout::new(10, 20, 1, BOX, BLOCKS, false, (80, 24)).unwrap();
+        assert_eq!(l.board.height, 20);
+        assert_eq!(l.board.y, 1);
+        assert_eq!(l.export.bottom(), 24);
+        // Centred with room for the finesse texts.
+        assert_eq!(l.board.x - 1, (80 - 53) / 2);
+        assert_eq!(l.finesse.width, 30);
+        assert_eq!(l.hold.bottom(), l.panel.bottom());
+        assert_eq!(
+            Layout::new(10, 20, 1, BOX, BLOCKS, false, (80, 23)),
+            Err((31, 24))
+        );
+        assert_eq!(
+            Layout::new(10, 20, 1, BOX, BLOCKS, false, (30, 24)),
+            Err((31, 24))
+        );
+    }
+
+    #[test]
+    fn panels_follow_the_board_and_queue() {
+        let l = Layout::new(10, 20, 3, BOX, BLOCKS, false, (120, 40)).unwrap();
+        assert_eq!(l.queue.len(), 3);
+        assert_eq!(l.queue[1].y, l.queue[0].bottom());
+        assert_eq!(l.hold.y, l.queue[2].bottom() + 1);
+        assert_eq!(l.panel.x, l.board.right() + 1);
+        // The info goes below the panels on short boards.
+        let l = Layout::new(4, 4, 1, BOX, BLOCKS, false, (80, 24)).unwrap();
+        assert!(l.finesse.y > l.panel.bottom());
+        assert!(l.export.y >= l.puzzle.bottom());
+    }
+
+    #[test]
+    fn half_blocks_take_less_room() {
+        let l = Layout::new(10, 40, 1, BOX, HALF_BLOCKS, false, (80, 24)).unwrap();
+        assert_eq!((l.board.width, l.board.height), (10, 20));
+        assert_eq!(l.queue[0].width, 4);
+        assert!(Layout::new(10, 40, 1, BOX, BLOCKS, false, (80, 24)).is_err());
+    }
+
+    #[test]
+    fn stats_left_of_the_board() {
+        let l = Layout::new(10, 20, 1, BOX, BLOCKS, true, (80, 24)).unwrap();
+        let stats = l.stats.unwrap();
+        assert_eq!(stats.right() + 2, l.board.x);
+        assert_eq!(stats.x, (80 - 14 - 53) / 2);
+        // Left out when there is no room.
+        let l = Layout::new(10, 20, 1, BOX, BLOCKS, true, (45, 24)).unwrap();
+        assert_eq!(l.stats.map(|r| r.x), Some(0));
+        let l = Layout::new(10, 20, 1, BOX, BLOCKS, true, (44, 24)).unwrap();
+        assert_eq!(l.stats, None);
+        assert_eq!(l.board.x - 1, (44 - 31) / 2);
+    }
+
+    #[test]
+    fn bigger_pieces_widen_the_panels() {
+        let l = Layout::new(10, 20, 1, (5, 3), BLOCKS, false, (80, 26)).unwrap();
+        assert_eq!(l.queue[0].width, 10);
+        assert_eq!(l.panel.width, 10);
+        assert_eq!(l.hold.height, 3);
+        assert_eq!(l.hold.bottom(), l.panel.bottom());
+        assert_eq!(
+            Layout::new(10, 20, 1, (5, 3), BLOCKS, false, (80, 24)),
+            Err((33, 26))
+        );
+    }
+
+    #[test]
+    fn texts_are_cut_on_narrow_screens() {
+        let l = Layout::new(10, 20, 1, BOX, BLOCKS, false, (40, 24)).unwrap();
+        assert_eq!(l.board.x - 1, (40 - 31) / 2);
+        assert_eq!(l.finesse.right(), 40);
+    }
+}
@@ -0,0 +1,22 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+#![forbid(unsafe_code)]
+
+pub mod bot;
+pub mod config;
+pub mod finesse;
+pub mod fumen;
+pub mod highscore;
+pub mod history;
+pub mod model;
+#[cfg(feature = "music")]
+pub mod music;
+pub mod paths;
+pub mod pieceset;
+pub mod puzzle;
+pub mod registry;
+pub mod save;
+pub mod sound;
+pub mod stats;
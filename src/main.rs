@@ -4,124 +4,284 @@
 
 #![forbid(unsafe_code)]
 
-use pancurses::Window;
+use tetris::config;
+use tetris::finesse;
+use tetris::highscore;
+use tetris::history;
+use tetris::model;
+#[cfg(feature = "music")]
+use tetris::music;
+use tetris::pieceset::PieceSet;
+use tetris::puzzle;
 
-mod model;
+#[cfg(feature = "crossterm")]
+mod benchmark;
+#[cfg(feature = "curses")]
+mod curses;
+mod layout;
+#[cfg(any(test, feature = "crossterm"))]
+mod memory;
+mod render;
+mod scene;
+#[cfg(feature = "crossterm")]
+mod terminal;
+mod theme;
 mod view;
 
-use self::model::Board;
-use self::model::Game;
-use self::model::PieceFactory;
+#[cfg(not(any(feature = "curses", feature = "crossterm")))]
+compile_error!("Build with the curses or the crossterm feature.");
 
-fn left(g: &mut Game) {
-    if g.pos.0 > 0 {
-        g.clear();
-        g.pos.0 -= 1;
-        if !g.fits() {
-            g.pos.0 += 1;
-        }
-        g.draw();
-    }
+use self::puzzle::Puzzle;
+use self::render::Renderer;
+use self::scene::App;
+use self::scene::Mode;
+use self::scene::Scene;
+use self::scene::Settings;
+use self::scene::MIN_HEIGHT;
+use self::scene::MIN_WIDTH;
+
+struct Options {
+    finesse: bool,
+    restart_on_fault: bool,
+    puzzle_menu: bool,
+    puzzle_file: Option<String>,
+    resume: bool,
+    render_music: Option<String>,
+    renderer: Option<String>,
+    redraw_benchmark: bool,
+    export_history: Option<String>,
+    pieces: Option<String>,
+    width: u16,
+    height: u16,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: tetris [--finesse] [--restart-on-fault] [--puzzle | --puzzle-file FILE] [--resume]"
+    );
+    eprintln!("              [--pieces pentominoes|FILE] [--renderer curses|crossterm]");
+    eprintln!("              [--width N] [--height N]");
+    eprintln!("       tetris --render-music FILE.wav");
+    eprintln!("       tetris --redraw-benchmark");
+    eprintln!("       tetris --export-history csv|json");
+    std::process::exit(1);
+}
+
+/// A board's width or height, at least `min`.
+fn board_size(arg: Option<String>, min: u16) -> u16 {
+    arg.and_then(|a| a.parse::<u16>().ok())
+        .filter(|&n| n >= min)
+        .unwrap_or_else(|| usage())
 }
 
-fn right(g: &mut Game) {
-    if g.pos.0 < g.board.width() as i8 - 1 {
-        g.clear();
-        g.pos.0 += 1;
-        if !g.fits() {
-            g.pos.0 -= 1;
+fn parse_args() -> Options {
+    let mut o = Options {
+        finesse: false,
+        restart_on_fault: false,
+        puzzle_menu: false,
+        puzzle_file: None,
+        resume: false,
+        render_music: None,
+        renderer: None,
+        redraw_benchmark: false,
+        export_history: None,
+        pieces: None,
+        width: 10,
+        height: 20,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--finesse" => o.finesse = true,
+            "--restart-on-fault" => {
+                o.finesse = true;
+                o.restart_on_fault = true;
+            }
+            "--puzzle" => o.puzzle_menu = true,
+            "--resume" => o.resume = true,
+            "--puzzle-file" => o.puzzle_file = Some(args.next().unwrap_or_else(|| usage())),
+            "--render-music" => o.render_music = Some(args.next().unwrap_or_else(|| usage())),
+            "--redraw-benchmark" => o.redraw_benchmark = true,
+            "--renderer" => o.renderer = Some(args.next().unwrap_or_else(|| usage())),
+            "--export-history" => o.export_history = Some(args.next().unwrap_or_else(|| usage())),
+            "--pieces" => o.pieces = Some(args.next().unwrap_or_else(|| usage())),
+            "--width" => o.width = board_size(args.next(), MIN_WIDTH),
+            "--height" => o.height = board_size(args.next(), MIN_HEIGHT),
+            _ => usage(),
         }
-        g.draw();
     }
+    if o.resume && (o.puzzle_menu || o.puzzle_file.is_some() || o.pieces.is_some()) {
+        usage();
+    }
+    o
 }
 
-fn up(g: &mut Game) {
-    g.clear();
-    g.turn_piece();
-    if !g.fits() {
-        g.counter_turn_piece();
-    }
-    g.draw();
+fn load_config() -> config::Config {
+    let path = match config::default_path() {
+        Some(p) => p,
+        None => return config::Config::default(),
+    };
+    config::Config::load(&path).unwrap_or_else(|e| {
+        eprintln!("Bad settings in {}: {}", path.display(), e);
+        std::process::exit(1);
+    })
 }
 
-fn down(g: &mut Game) {
-    g.clear();
-    g.pos.1 += 1;
-    if !g.fits() {
-        g.pos.1 -= 1;
-        g.draw();
-        // FIXME:
-        g.piece_stuck();
-    }
-    if !g.game_over {
-        g.draw();
+/// Writes the music to a WAV file, to hear it without a sound card.
+#[cfg(feature = "music")]
+fn render_music(path: &str) {
+    let song = music::Song::korobeiniki();
+    let samples = music::render(&song, music::SAMPLE_RATE, 1.0);
+    if let Err(e) = std::fs::write(path, music::wav(&samples, music::SAMPLE_RATE)) {
+        eprintln!("Can't write {}: {}", path, e);
+        std::process::exit(1);
     }
 }
 
-fn fall(g: &mut Game) {
-    g.clear();
-    while g.fits() {
-        g.pos.1 += 1;
+#[cfg(not(feature = "music"))]
+fn render_music(_path: &str) {
+    eprintln!("Built without the music feature.");
+    std::process::exit(1);
+}
+
+#[cfg(feature = "crossterm")]
+fn redraw_benchmark() {
+    benchmark::redraw();
+}
+
+#[cfg(not(feature = "crossterm"))]
+fn redraw_benchmark() {
+    eprintln!("Built without the crossterm feature.");
+    std::process::exit(1);
+}
+
+/// Prints the finished games as CSV or JSON.
+fn export_history(format: &str) {
+    let path = history::default_path().unwrap_or_else(|| {
+        eprintln!("Can't find the history, is $HOME set?");
+        std::process::exit(1);
+    });
+    let h = history::History::load(&path).unwrap_or_else(|e| {
+        eprintln!("Can't read {}: {}", path.display(), e);
+        std::process::exit(1);
+    });
+    match format {
+        "csv" => print!("{}", h.to_csv()),
+        "json" => print!("{}", h.to_json()),
+        _ => usage(),
     }
-    g.pos.1 -= 1;
-    g.draw();
-    g.piece_stuck();
-    g.draw();
 }
 
-fn game_loop(win: &Window, g: &mut Game) {
-    use std::{thread, time};
-
-    g.draw();
-    let mut now = time::Instant::now();
-    while !g.game_over {
-        match win.getch() {
-            Some(pancurses::Input::Character(c)) => match c {
-                'q' => return,
-                'h' => left(g),
-                'l' => right(g),
-                'k' => up(g),
-                'j' => {
-                    now = time::Instant::now();
-                    down(g)
-                }
-                ' ' => {
-                    now = time::Instant::now();
-                    fall(g)
-                }
-                _ => (),
-            },
-            Some(_) => (),
-            None => {
-                thread::sleep(time::Duration::from_millis(5));
-
-                if now.elapsed() > time::Duration::from_millis(200) {
-                    now = time::Instant::now();
-                    down(g)
-                }
+/// The renderer used when none is asked for.
+#[cfg(feature = "curses")]
+const DEFAULT_RENDERER: &str = "curses";
+#[cfg(not(feature = "curses"))]
+const DEFAULT_RENDERER: &str = "crossterm";
+
+/// Takes over the terminal with the named renderer.
+fn open_renderer(name: &str) -> Box<dyn Renderer> {
+    match name {
+        #[cfg(feature = "curses")]
+        "curses" => Box::new(curses::Curses::new()),
+        #[cfg(feature = "crossterm")]
+        "crossterm" => match terminal::Terminal::new() {
+            Ok(t) => Box::new(t),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
             }
+        },
+        _ => {
+            eprintln!("Built without the {} renderer.", name);
+            std::process::exit(1);
         }
-        view::draw_in_win(g, win);
     }
-    while win.getch() != Some(pancurses::Input::Character('q')) {
-        thread::sleep(time::Duration::from_millis(200));
+}
+
+fn load_puzzle(path: &str) -> Puzzle {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Can't read {}: {}", path, e);
+        std::process::exit(1);
+    });
+    Puzzle::parse(&text).unwrap_or_else(|e| {
+        eprintln!("Bad puzzle {}: {}", path, e);
+        std::process::exit(1);
+    })
+}
+
+/// A bundled piece set by its name, or one from a file.
+fn load_pieces(name: &str) -> PieceSet {
+    if let Some(set) = PieceSet::bundled()
+        .into_iter()
+        .find(|s| s.name.eq_ignore_ascii_case(name))
+    {
+        return set;
     }
+    PieceSet::load(std::path::Path::new(name)).unwrap_or_else(|e| {
+        eprintln!("Bad piece set {}: {}", name, e);
+        std::process::exit(1);
+    })
 }
 
 /// The entry point.
 fn main() {
-    const WIDTH: u8 = 10;
-    const HEIGHT: u8 = 20;
-    let win = view::init(WIDTH, HEIGHT);
+    let options = parse_args();
+    if let Some(path) = &options.render_music {
+        render_music(path);
+        return;
+    }
+    if options.redraw_benchmark {
+        redraw_benchmark();
+        return;
+    }
+    if let Some(format) = &options.export_history {
+        export_history(format);
+        return;
+    }
+    let settings = Settings {
+        mode: if options.finesse {
+            Mode::Finesse
+        } else {
+            Mode::Marathon
+        },
+        restart_on_fault: options.restart_on_fault,
+        width: options.width,
+        height: options.height,
+        pieces: options.pieces.as_deref().map(load_pieces),
+    };
+    let puzzle = options.puzzle_file.as_deref().map(load_puzzle);
+    let config = load_config();
 
-    let piece_factory = PieceFactory::new();
-    let b = Board::new(WIDTH, HEIGHT);
-    let mut game = Game::new(b, piece_factory);
-    win.nodelay(true);
+    let mut app = App::new(
+        open_renderer(options.renderer.as_deref().unwrap_or(DEFAULT_RENDERER)),
+        settings,
+        config,
+    );
+    app.scene = if options.resume {
+        app.resume()
+    } else if puzzle.is_some() {
+        app.start(puzzle)
+    } else if options.puzzle_menu {
+        Scene::Puzzles { selected: 0 }
+    } else if options.finesse {
+        app.start(None)
+    } else {
+        Scene::Title { selected: 0 }
+    };
+    app.draw();
+    app.run();
 
-    game_loop(&win, &mut game);
+    app.screen.end();
 
-    view::end();
+    for e in &app.errors {
+        eprintln!("{}", e);
+    }
+    if let Some(t) = &app.session.trainer {
+        println!("{}", t.report());
+    }
+    for s in &app.session.exported {
+        println!("{}", s);
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,162 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! A renderer that keeps the screen in memory, to see what was drawn
+//! or what changed since the last frame. It has no keyboard.
+
+use std::cell::RefCell;
+
+use crate::render;
+use crate::render::Input;
+use crate::render::Renderer;
+use crate::render::Style;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub ch: char,
+    pub style: Style,
+}
+
+const BLANK: Cell = Cell {
+    ch: ' ',
+    style: render::TEXT,
+};
+
+pub struct Memory {
+    width: i32,
+    height: i32,
+    cells: RefCell<Vec<Cell>>,
+}
+
+impl Memory {
+    pub fn new(width: i32, height: i32) -> Self {
+        Memory {
+            width,
+            height,
+            cells: RefCell::new(vec![BLANK; (width * height) as usize]),
+        }
+    }
+
+    pub fn cell(&self, x: i32, y: i32) -> Cell {
+        self.cells.borrow()[(x + y * self.width) as usize]
+    }
+
+    /// The text on line `y`, without the spaces at its end.
+    #[cfg(test)]
+    pub fn line(&self, y: i32) -> String {
+        let line = (0..self.width)
+            .map(|x| self.cell(x, y).ch)
+            .collect::<String>();
+        line.trim_end().to_string()
+    }
+
+    /// The text on the screen, without the spaces at the ends of the
+    /// lines or the empty lines at the end.
+    #[cfg(test)]
+    pub fn text(&self) -> String {
+        let lines = (0..self.height).map(|y| self.line(y)).collect::<Vec<_>>();
+        lines.join("\n").trim_end().to_string()
+    }
+
+    /// The runs of cells of the same style that differ from `shown`,
+    /// as column, line, text and style. The screens are the same size.
+    pub fn changes(&self, shown: &Memory) -> Vec<(i32, i32, String, Style)> {
+        let mut runs: Vec<(i32, i32, String, Style)> = vec![];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let c = self.cell(x, y);
+                if c == shown.cell(x, y) {
+                    continue;
+                }
+                match runs.last_mut() {
+                    Some((rx, ry, text, style))
+                        if *ry == y
+                            && *style == c.style
+                            && *rx + text.chars().count() as i32 == x =>
+                    {
+                        text.push(c.ch)
+                    }
+                    _ => runs.push((x, y, c.ch.to_string(), c.style)),
+                }
+            }
+        }
+        runs
+    }
+
+    /// Makes this screen show what `other` shows.
+    pub fn copy_from(&self, other: &Memory) {
+        self.cells.borrow_mut().clone_from(&other.cells.borrow());
+    }
+
+    fn set(&self, x: i32, y: i32, cell: Cell) {
+        if (0..self.width).contains(&x) && (0..self.height).contains(&y) {
+            self.cells.borrow_mut()[(x + y * self.width) as usize] = cell;
+        }
+    }
+}
+
+impl Renderer for Memory {
+    fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    fn colours(&self) -> i32 {
+        8
+    }
+
+    fn can_change_colour(&self) -> bool {
+        false
+    }
+
+    fn erase(&self) {
+        for c in self.cells.borrow_mut().iter_mut() {
+            *c = BLANK;
+        }
+    }
+
+    fn put(&self, x: i32, y: i32, s: &str, style: Style) {
+        for (i, ch) in s.chars().enumerate() {
+            self.set(x + i as i32, y, Cell { ch, style });
+        }
+    }
+
+    fn clear_line(&self, x: i32, y: i32) {
+        for x in x..self.width {
+            self.set(x, y, BLANK);
+        }
+    }
+
+    fn present(&self) {}
+
+    fn read_key(&self) -> Option<Input> {
+        None
+    }
+
+    fn end(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::*;
+
+    #[test]
+    fn changes_since_the_last_frame() {
+        let (shown, next) = (Memory::new(10, 3), Memory::new(10, 3));
+        next.put(1, 1, "abc", render::TEXT);
+        next.put(4, 1, "d", render::SELECTED);
+        assert_eq!(
+            next.changes(&shown),
+            vec![
+                (1, 1, "abc".to_string(), render::TEXT),
+                (4, 1, "d".to_string(), render::SELECTED)
+            ]
+        );
+        shown.copy_from(&next);
+        next.put(2, 1, "x", render::TEXT);
+        assert_eq!(
+            next.changes(&shown),
+            vec![(2, 1, "x".to_string(), render::TEXT)]
+        );
+    }
+}
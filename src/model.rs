@@ -2,59 +2,164 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use rand::*;
+use std::num::Wrapping;
+use std::time::Duration;
+
+use crate::stats::Stats;
+
+/// The time between the piece's falls.
+pub const GRAVITY: Duration = Duration::from_millis(200);
+
+/// How long a soft drop keeps the piece falling faster. Terminals don't
+/// tell when a key is released, but repeat it while it's held.
+const SOFT_DROP_HOLD: Duration = Duration::from_millis(150);
+
+/// How fast a soft drop is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SoftDrop {
+    /// Gravity this many times faster.
+    Factor(u32),
+    /// Straight to the bottom, without locking the piece.
+    Sonic,
+}
+
+impl Default for SoftDrop {
+    fn default() -> Self {
+        SoftDrop::Factor(20)
+    }
+}
+
+/// The widest and highest a piece's shape can be.
+pub const MAX_SHAPE: u8 = 5;
 
 #[derive(Clone)]
 pub struct Tetramino {
-    shape: [bool; 16],
+    shape: [bool; 25],
     /// x, y
     offset: (i8, i8),
     /// width, height
     size: (u8, u8),
 }
 
-#[derive(Clone)]
-pub enum PieceType {
-    None,
-    I,
-    J,
-    L,
-    O,
-    S,
-    T,
-    Z,
-}
+/// What fills a cell: a piece's blocks, garbage or nothing, by the
+/// letter it's written as on the board printouts. The seven pieces,
+/// garbage and the empty cell are built in, piece sets add their own
+/// pieces with lowercase letters. How they look is in the
+/// `registry`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PieceType(u8);
 
 #[derive(Clone)]
 pub struct Piece {
     tetraminos: Vec<Tetramino>,
     index: u8,
     pub piece_type: PieceType,
+    /// Where it spawns, from the middle of the top row.
+    pub spawn: (i8, i8),
 }
 
+#[derive(Clone)]
 pub struct Board {
     pub map: Vec<PieceType>,
     /// width, height
-    pub size: (u8, u8),
+    pub size: (u16, u16),
+}
+
+/// A key the player pressed to move a piece.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Move {
+    Left,
+    Right,
+    RotateCw,
+    RotateCcw,
+    Rotate180,
+    SoftDrop,
+    HardDrop,
+}
+
+/// Where a piece got stuck and how it was moved there.
+#[derive(Clone)]
+pub struct Lock {
+    pub piece: Piece,
+    pub pos: (i32, i32),
+    pub moves: Vec<Move>,
+    /// The number of lines it cleared.
+    pub lines: usize,
+    /// The piece was a T that got stuck right after being turned into
+    /// a slot with three of its four corners filled.
+    pub tspin: bool,
+}
+
+/// Something that happened in the game.
+#[derive(Clone)]
+pub enum Event {
+    /// The piece moved sideways or turned.
+    Moved(Move),
+    Locked(Lock),
+    /// The piece was hard dropped from one row to another.
+    HardDropped {
+        piece: Piece,
+        x: i32,
+        from: i32,
+        to: i32,
+    },
+    /// These rows are full and are removed, after the line clear delay.
+    Cleared(Vec<i32>),
+    LevelUp(u16),
+    GameOver,
 }
 
 pub struct Game {
     pub board: Board,
     pub piece: Piece,
     pub next_piece: Piece,
-    piece_factory: PieceFactory,
+    pub(crate) piece_factory: PieceFactory,
     pub score: u32,
     pub lines: u16,
-    pub pos: (i8, i8),
+    pub pos: (i32, i32),
     pub game_over: bool,
+    /// The moves made with the current piece.
+    pub moves: Vec<Move>,
+    /// The last successful move turned the piece.
+    pub(crate) spun: bool,
+    /// Events that has happened since they were last taken.
+    pub events: Vec<Event>,
+    /// The time the game has been played.
+    pub time: Duration,
+    /// How long it takes for the piece to fall one row by itself.
+    pub gravity: Duration,
+    /// The time since the piece last fell.
+    pub(crate) fall_time: Duration,
+    /// Time stands still while paused.
+    pub paused: bool,
+    pub soft_drop_speed: SoftDrop,
+    /// The time left of the current soft drop.
+    pub(crate) soft_dropping: Duration,
+    /// How long full rows stay before they are removed and the next
+    /// piece comes.
+    pub line_clear_delay: Duration,
+    /// The time left before the full rows are removed.
+    pub(crate) clearing: Duration,
+    /// The piece put aside to be used later.
+    pub hold: Option<Piece>,
+    /// The current piece came from, or went to, the hold.
+    pub(crate) held: bool,
+    /// Something that is shown changed since the view cleared it.
+    pub dirty: bool,
+    pub stats: Stats,
 }
 
 impl Tetramino {
     fn is_set(&self, x: i8, y: i8) -> bool {
-        if !(0..=3).contains(&x) || !(0..=3).contains(&y) {
+        let size = MAX_SHAPE as i8;
+        if !(0..size).contains(&x) || !(0..size).contains(&y) {
             return false;
         }
-        self.shape[(x + y * 4) as usize]
+        self.shape[(x + y * size) as usize]
+    }
+    /// The position of the tetramino's origin within its shape.
+    pub fn offset(&self) -> (i8, i8) {
+        self.offset
     }
     fn width(&self) -> u8 {
         self.size.0
@@ -62,16 +167,16 @@ impl Tetramino {
     fn height(&self) -> u8 {
         self.size.1
     }
-    fn new(tmpl: &str, offset_x: i8, offset_y: i8) -> Self {
+    pub(crate) fn new(tmpl: &str, offset_x: i8, offset_y: i8) -> Self {
         let mut s = tmpl.chars();
         let mut height: u8 = 0;
         let mut width: u8 = 0;
-        let mut v = [false; 16];
+        let mut v = [false; 25];
         for y in 1..5 {
             for x in 1..5 {
                 if let Some(c) = s.next() {
                     if c == 'X' {
-                        v[x + y * 4 - 5_usize] = true;
+                        v[x + y * MAX_SHAPE as usize - 6_usize] = true;
                         height = y as u8;
                         if x as u8 > width {
                             width = x as u8;
@@ -86,6 +191,163 @@ impl Tetramino {
             size: (width, height),
         }
     }
+
+    /// Creates a shape from its rows, with X for its blocks and dots
+    /// for the holes, turning around the block at `offset`. It's up to
+    /// `MAX_SHAPE` wide and high, without empty rows or columns at its
+    /// edges.
+    pub fn from_rows(rows: &[&str], offset: (i8, i8)) -> Result<Self, String> {
+        let height = rows.len();
+        let width = rows.iter().map(|r| r.chars().count()).max().unwrap_or(0);
+        if height == 0 || width == 0 {
+            return Err("an empty shape".to_string());
+        }
+        if width > MAX_SHAPE as usize || height > MAX_SHAPE as usize {
+            return Err(format!("a shape larger than {}x{}", MAX_SHAPE, MAX_SHAPE));
+        }
+        let mut v = [false; 25];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                match c {
+                    'X' => v[x + y * MAX_SHAPE as usize] = true,
+                    '.' => (),
+                    _ => return Err(format!("unknown block '{}' in \"{}\"", c, row)),
+                }
+            }
+        }
+        let t = Tetramino {
+            shape: v,
+            offset,
+            size: (width as u8, height as u8),
+        };
+        let used = |x: i8, y: i8| t.is_set(x, y);
+        let (w, h) = (width as i8, height as i8);
+        if !(0..w).any(|x| used(x, 0))
+            || !(0..w).any(|x| used(x, h - 1))
+            || !(0..h).any(|y| used(0, y))
+            || !(0..h).any(|y| used(w - 1, y))
+        {
+            return Err(format!("empty edges in \"{}\"", rows.join("/")));
+        }
+        Ok(t)
+    }
+
+    /// The shape turned clockwise around `centre`, counted in half
+    /// blocks from the middle of the cell at the offset. Even numbers
+    /// are the middle of a block, odd ones the corners between them.
+    fn turned(&self, centre: (i8, i8)) -> Tetramino {
+        let (ox, oy) = self.offset;
+        let (cx, cy) = centre;
+        let blocks = self
+            .blocks()
+            .iter()
+            .map(|&(x, y)| {
+                let (dx, dy) = (2 * (x - ox) - cx, 2 * (y - oy) - cy);
+                ((cx - dy) / 2, (cy + dx) / 2)
+            })
+            .collect::<Vec<_>>();
+        let left = blocks.iter().map(|b| b.0).min().unwrap_or(0);
+        let top = blocks.iter().map(|b| b.1).min().unwrap_or(0);
+        let mut v = [false; 25];
+        let mut size = (0, 0);
+        for (x, y) in blocks {
+            let (x, y) = (x - left, y - top);
+            v[(x + y * MAX_SHAPE as i8) as usize] = true;
+            size = (size.0.max(x as u8 + 1), size.1.max(y as u8 + 1));
+        }
+        Tetramino {
+            shape: v,
+            offset: (-left, -top),
+            size,
+        }
+    }
+
+    /// The shape's rotations, turning it clockwise around `centre` as
+    /// in `turned` until it's back where it started. That's one, two or
+    /// four of them.
+    pub fn rotations(&self, centre: (i8, i8)) -> Result<Vec<Tetramino>, String> {
+        if centre.0 & 1 != centre.1 & 1 {
+            return Err(format!(
+                "centre {:?} is neither a block's middle nor a corner",
+                centre
+            ));
+        }
+        let mut rotations = vec![self.clone()];
+        let mut t = self.turned(centre);
+        while !t.same_as(self) {
+            let next = t.turned(centre);
+            rotations.push(t);
+            t = next;
+        }
+        Ok(rotations)
+    }
+
+    /// Covers the same cells around the offset.
+    fn same_as(&self, other: &Tetramino) -> bool {
+        self.offset == other.offset && self.blocks() == other.blocks()
+    }
+
+    /// The blocks of the shape, from the top left.
+    pub fn blocks(&self) -> Vec<(i8, i8)> {
+        let mut v = vec![];
+        for y in 0..self.height() as i8 {
+            for x in 0..self.width() as i8 {
+                if self.is_set(x, y) {
+                    v.push((x, y));
+                }
+            }
+        }
+        v
+    }
+}
+
+impl PieceType {
+    pub const NONE: PieceType = PieceType(b'.');
+    pub const I: PieceType = PieceType(b'I');
+    pub const J: PieceType = PieceType(b'J');
+    pub const L: PieceType = PieceType(b'L');
+    pub const O: PieceType = PieceType(b'O');
+    pub const S: PieceType = PieceType(b'S');
+    pub const T: PieceType = PieceType(b'T');
+    pub const Z: PieceType = PieceType(b'Z');
+    pub const GARBAGE: PieceType = PieceType(b'X');
+    /// The seven usual pieces.
+    pub const PIECES: [PieceType; 7] = [
+        PieceType::I,
+        PieceType::J,
+        PieceType::L,
+        PieceType::O,
+        PieceType::S,
+        PieceType::T,
+        PieceType::Z,
+    ];
+
+    /// The piece type written as a letter, like on the board printouts.
+    pub fn from_char(c: char) -> Option<PieceType> {
+        match c {
+            '.' | 'I' | 'J' | 'L' | 'O' | 'S' | 'T' | 'Z' | 'X' => Some(PieceType(c as u8)),
+            _ => PieceType::custom(c),
+        }
+    }
+
+    /// A piece of a piece set, written as a lowercase letter.
+    pub fn custom(c: char) -> Option<PieceType> {
+        if c.is_ascii_lowercase() {
+            Some(PieceType(c as u8))
+        } else {
+            None
+        }
+    }
+
+    pub fn to_char(self) -> char {
+        self.0 as char
+    }
+
+    /// One of the seven pieces, garbage or nothing, not from a piece
+    /// set.
+    pub fn is_built_in(self) -> bool {
+        !self.to_char().is_ascii_lowercase()
+    }
 }
 
 impl Piece {
@@ -98,7 +360,19 @@ impl Piece {
             tetraminos: t2,
             index: 0,
             piece_type: pt,
+            spawn: (0, 0),
+        }
+    }
+
+    /// A piece that turns through `rotations`. With only one, the others
+    /// are made by turning it around the block at its offset.
+    pub fn from_rotations(rotations: Vec<Tetramino>, pt: PieceType) -> Self {
+        if rotations.len() == 1 {
+            if let Ok(r) = rotations[0].rotations((0, 0)) {
+                return Piece::new(r, pt);
+            }
         }
+        Piece::new(rotations, pt)
     }
 
     pub fn curr(&self) -> Tetramino {
@@ -120,110 +394,175 @@ impl Piece {
             self.index - 1
         }
     }
+
+    /// The number of distinct rotation states of the piece.
+    pub fn rotations(&self) -> usize {
+        self.tetraminos.len()
+    }
+
+    pub fn rotation(&self) -> usize {
+        self.index as usize
+    }
+
+    pub fn set_rotation(&mut self, index: usize) {
+        self.index = (index % self.tetraminos.len()) as u8;
+    }
+
+    /// The piece turned to its lowest rotation, to show it in little
+    /// room.
+    pub fn flattest(&self) -> Piece {
+        let mut p = self.clone();
+        if let Some(i) = (0..self.rotations()).min_by_key(|i| self.tetraminos[*i].height()) {
+            p.set_rotation(i);
+        }
+        p
+    }
 }
 
 impl Board {
-    pub fn new(width: u8, height: u8) -> Self {
+    pub fn new(width: u16, height: u16) -> Self {
         Board {
-            map: vec![PieceType::None; (width as usize) * height as usize],
+            map: vec![PieceType::NONE; usize::from(width) * usize::from(height)],
             size: (width, height),
         }
     }
 
-    // FIXME: Change to an iterator?
-    fn get_indexes(&self, p: &Piece, x: i8, y: i8) -> Vec<usize> {
-        let mut v = vec![];
-        let curr = p.curr();
-        let width = self.width() as usize;
-        let x = x as usize - curr.offset.0 as usize;
-        let y = y as usize - curr.offset.1 as usize;
-        for y1 in 0..curr.height() {
-            for x1 in 0..curr.width() {
-                if curr.is_set(x1 as i8, y1 as i8) {
-                    let x2 = x1 as usize + x;
-                    let y2 = y1 as usize + y;
-                    v.push(x2 + y2 * width);
-                }
+    /// Creates a board from rows of cells written as in
+    /// `PieceType::from_char`.
+    ///
+    /// The rows are put at the bottom of the board.
+    pub fn from_rows(width: u16, height: u16, rows: &[&str]) -> Result<Self, String> {
+        if rows.len() > usize::from(height) {
+            return Err(format!("more than {} rows", height));
+        }
+        if let Some(row) = rows
+            .iter()
+            .find(|r| r.chars().count() != usize::from(width))
+        {
+            return Err(format!("row \"{}\" isn't {} cells wide", row, width));
+        }
+        let mut b = Board::new(width, height);
+        let top = usize::from(height) - rows.len();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let t = PieceType::from_char(c)
+                    .ok_or_else(|| format!("unknown cell '{}' in row \"{}\"", c, row))?;
+                b.map[x + (top + y) * usize::from(width)] = t;
             }
         }
-        v
+        Ok(b)
     }
 
-    pub fn draw(&mut self, p: &Piece, x: i8, y: i8) {
-        for i in self.get_indexes(p, x, y) {
-            self.map[i] = p.piece_type.clone();
+    /// The index in the map of the cell at x, y, if it's on the board.
+    fn index(&self, x: i64, y: i64) -> Option<usize> {
+        let (width, height) = (i64::from(self.width()), i64::from(self.height()));
+        if (0..width).contains(&x) && (0..height).contains(&y) {
+            Some((x + y * width) as usize)
+        } else {
+            None
         }
     }
 
-    pub fn fits(&mut self, p: &Piece, x: i8, y: i8) -> bool {
-        let c = p.curr();
-        if x < c.offset.0 {
-            return false;
-        }
-        if x + c.width() as i8 - c.offset.0 > self.width() as i8 {
-            return false;
-        }
-        if y < c.offset.1 {
-            return false;
-        }
-        if y + c.height() as i8 - c.offset.1 > self.height() as i8 {
-            return false;
-        }
+    /// The indexes of the cells the piece's blocks are in at x, y, or
+    /// None for those outside the board.
+    fn block_indexes(&self, p: &Piece, x: i32, y: i32) -> Vec<Option<usize>> {
+        let curr = p.curr();
+        let (ox, oy) = curr.offset;
+        curr.blocks()
+            .iter()
+            .map(|&(bx, by)| {
+                self.index(
+                    i64::from(x) + i64::from(bx - ox),
+                    i64::from(y) + i64::from(by - oy),
+                )
+            })
+            .collect()
+    }
+
+    /// The indexes of the cells on the board the piece covers at x, y.
+    pub(crate) fn get_indexes(&self, p: &Piece, x: i32, y: i32) -> Vec<usize> {
+        self.block_indexes(p, x, y).into_iter().flatten().collect()
+    }
+
+    pub fn draw(&mut self, p: &Piece, x: i32, y: i32) {
         for i in self.get_indexes(p, x, y) {
-            match self.map[i] {
-                PieceType::None => (),
-                _ => return false,
-            }
+            self.map[i] = p.piece_type;
         }
-        true
     }
 
-    pub fn clear(&mut self, p: &Piece, x: i8, y: i8) {
+    /// The piece is on the board at x, y without covering any blocks.
+    pub fn fits(&self, p: &Piece, x: i32, y: i32) -> bool {
+        self.block_indexes(p, x, y)
+            .iter()
+            .all(|i| matches!(i, Some(i) if self.map[*i] == PieceType::NONE))
+    }
+
+    pub fn clear(&mut self, p: &Piece, x: i32, y: i32) {
         for i in self.get_indexes(p, x, y) {
-            self.map[i] = PieceType::None;
+            self.map[i] = PieceType::NONE;
         }
     }
 
-    pub fn width(&self) -> u8 {
+    pub fn width(&self) -> u16 {
         self.size.0
     }
 
-    pub fn height(&self) -> u8 {
+    pub fn height(&self) -> u16 {
         self.size.1
     }
 
-    pub fn is_set(&self, x: i8, y: i8) -> bool {
-        !matches!(
-            self.map[x as usize + y as usize * self.width() as usize],
-            PieceType::None
-        )
+    /// What fills the cell at x, y, nothing outside the board.
+    pub fn cell(&self, x: i32, y: i32) -> PieceType {
+        self.index(i64::from(x), i64::from(y))
+            .map_or(PieceType::NONE, |i| self.map[i])
     }
 
-    fn is_line_full(&self, y: i8) -> bool {
-        for x in 0..self.width() {
-            if !self.is_set(x as i8, y) {
-                return false;
-            }
-        }
-        true
+    pub fn is_set(&self, x: i32, y: i32) -> bool {
+        self.cell(x, y) != PieceType::NONE
     }
 
-    fn clear_line(&mut self, y: u8) {
-        let offset = y as usize * self.width() as usize;
-        for x in 0..self.width() {
-            self.map[x as usize + offset] = PieceType::None;
-        }
+    /// The full rows, from the bottom up.
+    pub fn full_lines(&self) -> Vec<i32> {
+        (0..i32::from(self.height()))
+            .rev()
+            .filter(|y| self.is_line_full(*y))
+            .collect()
+    }
+
+    /// The cells the piece covers at x, y.
+    pub fn cells(&self, p: &Piece, x: i32, y: i32) -> Vec<(i32, i32)> {
+        let width = usize::from(self.width());
+        self.get_indexes(p, x, y)
+            .iter()
+            .map(|i| ((i % width) as i32, (i / width) as i32))
+            .collect()
     }
 
-    fn copy_line(&mut self, from_y: i8, to_y: i8) {
-        for x in 0..self.width() {
-            let from_pos = x as usize + from_y as usize * self.width() as usize;
-            let to_pos = x as usize + to_y as usize * self.width() as usize;
-            self.map[to_pos] = self.map[from_pos].clone();
+    /// The cells of row y, which must be on the board.
+    fn row(&self, y: i32) -> std::ops::Range<usize> {
+        let width = usize::from(self.width());
+        let start = y as usize * width;
+        start..start + width
+    }
+
+    fn is_line_full(&self, y: i32) -> bool {
+        let row = &self.map[self.row(y)];
+        !row.is_empty() && row.iter().all(|t| *t != PieceType::NONE)
+    }
+
+    fn clear_line(&mut self, y: i32) {
+        let row = self.row(y);
+        for t in &mut self.map[row] {
+            *t = PieceType::NONE;
         }
     }
 
-    fn scroll_down(&mut self, y: i8) {
+    fn copy_line(&mut self, from_y: i32, to_y: i32) {
+        let (from, to) = (self.row(from_y), self.row(to_y));
+        self.map.copy_within(from, to.start);
+    }
+
+    fn scroll_down(&mut self, y: i32) {
         for y2 in 0..y {
             let ry = y - y2;
             self.copy_line(ry - 1, ry);
@@ -234,7 +573,7 @@ impl Board {
     /// remove_full_lines() removes full lines and scrolls the rest.
     ///
     /// The fn returns the removed lines.
-    pub fn remove_full_lines(&mut self) -> Vec<i8> {
+    pub fn remove_full_lines(&mut self) -> Vec<i32> {
         // RULES: This can be done in two ways:
         // This way, the original tetris way or
         // each unconnected region falls on its own. IE:
@@ -244,8 +583,8 @@ impl Board {
         // With old algorith, one line is removed.
         // with the new, two lines are removed.
         let mut lines = 0;
-        let mut v: Vec<i8> = vec![];
-        let mut ry = self.height() as i8 - 1_i8;
+        let mut v: Vec<i32> = vec![];
+        let mut ry = i32::from(self.height()) - 1;
         while ry >= 0 {
             if self.is_line_full(ry) {
                 v.push(ry - lines);
@@ -261,12 +600,11 @@ impl Board {
     #[cfg(test)]
     pub fn print(&self) {
         println!("Board:");
-        let width = self.width() as usize;
-        for y in 0..self.height() {
+        for row in self.map.chunks(usize::from(self.width())) {
             print!(">");
-            for x in 0..width {
-                let o = match self.map[x + y as usize * width] {
-                    PieceType::None => '.',
+            for t in row {
+                let o = match *t {
+                    PieceType::NONE => '.',
                     _ => 'X',
                 };
                 print!("{}", o);
@@ -276,83 +614,233 @@ impl Board {
     }
 }
 
-fn get_pieces() -> Vec<Piece> {
-    vec![
-        Piece::new(vec![Tetramino::new("XX..XX..", 1, 1)], PieceType::O),
-        Piece::new(
-            vec![
-                Tetramino::new("XXXX", 2, 0),
-                Tetramino::new("X...X...X...X", 0, 2),
-            ],
-            PieceType::I,
-        ),
-        Piece::new(
-            vec![
-                Tetramino::new("XX...XX", 1, 1),
-                Tetramino::new(".X..XX..X", 1, 1),
-            ],
-            PieceType::S,
-        ),
-        Piece::new(
-            vec![
-                Tetramino::new(".XX.XX", 1, 1),
-                Tetramino::new("X...XX...X", 1, 1),
-            ],
-            PieceType::Z,
-        ),
-        Piece::new(
-            vec![
-                Tetramino::new("XXX..X..", 1, 1),
-                Tetramino::new(".X..XX...X", 1, 1),
-                Tetramino::new(".X..XXX", 1, 1),
-                Tetramino::new("X...XX..X", 1, 1),
-            ],
-            PieceType::T,
-        ),
-        Piece::new(
-            vec![
-                Tetramino::new("X...X...XX", 1, 1),
-                Tetramino::new("XXX.X", 1, 1),
-                Tetramino::new("XX...X...X", 1, 1),
-                Tetramino::new("..X.XXX", 1, 1),
-            ],
-            PieceType::L,
-        ),
-        Piece::new(
-            vec![
-                Tetramino::new(".X...X..XX", 1, 1),
-                Tetramino::new("X...XXX", 1, 1),
-                Tetramino::new("XX..X...X", 1, 1),
-                Tetramino::new("XXX...X", 1, 1),
-            ],
-            PieceType::J,
-        ),
-    ]
+/// A piece's shape and offset as it spawns, as in `Tetramino::new`,
+/// the centre it turns around, as in `Tetramino::turned`, and the SRS
+/// rotation it spawns in, 0 to 3 for 0, R, 2 and L.
+type Shape = (&'static str, (i8, i8), (i8, i8), PieceType, usize);
+
+/// The seven pieces as they spawn. They turn around the centres of SRS:
+/// the middle of the three blocks in a row for J, L, S, T and Z, and a
+/// corner for I and O. Not all spawn as in SRS: the T points down, the
+/// L and J stand up and the I lies in its lower middle row.
+const SHAPES: [Shape; 7] = [
+    ("XX..XX..", (1, 1), (-1, -1), PieceType::O, 0),
+    ("XXXX", (2, 0), (-1, -1), PieceType::I, 2),
+    (".XX.XX", (1, 1), (0, 0), PieceType::S, 0),
+    ("XX...XX", (1, 1), (0, 0), PieceType::Z, 0),
+    ("XXX..X..", (1, 1), (0, -2), PieceType::T, 2),
+    ("X...X...XX", (1, 1), (-2, 0), PieceType::L, 1),
+    (".X...X..XX", (1, 1), (0, 0), PieceType::J, 3),
+];
+
+pub(crate) fn get_pieces() -> Vec<Piece> {
+    SHAPES
+        .iter()
+        .map(|&(tmpl, (x, y), centre, t, _)| {
+            let rotations = Tetramino::new(tmpl, x, y)
+                .rotations(centre)
+                .expect("a centre on a block or a corner");
+            Piece::new(rotations, t)
+        })
+        .collect()
+}
+
+/// The SRS wall kicks, by the SRS rotation turned from, with y growing
+/// downwards. Clockwise, then counter-clockwise.
+const KICKS: [[[(i8, i8); 5]; 4]; 2] = [
+    [
+        [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+        [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+        [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+        [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    ],
+    [
+        [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+        [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+        [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+        [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    ],
+];
+
+/// The I's SRS kicks, as above.
+const I_KICKS: [[[(i8, i8); 5]; 4]; 2] = [
+    [
+        [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
+        [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+        [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],
+        [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+    ],
+    [
+        [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+        [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],
+        [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+        [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
+    ],
+];
+
+/// The kicks for half turns, from the SRS+ rotation system.
+const KICKS_180: [[(i8, i8); 6]; 4] = [
+    [(0, 0), (0, -1), (1, -1), (-1, -1), (1, 0), (-1, 0)],
+    [(0, 0), (1, 0), (1, -2), (1, -1), (0, -2), (0, -1)],
+    [(0, 0), (0, 1), (-1, 1), (1, 1), (-1, 0), (1, 0)],
+    [(0, 0), (-1, 0), (-1, -2), (-1, -1), (0, -2), (0, -1)],
+];
+
+/// The places to try, in order, when the piece is turned clockwise
+/// `turns` times from its current rotation.
+fn kicks(piece: &Piece, turns: usize) -> &'static [(i8, i8)] {
+    let spawn = SHAPES
+        .iter()
+        .find(|s| s.3 == piece.piece_type)
+        .map_or(0, |s| s.4);
+    let from = (spawn + piece.rotation()) % 4;
+    let direction = if turns == 3 { 1 } else { 0 };
+    match (piece.rotations(), turns) {
+        (4, 2) => &KICKS_180[from],
+        (4, _) if piece.piece_type == PieceType::I => &I_KICKS[direction][from],
+        (4, _) => &KICKS[direction][from],
+        (2, 1) | (2, 3) => &KICKS[direction][from],
+        _ => &[(0, 0)],
+    }
+}
+
+/// A small random number generator (SplitMix64).
+///
+/// Unlike the ones from rand, its whole state is one number, so it can
+/// be saved and it deals the same pieces in every version.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Random {
+    pub state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut z = Wrapping(self.state) + Wrapping(0x9e37_79b9_7f4a_7c15);
+        self.state = z.0;
+        z = (z ^ (z >> 30)) * Wrapping(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)) * Wrapping(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)).0
+    }
+
+    /// A number in 0..n.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
 }
 
 pub struct PieceFactory {
     pieces: Vec<Piece>,
+    pub(crate) rng: Random,
+    /// The state the random pieces started from.
+    pub(crate) seed: u64,
+    /// If not empty, the pieces are dealt in this order, over and over.
+    pub(crate) sequence: Vec<PieceType>,
+    pub(crate) dealt: usize,
 }
 
 impl PieceFactory {
     pub fn new() -> PieceFactory {
+        PieceFactory::with_seed(rand::random())
+    }
+
+    /// A factory that always deals the same pieces for the same seed.
+    pub fn with_seed(seed: u64) -> PieceFactory {
         PieceFactory {
             pieces: get_pieces(),
+            rng: Random::new(seed),
+            seed,
+            sequence: vec![],
+            dealt: 0,
+        }
+    }
+
+    /// A factory that deals other pieces than the usual seven, for
+    /// the same seed in the same order.
+    pub fn with_pieces(pieces: Vec<Piece>, seed: u64) -> PieceFactory {
+        PieceFactory {
+            pieces,
+            ..PieceFactory::with_seed(seed)
         }
     }
 
-    pub fn next(&self) -> Piece {
-        let i = rand::thread_rng().gen_range(0..self.pieces.len());
+    /// The types of the pieces it deals.
+    pub fn types(&self) -> Vec<PieceType> {
+        self.pieces.iter().map(|p| p.piece_type).collect()
+    }
+
+    /// The size of a box that shows any of the pieces in their first
+    /// and their flattest rotation, with their origin in its middle,
+    /// and how many rows they are at most in their flattest.
+    pub fn preview_size(&self) -> (u8, u8) {
+        let fits = |size: u8| {
+            let middle = (size / 2) as i8;
+            self.pieces.iter().all(|p| {
+                [p.curr(), p.flattest().curr()].iter().all(|t| {
+                    t.offset.0 <= middle
+                        && t.offset.1 <= middle
+                        && middle - t.offset.0 + t.width() as i8 <= size as i8
+                        && middle - t.offset.1 + t.height() as i8 <= size as i8
+                })
+            })
+        };
+        let size = (4..2 * MAX_SHAPE)
+            .find(|s| fits(*s))
+            .unwrap_or(2 * MAX_SHAPE);
+        let rows = self.pieces.iter().map(|p| p.flattest().curr().height());
+        (size, rows.max().unwrap_or(1))
+    }
+
+    /// A factory that deals the given pieces in order.
+    pub fn from_sequence(sequence: Vec<PieceType>) -> PieceFactory {
+        PieceFactory {
+            sequence,
+            ..PieceFactory::new()
+        }
+    }
+
+    /// Returns the piece of the given type.
+    pub fn piece(&self, t: PieceType) -> Option<Piece> {
+        self.pieces.iter().find(|p| p.piece_type == t).cloned()
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Piece {
+        if !self.sequence.is_empty() {
+            let t = self.sequence[self.dealt % self.sequence.len()];
+            self.dealt += 1;
+            if let Some(p) = self.piece(t) {
+                return p;
+            }
+        }
+        let i = self.rng.below(self.pieces.len());
         let p = &self.pieces[i];
         p.clone()
     }
 }
 
+impl Default for PieceFactory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where a piece comes on the board.
+pub fn spawn_pos(board: &Board, p: &Piece) -> (i32, i32) {
+    (
+        i32::from(board.width()) / 2 + i32::from(p.spawn.0),
+        i32::from(p.curr().offset.1) + i32::from(p.spawn.1),
+    )
+}
+
 impl Game {
-    pub fn new(board: Board, piece_factory: PieceFactory) -> Game {
-        let w = board.width() as i8;
+    pub fn new(board: Board, mut piece_factory: PieceFactory) -> Game {
         let p = piece_factory.next();
-        let h = p.curr().offset.1;
+        let pos = spawn_pos(&board, &p);
+        let stats = Stats::new(piece_factory.types());
         Game {
             board,
             piece: p,
@@ -360,13 +848,112 @@ impl Game {
             piece_factory,
             score: 0,
             lines: 0,
-            pos: (w / 2, h),
+            pos,
             game_over: false,
+            moves: vec![],
+            spun: false,
+            events: vec![],
+            time: Duration::ZERO,
+            gravity: GRAVITY,
+            fall_time: Duration::ZERO,
+            paused: false,
+            soft_drop_speed: SoftDrop::default(),
+            soft_dropping: Duration::ZERO,
+            line_clear_delay: Duration::ZERO,
+            clearing: Duration::ZERO,
+            hold: None,
+            held: false,
+            dirty: true,
+            stats,
         }
     }
 
+    /// The seed the game's pieces came from, the same seed deals the
+    /// same pieces.
+    pub fn seed(&self) -> u64 {
+        self.piece_factory.seed
+    }
+
+    /// The size of the boxes the next and held pieces are shown in,
+    /// see `PieceFactory::preview_size`.
+    pub fn preview_size(&self) -> (u8, u8) {
+        self.piece_factory.preview_size()
+    }
+
+    /// Lets time pass in the game, the piece falls when it's time.
+    pub fn tick(&mut self, dt: Duration) {
+        if self.game_over || self.paused {
+            return;
+        }
+        // The time is shown in whole seconds.
+        if (self.time + dt).as_secs() != self.time.as_secs() {
+            self.dirty = true;
+        }
+        self.time += dt;
+        if self.is_clearing() {
+            self.clearing = self.clearing.saturating_sub(dt);
+            if !self.is_clearing() {
+                self.finish_clearing();
+            }
+            return;
+        }
+        self.fall_time += dt;
+        let gravity = match self.soft_drop_speed {
+            SoftDrop::Factor(f) if self.soft_dropping > Duration::ZERO => self.gravity / f.max(1),
+            _ => self.gravity,
+        };
+        if self.fall_time >= gravity {
+            self.fall_time = Duration::ZERO;
+            if self.soft_dropping == Duration::ZERO {
+                self.down();
+            } else if self.drop_row() {
+                self.score += 1;
+            } else {
+                self.piece_stuck();
+            }
+        }
+        self.soft_dropping = self.soft_dropping.saturating_sub(dt);
+    }
+
+    /// Full rows are waiting to be removed, nothing can be moved.
+    pub fn is_clearing(&self) -> bool {
+        self.clearing > Duration::ZERO
+    }
+
+    /// Removes the full rows and brings the next piece without waiting
+    /// for the line clear delay.
+    pub fn finish_clearing(&mut self) {
+        self.clearing = Duration::ZERO;
+        self.board.remove_full_lines();
+        self.next_piece();
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// The level, one more every ten lines.
+    pub fn level(&self) -> u16 {
+        self.lines / 10 + 1
+    }
+
+    /// How many rows high the blocks are stacked, without the falling
+    /// piece.
+    pub fn stack_height(&self) -> u16 {
+        let piece = self.board.cells(&self.piece, self.pos.0, self.pos.1);
+        let width = i32::from(self.board.width());
+        (0..i32::from(self.board.height()))
+            .find(|y| (0..width).any(|x| self.board.is_set(x, *y) && !piece.contains(&(x, *y))))
+            .map_or(0, |y| self.board.height() - y as u16)
+    }
+
     pub fn draw(&mut self) {
         self.board.draw(&self.piece, self.pos.0, self.pos.1);
+        self.dirty = true;
     }
 
     pub fn clear(&mut self) {
@@ -385,23 +972,301 @@ impl Game {
         self.piece.prev();
     }
 
-    pub fn piece_stuck(&mut self) -> Vec<i8> {
-        let v = self.board.remove_full_lines();
+    pub fn left(&mut self) {
+        if self.is_clearing() {
+            return;
+        }
+        self.moves.push(Move::Left);
+        self.clear();
+        self.pos.0 -= 1;
+        if self.fits() {
+            self.spun = false;
+            self.events.push(Event::Moved(Move::Left));
+        } else {
+            self.pos.0 += 1;
+        }
+        self.draw();
+    }
+
+    pub fn right(&mut self) {
+        if self.is_clearing() {
+            return;
+        }
+        self.moves.push(Move::Right);
+        self.clear();
+        self.pos.0 += 1;
+        if self.fits() {
+            self.spun = false;
+            self.events.push(Event::Moved(Move::Right));
+        } else {
+            self.pos.0 -= 1;
+        }
+        self.draw();
+    }
+
+    /// Turns the piece clockwise.
+    pub fn up(&mut self) {
+        self.rotate(Move::RotateCw, 1);
+    }
+
+    pub fn rotate_ccw(&mut self) {
+        self.rotate(Move::RotateCcw, 3);
+    }
+
+    pub fn rotate_180(&mut self) {
+        self.rotate(Move::Rotate180, 2);
+    }
+
+    /// Turns the piece clockwise `turns` times, kicking it to the first
+    /// place it fits, if there is any.
+    fn rotate(&mut self, m: Move, turns: usize) {
+        if self.is_clearing() {
+            return;
+        }
+        self.moves.push(m);
+        self.clear();
+        let kicks = kicks(&self.piece, turns);
+        for _ in 0..turns {
+            self.turn_piece();
+        }
+        let pos = self.pos;
+        let mut fits = false;
+        for (dx, dy) in kicks {
+            self.pos = (pos.0 + i32::from(*dx), pos.1 + i32::from(*dy));
+            if self.fits() {
+                fits = true;
+                break;
+            }
+        }
+        if fits {
+            self.spun = true;
+            self.events.push(Event::Moved(m));
+        } else {
+            self.pos = pos;
+            for _ in 0..turns {
+                self.counter_turn_piece();
+            }
+        }
+        self.draw();
+    }
+
+    /// Puts the piece in the hold and takes out the one that was
+    /// there, or the next one. Only once per piece.
+    pub fn hold(&mut self) {
+        if self.held || self.game_over || self.is_clearing() {
+            return;
+        }
+        self.stats.keys += self.moves.len() as u32 + 1;
+        self.clear();
+        let mut piece = self.piece.clone();
+        piece.set_rotation(0);
+        self.piece = match self.hold.replace(piece) {
+            Some(p) => p,
+            None => {
+                let p = self.next_piece.clone();
+                self.next_piece = self.piece_factory.next();
+                p
+            }
+        };
+        self.held = true;
+        self.spawn();
+    }
+
+    /// Puts the current piece at the top.
+    fn spawn(&mut self) {
+        self.moves.clear();
+        self.spun = false;
+        self.fall_time = Duration::ZERO;
+        self.pos = spawn_pos(&self.board, &self.piece);
+        if self.fits() {
+            self.draw();
+        } else {
+            self.game_over = true;
+        }
+    }
+
+    /// Moves the piece one step down, or locks it if it can't move.
+    pub fn down(&mut self) {
+        if self.is_clearing() {
+            return;
+        }
+        if !self.drop_row() {
+            self.piece_stuck();
+        }
+    }
+
+    /// Moves the piece one step down, if it fits.
+    fn drop_row(&mut self) -> bool {
+        self.clear();
+        self.pos.1 += 1;
+        let fits = self.fits();
+        if fits {
+            self.spun = false;
+        } else {
+            self.pos.1 -= 1;
+        }
+        self.draw();
+        fits
+    }
+
+    /// Moves the piece as far down as it goes, without locking it.
+    /// Returns the number of rows.
+    fn drop_to_bottom(&mut self) -> u32 {
+        let mut rows = 0;
+        while self.drop_row() {
+            rows += 1;
+        }
+        rows
+    }
+
+    /// Moves the piece down because the player asked for it, a point
+    /// per row. It keeps falling faster for a while, or all the way
+    /// down at once.
+    pub fn soft_drop(&mut self) {
+        if self.is_clearing() {
+            return;
+        }
+        self.moves.push(Move::SoftDrop);
+        self.fall_time = Duration::ZERO;
+        match self.soft_drop_speed {
+            SoftDrop::Sonic => self.score += self.drop_to_bottom(),
+            SoftDrop::Factor(_) => {
+                self.soft_dropping = SOFT_DROP_HOLD;
+                if self.drop_row() {
+                    self.score += 1;
+                } else {
+                    self.piece_stuck();
+                }
+            }
+        }
+    }
+
+    /// Drops the piece to the bottom and locks it, two points per row.
+    pub fn fall(&mut self) {
+        if self.is_clearing() {
+            return;
+        }
+        self.moves.push(Move::HardDrop);
+        self.fall_time = Duration::ZERO;
+        let from = self.pos.1;
+        let rows = self.drop_to_bottom();
+        self.score += 2 * rows;
+        if rows > 0 {
+            self.events.push(Event::HardDropped {
+                piece: self.piece.clone(),
+                x: self.pos.0,
+                from,
+                to: self.pos.1,
+            });
+        }
+        self.piece_stuck();
+    }
+
+    /// Drops the current piece straight down from column `x` in the
+    /// given rotation.
+    ///
+    /// Returns false, leaving the piece where it was, if the piece
+    /// doesn't fit there.
+    pub fn place(&mut self, rotation: usize, x: i32) -> bool {
+        self.clear();
+        let (old_rotation, old_pos) = (self.piece.rotation(), self.pos);
+        self.piece.set_rotation(rotation);
+        self.pos.0 = x;
+        self.pos.1 = self.pos.1.max(i32::from(self.piece.curr().offset.1));
+        if !self.fits() {
+            self.piece.set_rotation(old_rotation);
+            self.pos = old_pos;
+            self.draw();
+            return false;
+        }
+        self.fall();
+        true
+    }
+
+    /// Checks if the current, drawn, piece is a T in a T-spin position.
+    fn is_tspin(&self) -> bool {
+        if !self.spun || self.piece.piece_type != PieceType::T {
+            return false;
+        }
+        let cells = self.board.cells(&self.piece, self.pos.0, self.pos.1);
+        // The centre is the cell next to the three others.
+        let centre = cells.iter().find(|(x, y)| {
+            cells
+                .iter()
+                .filter(|(x2, y2)| (x - x2).abs() + (y - y2).abs() == 1)
+                .count()
+                == 3
+        });
+        let (x, y) = match centre {
+            Some(c) => *c,
+            None => return false,
+        };
+        let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)]
+            .iter()
+            .filter(|(dx, dy)| {
+                let (cx, cy) = (x + dx, y + dy);
+                cx < 0
+                    || cy < 0
+                    || cx >= i32::from(self.board.width())
+                    || cy >= i32::from(self.board.height())
+                    || self.board.is_set(cx, cy)
+            })
+            .count();
+        corners >= 3
+    }
+
+    /// Locks the piece where it is. Full rows are removed, at once or
+    /// after the line clear delay, and the next piece comes.
+    ///
+    /// The fn returns the full rows.
+    pub fn piece_stuck(&mut self) -> Vec<i32> {
+        self.dirty = true;
+        let tspin = self.is_tspin();
+        let v = self.board.full_lines();
+        let level = self.level();
         self.lines += v.len() as u16;
         self.score += 1 + 10 * ((v.len() * v.len()) as u32);
+        let lock = Lock {
+            piece: self.piece.clone(),
+            pos: self.pos,
+            moves: std::mem::take(&mut self.moves),
+            lines: v.len(),
+            tspin,
+        };
+        self.stats.on_lock(&lock, self.board.width());
+        self.events.push(Event::Locked(lock));
+        if !v.is_empty() {
+            self.events.push(Event::Cleared(v.clone()));
+        }
+        if self.level() > level {
+            self.events.push(Event::LevelUp(self.level()));
+        }
+        self.held = false;
+        if !v.is_empty() && self.line_clear_delay > Duration::ZERO {
+            self.clearing = self.line_clear_delay;
+        } else {
+            self.board.remove_full_lines();
+            self.next_piece();
+        }
+        v
+    }
+
+    fn next_piece(&mut self) {
         self.piece = self.next_piece.clone();
         self.next_piece = self.piece_factory.next();
-        self.pos = (self.board.width() as i8 / 2_i8, self.piece.curr().offset.1);
-        if !self.fits() {
-            self.game_over = true;
+        self.spawn();
+        if self.game_over {
+            self.events.push(Event::GameOver);
         }
-        v
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use crate::model::Board;
+    use crate::model::Event;
     use crate::model::Piece;
     use crate::model::Tetramino;
 
@@ -427,7 +1292,7 @@ mod tests {
 
     fn assert_empty_line(b: &Board, y: i32) {
         for x in 0..b.width() {
-            assert!(!b.is_set(x as i8, y as i8));
+            assert!(!b.is_set(i32::from(x), y));
         }
     }
 
@@ -473,6 +1338,265 @@ mod tests {
         assert_eq!(i.width(), 4);
     }
 
+    #[test]
+    fn turned_shapes_match_the_rotations() {
+        let t = Tetramino::from_rows(&["XXX", ".X."], (1, 0)).unwrap();
+        let turned = t.turned((0, 0));
+        assert_eq!(turned.blocks(), vec![(1, 0), (0, 1), (1, 1), (1, 2)]);
+        assert_eq!(turned.offset, (1, 1));
+        let t = Piece::from_rotations(vec![t], crate::model::PieceType::custom('t').unwrap());
+        assert_eq!(t.rotations(), 4);
+        let i = Tetramino::from_rows(&["XXX"], (1, 0)).unwrap();
+        let i = Piece::from_rotations(vec![i], crate::model::PieceType::custom('i').unwrap());
+        assert_eq!(i.rotations(), 2);
+        assert_eq!(i.flattest().curr().height(), 1);
+        assert_eq!(
+            get_i().rotations((0, 1)).err(),
+            Some("centre (0, 1) is neither a block's middle nor a corner".to_string())
+        );
+    }
+
+    type SrsPiece = (crate::model::PieceType, usize, [[(i32, i32); 4]; 4]);
+
+    /// The pieces' rotations 0, R, 2 and L in their boxes as in SRS, with
+    /// y growing downwards, and the one each spawns in here.
+    const SRS: [SrsPiece; 7] = [
+        (
+            crate::model::PieceType::O,
+            0,
+            [
+                [(1, 0), (2, 0), (1, 1), (2, 1)],
+                [(1, 0), (2, 0), (1, 1), (2, 1)],
+                [(1, 0), (2, 0), (1, 1), (2, 1)],
+                [(1, 0), (2, 0), (1, 1), (2, 1)],
+            ],
+        ),
+        (
+            crate::model::PieceType::I,
+            2,
+            [
+                [(0, 1), (1, 1), (2, 1), (3, 1)],
+                [(2, 0), (2, 1), (2, 2), (2, 3)],
+                [(0, 2), (1, 2), (2, 2), (3, 2)],
+                [(1, 0), (1, 1), (1, 2), (1, 3)],
+            ],
+        ),
+        (
+            crate::model::PieceType::S,
+            0,
+            [
+                [(1, 0), (2, 0), (0, 1), (1, 1)],
+                [(1, 0), (1, 1), (2, 1), (2, 2)],
+                [(1, 1), (2, 1), (0, 2), (1, 2)],
+                [(0, 0), (0, 1), (1, 1), (1, 2)],
+            ],
+        ),
+        (
+            crate::model::PieceType::Z,
+            0,
+            [
+                [(0, 0), (1, 0), (1, 1), (2, 1)],
+                [(2, 0), (1, 1), (2, 1), (1, 2)],
+                [(0, 1), (1, 1), (1, 2), (2, 2)],
+                [(1, 0), (0, 1), (1, 1), (0, 2)],
+            ],
+        ),
+        (
+            crate::model::PieceType::T,
+            2,
+            [
+                [(1, 0), (0, 1), (1, 1), (2, 1)],
+                [(1, 0), (1, 1), (2, 1), (1, 2)],
+                [(0, 1), (1, 1), (2, 1), (1, 2)],
+                [(1, 0), (0, 1), (1, 1), (1, 2)],
+            ],
+        ),
+        (
+            crate::model::PieceType::L,
+            1,
+            [
+                [(2, 0), (0, 1), (1, 1), (2, 1)],
+                [(1, 0), (1, 1), (1, 2), (2, 2)],
+                [(0, 1), (1, 1), (2, 1), (0, 2)],
+                [(0, 0), (1, 0), (1, 1), (1, 2)],
+            ],
+        ),
+        (
+            crate::model::PieceType::J,
+            3,
+            [
+                [(0, 0), (0, 1), (1, 1), (2, 1)],
+                [(1, 0), (2, 0), (1, 1), (1, 2)],
+                [(0, 1), (1, 1), (2, 1), (2, 2)],
+                [(1, 0), (1, 1), (0, 2), (1, 2)],
+            ],
+        ),
+    ];
+
+    /// The cells of a piece in SRS rotation `state`, with its box's top
+    /// left corner at x, y.
+    fn srs_cells(t: crate::model::PieceType, state: usize, x: i32, y: i32) -> Vec<(i32, i32)> {
+        let srs = SRS.iter().find(|s| s.0 == t).unwrap();
+        let mut cells = srs.2[state]
+            .iter()
+            .map(|&(cx, cy)| (x + cx, y + cy))
+            .collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    }
+
+    fn sorted_cells(b: &Board, p: &Piece, x: i32, y: i32) -> Vec<(i32, i32)> {
+        let mut cells = b.cells(p, x, y);
+        cells.sort_unstable();
+        cells
+    }
+
+    #[test]
+    fn built_in_pieces_turn_as_in_srs() {
+        let b = Board::new(10, 10);
+        for mut p in crate::model::get_pieces() {
+            let t = p.piece_type;
+            let spawn = SRS.iter().find(|s| s.0 == t).unwrap().1;
+            let n = if t == crate::model::PieceType::O {
+                1
+            } else {
+                4
+            };
+            assert_eq!(p.rotations(), n);
+            // The SRS box is in the same place in every rotation.
+            let (x, y) = (4, 4);
+            let mut corners = vec![];
+            for r in 0..n {
+                p.set_rotation(r);
+                let cells = sorted_cells(&b, &p, x, y);
+                let srs = srs_cells(t, (spawn + r) % 4, 0, 0);
+                let corner = (cells[0].0 - srs[0].0, cells[0].1 - srs[0].1);
+                assert_eq!(cells, srs_cells(t, (spawn + r) % 4, corner.0, corner.1));
+                corners.push(corner);
+            }
+            corners.dedup();
+            assert_eq!(corners.len(), 1, "{:?}", t);
+        }
+    }
+
+    /// SRS's kicks as they are usually written, with y growing upwards:
+    /// the rotations turned from and to, 0 to 3 for 0, R, 2 and L, and
+    /// the places to try.
+    type SrsKicks = [(usize, usize, [(i32, i32); 5]); 8];
+
+    const SRS_KICKS: SrsKicks = [
+        (0, 1, [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)]),
+        (1, 0, [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)]),
+        (1, 2, [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)]),
+        (2, 1, [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)]),
+        (2, 3, [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)]),
+        (3, 2, [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)]),
+        (3, 0, [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)]),
+        (0, 3, [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)]),
+    ];
+
+    const SRS_I_KICKS: SrsKicks = [
+        (0, 1, [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)]),
+        (1, 0, [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)]),
+        (1, 2, [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)]),
+        (2, 1, [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)]),
+        (2, 3, [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)]),
+        (3, 2, [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)]),
+        (3, 0, [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)]),
+        (0, 3, [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)]),
+    ];
+
+    #[test]
+    fn kicks_are_srs() {
+        for piece in crate::model::get_pieces() {
+            let t = piece.piece_type;
+            let table = match t {
+                crate::model::PieceType::O => continue,
+                crate::model::PieceType::I => &SRS_I_KICKS,
+                _ => &SRS_KICKS,
+            };
+            let spawn = SRS.iter().find(|s| s.0 == t).unwrap().1;
+            // The box's corner before the turn.
+            let (x, y) = (3, 8);
+            let kicked = |state: usize, (dx, dy): (i32, i32)| srs_cells(t, state, x + dx, y - dy);
+            for &(from, to, kicks) in table.iter() {
+                // Block all but the piece and where the kick takes it,
+                // so that kick or an earlier one that fits is used.
+                for kick in kicks.iter() {
+                    let start = kicked(from, (0, 0));
+                    let mut free = start.clone();
+                    free.extend(kicked(to, *kick));
+                    let expected = kicks
+                        .iter()
+                        .map(|&k| kicked(to, k))
+                        .find(|cells| cells.iter().all(|c| free.contains(c)))
+                        .unwrap();
+
+                    let mut g = t_game();
+                    g.board = Board::new(10, 20);
+                    for (i, cell) in g.board.map.iter_mut().enumerate() {
+                        if !free.contains(&((i % 10) as i32, (i / 10) as i32)) {
+                            *cell = crate::model::PieceType::GARBAGE;
+                        }
+                    }
+                    g.piece = piece.clone();
+                    g.piece.set_rotation((from + 4 - spawn) % 4);
+                    g.pos = (-4..14)
+                        .flat_map(|x| (-4..24).map(move |y| (x, y)))
+                        .find(|&(x, y)| sorted_cells(&g.board, &g.piece, x, y) == start)
+                        .unwrap();
+                    g.draw();
+                    if to == (from + 1) % 4 {
+                        g.up();
+                    } else {
+                        g.rotate_ccw();
+                    }
+                    assert_eq!(
+                        sorted_cells(&g.board, &g.piece, g.pos.0, g.pos.1),
+                        expected,
+                        "{:?} from {} to {} kicked {:?}",
+                        t,
+                        from,
+                        to,
+                        kick
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_i_turns_around_a_corner() {
+        let i = get_i_piece();
+        let cells = |r: usize| {
+            let mut p = i.clone();
+            p.set_rotation(r);
+            Board::new(10, 10).cells(&p, 4, 4)
+        };
+        assert_eq!(cells(0), vec![(2, 4), (3, 4), (4, 4), (5, 4)]);
+        assert_eq!(cells(1), vec![(3, 2), (3, 3), (3, 4), (3, 5)]);
+        assert_eq!(cells(2), vec![(2, 3), (3, 3), (4, 3), (5, 3)]);
+        assert_eq!(cells(3), vec![(4, 2), (4, 3), (4, 4), (4, 5)]);
+    }
+
+    #[test]
+    fn held_pieces_fit_the_preview() {
+        let f = crate::model::PieceFactory::new();
+        assert_eq!(f.preview_size(), (4, 2));
+        let l = f.piece(crate::model::PieceType::L).unwrap();
+        assert_eq!(l.curr().height(), 3);
+        let mut b = Board::new(4, 2);
+        let flat = l.flattest();
+        b.draw(&flat, 2, i32::from(flat.curr().offset.1));
+        assert_eq!(
+            b.map
+                .iter()
+                .filter(|t| **t != crate::model::PieceType::NONE)
+                .count(),
+            4
+        );
+    }
+
     #[test]
     fn tet_offset() {
         let i = get_i();
@@ -492,15 +1616,15 @@ mod tests {
             assert_empty_line(&b, i32::from(y));
         }
         for x in 0..3 {
-            assert!(!b.is_set(x as i8, 0_i8));
+            assert!(!b.is_set(x, 0));
         }
         for x in 3..7 {
-            assert!(b.is_set(x as i8, 0_i8));
+            assert!(b.is_set(x, 0));
         }
         for x in 7..b.width() {
-            assert!(!b.is_set(x as i8, 0_i8));
+            assert!(!b.is_set(x as i32, 0_i32));
         }
-        b.clear(&i, 5_i8, 0_i8);
+        b.clear(&i, 5_i32, 0_i32);
         for y in 0..b.height() {
             assert_empty_line(&b, i32::from(y));
         }
@@ -514,7 +1638,7 @@ mod tests {
         b.draw(&i, 2, 7);
         b.draw(&i, 2, 9);
         b.draw(&i, 6, 9);
-        i.next();
+        i.prev();
         b.draw(&i, 8, 8);
     }
 
@@ -522,7 +1646,7 @@ mod tests {
         add_almost_full_lines(b);
 
         let mut i = get_i_piece();
-        i.next();
+        i.prev();
         b.draw(&i, 9, 8);
     }
 
@@ -536,7 +1660,7 @@ mod tests {
         assert_eq!(l.len(), 0);
 
         let mut i = get_i_piece();
-        i.next();
+        i.prev();
         b.draw(&i, 9, 8);
 
         b.print();
@@ -545,8 +1669,8 @@ mod tests {
         b.print();
 
         assert_eq!(l.len(), 2);
-        assert!(l.contains(&9_i8));
-        assert!(l.contains(&7_i8));
+        assert!(l.contains(&9_i32));
+        assert!(l.contains(&7_i32));
 
         let l = b.remove_full_lines();
         assert_eq!(l.len(), 0);
@@ -563,19 +1687,373 @@ mod tests {
     #[test]
     fn new_game() {
         let b = Board::new(10, 20);
-        let piece_factory = crate::PieceFactory::new();
-        crate::Game::new(b, piece_factory);
+        let piece_factory = crate::model::PieceFactory::new();
+        crate::model::Game::new(b, piece_factory);
     }
 
     #[test]
     fn piece_is_stuck() {
         let b = Board::new(10, 10);
-        let piece_factory = crate::PieceFactory::new();
-        let mut g = crate::Game::new(b, piece_factory);
+        let piece_factory = crate::model::PieceFactory::new();
+        let mut g = crate::model::Game::new(b, piece_factory);
 
         add_full_lines(&mut g.board);
 
         g.piece_stuck();
         g.board.print();
     }
+
+    #[test]
+    fn hold_swaps_once_per_piece() {
+        let b = Board::new(10, 20);
+        let mut g = crate::model::Game::new(b, crate::model::PieceFactory::with_seed(3));
+        g.draw();
+        let (first, second) = (g.piece.piece_type, g.next_piece.piece_type);
+        g.hold();
+        assert_eq!(g.hold.as_ref().map(|p| p.piece_type), Some(first));
+        assert_eq!(g.piece.piece_type, second);
+        g.hold();
+        assert_eq!(g.piece.piece_type, second);
+        g.fall();
+        let third = g.piece.piece_type;
+        g.hold();
+        assert_eq!(g.piece.piece_type, first);
+        assert_eq!(g.hold.as_ref().map(|p| p.piece_type), Some(third));
+    }
+
+    #[test]
+    fn rotations_undo_each_other() {
+        let b = Board::new(10, 20);
+        let mut g = crate::model::Game::new(
+            b,
+            crate::model::PieceFactory::from_sequence(vec![crate::model::PieceType::T]),
+        );
+        g.draw();
+        g.soft_drop();
+        g.up();
+        g.rotate_ccw();
+        assert_eq!(g.piece.rotation(), 0);
+        g.rotate_180();
+        assert_eq!(g.piece.rotation(), 2);
+        g.rotate_ccw();
+        assert_eq!(g.piece.rotation(), 1);
+    }
+
+    #[test]
+    fn kicked_off_the_wall() {
+        let b = Board::new(10, 20);
+        let mut g = crate::model::Game::new(
+            b,
+            crate::model::PieceFactory::from_sequence(vec![crate::model::PieceType::I]),
+        );
+        g.draw();
+        g.soft_drop();
+        g.soft_drop();
+        g.up();
+        assert_eq!(g.piece.rotation(), 1);
+        for _ in 0..5 {
+            g.right();
+        }
+        assert_eq!(g.pos.0, 10);
+        assert_eq!(g.board.cells(&g.piece, g.pos.0, g.pos.1)[0].0, 9);
+        g.up();
+        assert_eq!(g.piece.rotation(), 2);
+        assert_eq!(g.pos.0, 8);
+    }
+
+    #[test]
+    fn tspin_double_needs_a_kick() {
+        let mut g = t_game();
+        let rows = ["..X..X....", "XXX...XXXX", "XXXX.XXXXX"];
+        g.board = Board::from_rows(10, 20, &rows).unwrap();
+        // Standing left of the slot with the nub to the right, SRS's R.
+        g.piece.set_rotation(3);
+        g.pos = (3, 18);
+        assert_eq!(
+            g.board.cells(&g.piece, 3, 18),
+            vec![(3, 16), (3, 17), (4, 17), (3, 18)]
+        );
+        g.draw();
+        // Turned where it is it hits the blocks left and right, one
+        // step right too, so it's kicked down into the slot.
+        g.up();
+        assert_eq!(g.pos, (4, 19));
+        assert_eq!(
+            g.board.cells(&g.piece, 4, 19),
+            vec![(3, 18), (4, 18), (5, 18), (4, 19)]
+        );
+        g.fall();
+        let lock = g.events.iter().find_map(|e| match e {
+            Event::Locked(l) => Some(l),
+            _ => None,
+        });
+        assert!(matches!(lock, Some(l) if l.tspin && l.lines == 2));
+    }
+
+    fn t_game() -> crate::model::Game {
+        let b = Board::new(10, 20);
+        let mut g = crate::model::Game::new(
+            b,
+            crate::model::PieceFactory::from_sequence(vec![crate::model::PieceType::T]),
+        );
+        g.draw();
+        g
+    }
+
+    #[test]
+    fn drops_score_per_row() {
+        let mut g = t_game();
+        g.soft_drop();
+        g.soft_drop();
+        assert_eq!(g.score, 2);
+        let y = g.pos.1;
+        g.fall();
+        // 2 per row down to the bottom, and 1 for the lock.
+        assert_eq!(g.score, 2 + 2 * (19 - y as u32) + 1);
+    }
+
+    #[test]
+    fn sonic_drop_does_not_lock() {
+        let mut g = t_game();
+        g.soft_drop_speed = crate::model::SoftDrop::Sonic;
+        g.soft_drop();
+        assert_eq!(g.pos.1, 19);
+        assert!(g.events.is_empty());
+        assert_eq!(g.score, 18);
+        g.left();
+        assert_eq!(g.pos.0, 4);
+    }
+
+    #[test]
+    fn soft_drop_speeds_up_gravity() {
+        let mut g = t_game();
+        g.soft_drop_speed = crate::model::SoftDrop::Factor(10);
+        let y = g.pos.1;
+        g.soft_drop();
+        for _ in 0..5 {
+            g.tick(g.gravity / 10);
+        }
+        assert_eq!(g.pos.1, y + 6);
+        assert_eq!(g.score, 6);
+
+        // It stops when the key isn't repeated.
+        for _ in 0..20 {
+            g.tick(g.gravity / 10);
+        }
+        assert!(g.pos.1 < y + 12);
+    }
+
+    #[test]
+    fn gravity_stops_when_paused() {
+        let b = Board::new(10, 20);
+        let mut g = crate::model::Game::new(b, crate::model::PieceFactory::with_seed(1));
+        let y = g.pos.1;
+        g.tick(g.gravity);
+        assert_eq!(g.pos.1, y + 1);
+
+        g.pause();
+        g.tick(g.gravity * 3);
+        assert_eq!(g.pos.1, y + 1);
+        assert_eq!(g.time, g.gravity);
+
+        g.resume();
+        g.tick(g.gravity / 2);
+        assert_eq!(g.pos.1, y + 1);
+        g.tick(g.gravity / 2);
+        assert_eq!(g.pos.1, y + 2);
+        assert_eq!(g.time, g.gravity * 2);
+    }
+
+    fn tetris_game() -> crate::model::Game {
+        let rows = ["XXXXXXXXX."; 4];
+        let b = Board::from_rows(10, 20, &rows).unwrap();
+        let mut g = crate::model::Game::new(
+            b,
+            crate::model::PieceFactory::from_sequence(vec![crate::model::PieceType::I]),
+        );
+        g.draw();
+        g.soft_drop();
+        g.soft_drop();
+        g.up();
+        for _ in 0..5 {
+            g.right();
+        }
+        g.events.clear();
+        g
+    }
+
+    #[test]
+    fn line_clear_waits_for_the_delay() {
+        let mut g = tetris_game();
+        g.line_clear_delay = Duration::from_millis(300);
+        g.fall();
+        assert_eq!(g.lines, 4);
+        assert!(g.is_clearing());
+        assert_eq!(g.board.full_lines(), vec![19, 18, 17, 16]);
+        // Nothing moves while the rows are cleared.
+        let pos = g.pos;
+        g.left();
+        g.fall();
+        assert_eq!(g.pos, pos);
+
+        g.tick(Duration::from_millis(200));
+        assert!(g.is_clearing());
+        g.tick(Duration::from_millis(100));
+        assert!(!g.is_clearing());
+        assert!(g.board.full_lines().is_empty());
+        assert!(!g.board.is_set(0, 19));
+    }
+
+    #[test]
+    fn clears_send_events() {
+        let mut g = tetris_game();
+        g.lines = 8;
+        g.fall();
+        assert!(!g.is_clearing());
+        let events = std::mem::take(&mut g.events);
+        assert!(matches!(&events[0], Event::HardDropped { from, to, .. } if to > from));
+        assert!(matches!(&events[1], Event::Locked(lock) if lock.lines == 4));
+        assert!(matches!(&events[2], Event::Cleared(rows) if rows == &[19, 18, 17, 16]));
+        assert!(matches!(events[3], Event::LevelUp(2)));
+        assert_eq!(events.len(), 4);
+    }
+
+    #[test]
+    fn stack_height_without_the_piece() {
+        let mut g = t_game();
+        assert_eq!(g.stack_height(), 0);
+        g.fall();
+        assert_eq!(g.stack_height(), 2);
+    }
+
+    #[test]
+    fn changes_make_it_dirty() {
+        let mut g = t_game();
+        assert!(g.dirty);
+        g.dirty = false;
+        g.tick(Duration::from_millis(10));
+        assert!(!g.dirty);
+        g.left();
+        assert!(g.dirty);
+        g.dirty = false;
+        g.tick(crate::model::GRAVITY);
+        assert!(g.dirty);
+    }
+
+    /// A coordinate on a board `size` long: on it, near its edges or
+    /// anywhere at all.
+    fn coordinate(rng: &mut crate::model::Random, size: u16) -> i32 {
+        match rng.below(3) {
+            0 => rng.below(usize::from(size)) as i32,
+            1 => rng.below(usize::from(size) + 10) as i32 - 5,
+            _ => rng.next_u64() as i32,
+        }
+    }
+
+    #[test]
+    fn pieces_anywhere_on_random_boards() {
+        let mut rng = crate::model::Random::new(1);
+        let pieces = crate::model::get_pieces();
+        for _ in 0..300 {
+            let width = 1 + rng.below(1000) as u16;
+            let height = 1 + rng.below(1000) as u16;
+            let mut b = Board::new(width, height);
+            for _ in 0..rng.below(100) {
+                let i = rng.below(b.map.len());
+                b.map[i] = crate::model::PieceType::GARBAGE;
+            }
+            let mut p = pieces[rng.below(pieces.len())].clone();
+            p.set_rotation(rng.below(4));
+            let (x, y) = (coordinate(&mut rng, width), coordinate(&mut rng, height));
+
+            let (ox, oy) = p.curr().offset;
+            let blocks = p
+                .curr()
+                .blocks()
+                .iter()
+                .map(|&(bx, by)| {
+                    (
+                        i64::from(x) + i64::from(bx - ox),
+                        i64::from(y) + i64::from(by - oy),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let on_board = |(cx, cy): (i64, i64)| {
+                (0..i64::from(width)).contains(&cx) && (0..i64::from(height)).contains(&cy)
+            };
+            let cells = b.cells(&p, x, y);
+            let expected = blocks
+                .iter()
+                .filter(|c| on_board(**c))
+                .map(|&(cx, cy)| (cx as i32, cy as i32))
+                .collect::<Vec<_>>();
+            assert_eq!(cells, expected);
+            let fits = blocks.iter().all(|c| on_board(*c))
+                && cells.iter().all(|&(cx, cy)| !b.is_set(cx, cy));
+            assert_eq!(b.fits(&p, x, y), fits);
+            assert!(!b.is_set(x, y) || on_board((i64::from(x), i64::from(y))));
+
+            if fits {
+                let before = b.map.clone();
+                b.draw(&p, x, y);
+                assert!(cells.iter().all(|&(cx, cy)| b.is_set(cx, cy)));
+                assert!(!b.fits(&p, x, y));
+                b.clear(&p, x, y);
+                assert_eq!(b.map, before);
+            }
+        }
+    }
+
+    #[test]
+    fn random_moves_stay_on_random_boards() {
+        let mut rng = crate::model::Random::new(2);
+        for _ in 0..20 {
+            let width = 1 + rng.below(1000) as u16;
+            let height = 1 + rng.below(1000) as u16;
+            let mut g = crate::model::Game::new(
+                Board::new(width, height),
+                crate::model::PieceFactory::with_seed(rng.next_u64()),
+            );
+            g.draw();
+            for _ in 0..300 {
+                if g.game_over {
+                    break;
+                }
+                match rng.below(9) {
+                    0 => g.left(),
+                    1 => g.right(),
+                    2 => g.up(),
+                    3 => g.rotate_ccw(),
+                    4 => g.rotate_180(),
+                    5 => g.soft_drop(),
+                    6 => g.hold(),
+                    7 => g.tick(crate::model::GRAVITY),
+                    _ => g.fall(),
+                }
+                let blocks = g.piece.curr().blocks().len();
+                if !g.game_over {
+                    assert_eq!(g.board.cells(&g.piece, g.pos.0, g.pos.1).len(), blocks);
+                }
+            }
+            assert!(g.stack_height() <= height);
+        }
+    }
+
+    #[test]
+    fn thousand_wide_boards() {
+        let mut g = crate::model::Game::new(
+            Board::new(1000, 1000),
+            crate::model::PieceFactory::from_sequence(vec![crate::model::PieceType::I]),
+        );
+        g.draw();
+        assert_eq!(g.pos.0, 500);
+        for _ in 0..600 {
+            g.right();
+        }
+        g.fall();
+        assert_eq!(g.stack_height(), 1);
+        assert!((996..1000).all(|x| g.board.is_set(x, 999)));
+        assert!(!g.board.is_set(1000, 999));
+        assert!(!g.board.is_set(-1, i32::MAX));
+    }
 }
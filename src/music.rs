@@ -0,0 +1,497 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! A small synthesizer for the background music.
+//!
+//! Songs are written in text files like this:
+//!
+//! ```text
+//! # Comments start with a hash.
+//! name = Scale
+//! tempo = 120
+//!
+//! voice = pulse
+//! C4/4 D4/4 E4/4 F4/8 F#4/8
+//! G4/2. r/4
+//!
+//! voice = triangle
+//! C2/1
+//! C2/2 r/2
+//! ```
+//!
+//! The tempo is in quarter notes per minute. Each voice is played with
+//! one of the waves square, pulse, triangle or saw. A note is its name,
+//! maybe sharp (#) or flat (b), the octave and the length: 1 is a whole
+//! note, 4 a quarter and so on, a dot makes it half as long again. r is
+//! a rest. All voices must be as long, the song loops.
+//!
+//! With the `audio` feature the music is played on the sound card,
+//! without it the songs can only be rendered to WAV files.
+
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::model::Game;
+
+pub const SAMPLE_RATE: u32 = 22050;
+
+const KOROBEINIKI: &str = include_str!("../music/korobeiniki.txt");
+
+/// Notes end this long before the next starts, in beats.
+const GAP: f64 = 0.05;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wave {
+    Square,
+    /// A square wave that is only high a quarter of the time.
+    Pulse,
+    Triangle,
+    Saw,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Note {
+    /// None for a rest.
+    pub freq: Option<f64>,
+    /// Quarter notes are one beat.
+    pub beats: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Voice {
+    pub wave: Wave,
+    pub notes: Vec<Note>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Song {
+    pub name: String,
+    pub tempo: f64,
+    pub voices: Vec<Voice>,
+}
+
+impl Wave {
+    fn parse(s: &str) -> Result<Wave, String> {
+        match s {
+            "square" => Ok(Wave::Square),
+            "pulse" => Ok(Wave::Pulse),
+            "triangle" => Ok(Wave::Triangle),
+            "saw" => Ok(Wave::Saw),
+            _ => Err(format!("unknown wave: {}", s)),
+        }
+    }
+
+    /// The wave at phase 0..1. The loudness evens out how loud the
+    /// waves sound.
+    fn sample(self, phase: f64) -> f64 {
+        match self {
+            Wave::Square => {
+                if phase < 0.5 {
+                    0.5
+                } else {
+                    -0.5
+                }
+            }
+            Wave::Pulse => {
+                if phase < 0.25 {
+                    0.5
+                } else {
+                    -0.5
+                }
+            }
+            Wave::Triangle => 4.0 * (phase - 0.5).abs() - 1.0,
+            Wave::Saw => phase - 0.5,
+        }
+    }
+}
+
+impl Note {
+    /// Parses a note like `C#4/8.` or a rest like `r/4`.
+    pub fn parse(s: &str) -> Result<Note, String> {
+        let bad = || format!("bad note: {}", s);
+        let (pitch, length) = match s.find('/') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => return Err(bad()),
+        };
+        let (length, dotted) = match length.strip_suffix('.') {
+            Some(l) => (l, true),
+            None => (length, false),
+        };
+        let beats = match length.parse::<u8>() {
+            Ok(l) if [1, 2, 4, 8, 16, 32].contains(&l) => 4.0 / f64::from(l),
+            _ => return Err(bad()),
+        };
+        let beats = if dotted { beats * 1.5 } else { beats };
+        if pitch == "r" {
+            return Ok(Note { freq: None, beats });
+        }
+        let mut chars = pitch.chars();
+        let semitone = match chars.next() {
+            Some('C') => 0,
+            Some('D') => 2,
+            Some('E') => 4,
+            Some('F') => 5,
+            Some('G') => 7,
+            Some('A') => 9,
+            Some('B') => 11,
+            _ => return Err(bad()),
+        };
+        let rest = chars.as_str();
+        let (semitone, octave) = match rest.chars().next() {
+            Some('#') => (semitone + 1, &rest[1..]),
+            Some('b') => (semitone - 1, &rest[1..]),
+            _ => (semitone, rest),
+        };
+        let octave = match octave.parse::<i32>() {
+            Ok(o) if (0..=8).contains(&o) => o,
+            _ => return Err(bad()),
+        };
+        let midi = (octave + 1) * 12 + semitone;
+        Ok(Note {
+            freq: Some(440.0 * 2f64.powf(f64::from(midi - 69) / 12.0)),
+            beats,
+        })
+    }
+}
+
+impl Voice {
+    fn beats(&self) -> f64 {
+        self.notes.iter().map(|n| n.beats).sum()
+    }
+}
+
+impl Song {
+    pub fn parse(text: &str) -> Result<Song, String> {
+        let mut name = None;
+        let mut tempo = None;
+        let mut voices: Vec<Voice> = vec![];
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+                None => {
+                    let voice = voices.last_mut().ok_or("notes before a voice")?;
+                    for note in line.split_whitespace() {
+                        voice.notes.push(Note::parse(note)?);
+                    }
+                    continue;
+                }
+            };
+            match key {
+                "name" => name = Some(value.to_string()),
+                "tempo" => {
+                    tempo = match value.parse::<f64>() {
+                        Ok(t) if t > 0.0 && t <= 1000.0 => Some(t),
+                        _ => return Err(format!("bad tempo: {}", value)),
+                    }
+                }
+                "voice" => voices.push(Voice {
+                    wave: Wave::parse(value)?,
+                    notes: vec![],
+                }),
+                _ => return Err(format!("unknown key: {}", key)),
+            }
+        }
+        let song = Song {
+            name: name.ok_or("no name")?,
+            tempo: tempo.ok_or("no tempo")?,
+            voices,
+        };
+        let beats = song.beats();
+        if beats == 0.0 {
+            return Err("no notes".to_string());
+        }
+        if song.voices.iter().any(|v| (v.beats() - beats).abs() > 1e-9) {
+            return Err("the voices are not as long".to_string());
+        }
+        Ok(song)
+    }
+
+    /// The song that comes with the game.
+    pub fn korobeiniki() -> Song {
+        Song::parse(KOROBEINIKI).expect("bad bundled song")
+    }
+
+    /// How long the song is, in beats.
+    pub fn beats(&self) -> f64 {
+        self.voices.first().map_or(0.0, Voice::beats)
+    }
+
+    /// How long the song is, in samples.
+    pub fn samples(&self, rate: u32, speed: f32) -> usize {
+        (self.beats() * 60.0 / self.tempo / f64::from(speed) * f64::from(rate)).round() as usize
+    }
+}
+
+/// How many times faster than written the music plays, it can be
+/// changed while it plays.
+#[derive(Clone, Debug)]
+pub struct Speed(Arc<AtomicU32>);
+
+impl Speed {
+    pub fn new(speed: f32) -> Self {
+        Speed(Arc::new(AtomicU32::new(speed.to_bits())))
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, speed: f32) {
+        self.0.store(speed.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// The music gets faster with the level, and even faster when the
+/// stack is two thirds up the board.
+pub fn speed_for(g: &Game) -> f32 {
+    let level = (1.0 + 0.05 * f32::from(g.level() - 1)).min(1.5);
+    if u32::from(g.stack_height()) * 3 >= u32::from(g.board.height()) * 2 {
+        level * 1.25
+    } else {
+        level
+    }
+}
+
+struct Playing {
+    note: usize,
+    /// How far into the note, in beats.
+    beat: f64,
+    phase: f64,
+}
+
+/// Plays a song in a loop, one sample at a time.
+pub struct Synth {
+    song: Song,
+    rate: u32,
+    speed: Speed,
+    voices: Vec<Playing>,
+}
+
+impl Synth {
+    pub fn new(song: Song, rate: u32, speed: Speed) -> Self {
+        let voices = song
+            .voices
+            .iter()
+            .map(|_| Playing {
+                note: 0,
+                beat: 0.0,
+                phase: 0.0,
+            })
+            .collect();
+        Synth {
+            song,
+            rate,
+            speed,
+            voices,
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let beats = self.song.tempo * f64::from(self.speed.get()) / 60.0 / f64::from(self.rate);
+        let mut sample = 0.0;
+        for (voice, p) in self.song.voices.iter().zip(self.voices.iter_mut()) {
+            let note = &voice.notes[p.note];
+            if let Some(freq) = note.freq {
+                if p.beat < note.beats - GAP {
+                    sample += voice.wave.sample(p.phase) * 0.3;
+                }
+                p.phase = (p.phase + freq / f64::from(self.rate)).fract();
+            }
+            p.beat += beats;
+            if p.beat >= note.beats {
+                p.beat -= note.beats;
+                p.note = (p.note + 1) % voice.notes.len();
+                p.phase = 0.0;
+            }
+        }
+        sample as f32
+    }
+}
+
+impl Iterator for Synth {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        Some(self.next_sample())
+    }
+}
+
+/// Plays the song once, at the given speed.
+pub fn render(song: &Song, rate: u32, speed: f32) -> Vec<f32> {
+    let n = song.samples(rate, speed);
+    Synth::new(song.clone(), rate, Speed::new(speed))
+        .take(n)
+        .collect()
+}
+
+/// Makes a mono, 16 bit WAV file of the samples.
+pub fn wav(samples: &[f32], rate: u32) -> Vec<u8> {
+    let data = samples.len() as u32 * 2;
+    let mut out = Vec::with_capacity(44 + data as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel.
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&rate.to_le_bytes());
+    out.extend_from_slice(&(rate * 2).to_le_bytes());
+    out.extend_from_slice(&2u16.to_le_bytes());
+    out.extend_from_slice(&16u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data.to_le_bytes());
+    for s in samples {
+        let s = (s.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+        out.extend_from_slice(&s.to_le_bytes());
+    }
+    out
+}
+
+#[cfg(feature = "audio")]
+impl rodio::Source for Synth {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+/// Music playing on the sound card while the game goes on. Without
+/// the `audio` feature, or a sound card, it is silent.
+pub struct Background {
+    speed: Speed,
+    #[cfg(feature = "audio")]
+    output: Option<(rodio::OutputStream, rodio::Sink)>,
+}
+
+impl Background {
+    /// Starts playing the song in a loop.
+    pub fn start(song: &Song) -> Self {
+        let speed = Speed::new(1.0);
+        #[cfg(feature = "audio")]
+        let output = rodio::OutputStream::try_default()
+            .ok()
+            .and_then(|(stream, handle)| {
+                let sink = rodio::Sink::try_new(&handle).ok()?;
+                sink.append(Synth::new(song.clone(), SAMPLE_RATE, speed.clone()));
+                Some((stream, sink))
+            });
+        #[cfg(not(feature = "audio"))]
+        let _ = song;
+        Background {
+            speed,
+            #[cfg(feature = "audio")]
+            output,
+        }
+    }
+
+    pub fn set_speed(&self, speed: f32) {
+        self.speed.set(speed);
+    }
+
+    /// Volume is from 0 to 1.
+    pub fn set_volume(&self, volume: f32) {
+        #[cfg(feature = "audio")]
+        if let Some((_, sink)) = &self.output {
+            sink.set_volume(volume);
+        }
+        #[cfg(not(feature = "audio"))]
+        let _ = volume;
+    }
+
+    pub fn pause(&self) {
+        #[cfg(feature = "audio")]
+        if let Some((_, sink)) = &self.output {
+            sink.pause();
+        }
+    }
+
+    pub fn resume(&self) {
+        #[cfg(feature = "audio")]
+        if let Some((_, sink)) = &self.output {
+            sink.play();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Board;
+    use crate::model::PieceFactory;
+    use crate::music::*;
+
+    #[test]
+    fn notes() {
+        let a = Note::parse("A4/4").unwrap();
+        assert!((a.freq.unwrap() - 440.0).abs() < 1e-9);
+        assert_eq!(a.beats, 1.0);
+        let c = Note::parse("C#5/8.").unwrap();
+        assert!((c.freq.unwrap() - 554.365).abs() < 1e-3);
+        assert_eq!(c.beats, 0.75);
+        assert_eq!(Note::parse("Bb3/4"), Note::parse("A#3/4"));
+        assert_eq!(Note::parse("r/2").unwrap().freq, None);
+        assert!(Note::parse("H4/4").is_err());
+        assert!(Note::parse("A4/3").is_err());
+        assert!(Note::parse("A4").is_err());
+    }
+
+    #[test]
+    fn bad_songs() {
+        assert!(Song::parse("name = x\ntempo = 120\nA4/4").is_err());
+        assert!(Song::parse("name = x\ntempo = 0\nvoice = saw\nA4/4").is_err());
+        assert!(Song::parse("name = x\ntempo = 120\nvoice = sine\nA4/4").is_err());
+        assert!(
+            Song::parse("name = x\ntempo = 120\nvoice = saw\nA4/4\nvoice = saw\nA4/2").is_err()
+        );
+    }
+
+    #[test]
+    fn korobeiniki_renders() {
+        let song = Song::korobeiniki();
+        assert_eq!(song.voices.len(), 2);
+        assert_eq!(song.beats(), 32.0);
+        let samples = render(&song, SAMPLE_RATE, 1.0);
+        // 32 beats at 150 per minute.
+        assert_eq!(samples.len(), (12.8 * SAMPLE_RATE as f64) as usize);
+        assert!(samples.iter().all(|s| s.abs() <= 1.0));
+        assert!(samples.iter().any(|s| s.abs() > 0.1));
+        // Twice as fast, half as long.
+        assert_eq!(render(&song, SAMPLE_RATE, 2.0).len(), samples.len() / 2);
+
+        let wav = wav(&samples, SAMPLE_RATE);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(wav.len(), 44 + 2 * samples.len());
+    }
+
+    #[test]
+    fn faster_with_level_and_stack() {
+        let mut g = Game::new(Board::new(10, 20), PieceFactory::with_seed(1));
+        assert_eq!(speed_for(&g), 1.0);
+        g.lines = 20;
+        assert!((speed_for(&g) - 1.1).abs() < 1e-6);
+        g.lines = 1000;
+        assert_eq!(speed_for(&g), 1.5);
+
+        let rows = ["X........."; 14];
+        g.board = Board::from_rows(10, 20, &rows).unwrap();
+        g.lines = 0;
+        assert_eq!(speed_for(&g), 1.25);
+    }
+}
@@ -0,0 +1,29 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Where the game keeps its files, as in the XDG Base Directory
+//! Specification.
+
+use std::env;
+use std::path::PathBuf;
+
+const APP: &str = "rust-teris";
+
+fn dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    let base = match env::var_os(var).map(PathBuf::from) {
+        Some(d) if d.is_absolute() => d,
+        _ => PathBuf::from(env::var_os("HOME")?).join(fallback),
+    };
+    Some(base.join(APP))
+}
+
+/// The directory for saved games and the like.
+pub fn data_dir() -> Option<PathBuf> {
+    dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// The directory for the settings.
+pub fn config_dir() -> Option<PathBuf> {
+    dir("XDG_CONFIG_HOME", ".config")
+}
@@ -0,0 +1,354 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Piece sets are other pieces to play with than the usual seven, like
+//! the twelve pentominoes.
+//!
+//! They are written in TOML files like this:
+//!
+//! ```toml
+//! name = "Trominoes"
+//!
+//! [[piece]]
+//! letter = "i"
+//! colour = "#00f0f0"
+//! shape = ["XXX"]
+//!
+//! [[piece]]
+//! letter = "l"
+//! colour = "#f0a000"
+//! rotations = [["X.", "XX"], ["XX", "X."], ["XX", ".X"], [".X", "XX"]]
+//! origins = [[0, 1], [0, 0], [1, 0], [1, 1]]
+//! spawn = [0, 1]
+//! ```
+//!
+//! Each piece has a lowercase letter of its own and a colour, and can
+//! have a `glyph` to draw its blocks with when the glyphs are ascii. Its
+//! `shape` is up to five rows of up to five blocks, X for a block and
+//! a dot for a hole, and is turned to get its other rotations. Or all
+//! of them are given as `rotations`, in clockwise order. The piece
+//! turns around the block at its `origin`, or `origins` for each
+//! rotation, counted from the top left. It's the block in the middle
+//! when not given. `spawn` moves where the piece comes on the board,
+//! from the middle of the top row.
+
+use std::path::Path;
+
+use crate::model::Piece;
+use crate::model::PieceFactory;
+use crate::model::PieceType;
+use crate::model::Tetramino;
+use crate::registry::Material;
+use crate::registry::Registry;
+use crate::registry::Rgb;
+
+const BUNDLED: [&str; 1] = [include_str!("../pieces/pentominoes.toml")];
+
+#[derive(Clone)]
+pub struct PieceSet {
+    pub name: String,
+    pub pieces: Vec<Piece>,
+    /// How each piece looks.
+    pub materials: Vec<Material>,
+}
+
+fn parse_colour(s: &str) -> Result<Rgb, String> {
+    let bad = || format!("bad colour: {}", s);
+    let hex = s
+        .strip_prefix('#')
+        .filter(|h| h.len() == 6)
+        .ok_or_else(bad)?;
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .ok_or_else(bad)
+    };
+    Ok((channel(0)?, channel(2)?, channel(4)?))
+}
+
+fn parse_rows(v: &toml::Value) -> Result<Vec<&str>, String> {
+    v.as_array()
+        .ok_or("a shape must be a list of rows")?
+        .iter()
+        .map(|r| {
+            r.as_str()
+                .ok_or_else(|| "a row must be a string".to_string())
+        })
+        .collect()
+}
+
+fn parse_pair(v: &toml::Value) -> Result<(i8, i8), String> {
+    let numbers = v
+        .as_array()
+        .filter(|a| a.len() == 2)
+        .ok_or("expected [x, y]")?
+        .iter()
+        .map(|n| {
+            n.as_integer()
+                .filter(|n| (-10..=10).contains(n))
+                .ok_or_else(|| format!("bad number: {}", n))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((numbers[0] as i8, numbers[1] as i8))
+}
+
+/// The shape turning around the block at `origin`, or the middle one.
+fn parse_shape(rows: &[&str], origin: Option<(i8, i8)>) -> Result<Tetramino, String> {
+    let width = rows.iter().map(|r| r.chars().count()).max().unwrap_or(0) as i8;
+    let height = rows.len() as i8;
+    let origin = origin.unwrap_or((width / 2, height / 2));
+    if !(0..width).contains(&origin.0) || !(0..height).contains(&origin.1) {
+        return Err(format!("origin {:?} outside the shape", origin));
+    }
+    Tetramino::from_rows(rows, origin)
+}
+
+fn parse_piece(t: &toml::value::Table) -> Result<(Piece, Material), String> {
+    for key in t.keys() {
+        if ![
+            "letter",
+            "colour",
+            "shape",
+            "origin",
+            "rotations",
+            "origins",
+            "spawn",
+            "glyph",
+        ]
+        .contains(&key.as_str())
+        {
+            return Err(format!("unknown key: {}", key));
+        }
+    }
+    let letter = t
+        .get("letter")
+        .and_then(|l| l.as_str())
+        .ok_or("no letter")?;
+    let piece_type = match letter.chars().collect::<Vec<_>>()[..] {
+        [c] => PieceType::custom(c),
+        _ => None,
+    }
+    .ok_or_else(|| format!("bad letter: {}, use one of a-z", letter))?;
+    let colour = t
+        .get("colour")
+        .and_then(|c| c.as_str())
+        .ok_or("no colour")?;
+    let colour = parse_colour(colour)?;
+    let pair = |key: &str| t.get(key).map(parse_pair).transpose();
+    let rotations = match (t.get("shape"), t.get("rotations")) {
+        (Some(shape), None) => vec![parse_shape(&parse_rows(shape)?, pair("origin")?)?],
+        (None, Some(rotations)) => {
+            let rotations = rotations
+                .as_array()
+                .filter(|a| (1..=4).contains(&a.len()))
+                .ok_or("rotations must be a list of one to four shapes")?;
+            let origins = match t.get("origins") {
+                Some(o) => o
+                    .as_array()
+                    .filter(|a| a.len() == rotations.len())
+                    .ok_or("an origin is needed for each rotation")?
+                    .iter()
+                    .map(|o| parse_pair(o).map(Some))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => vec![None; rotations.len()],
+            };
+            rotations
+                .iter()
+                .zip(origins)
+                .map(|(r, o)| parse_shape(&parse_rows(r)?, o))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+        _ => return Err("either a shape or rotations is needed".to_string()),
+    };
+    let mut piece = Piece::from_rotations(rotations, piece_type);
+    piece.spawn = pair("spawn")?.unwrap_or((0, 0));
+    let glyph = match t
+        .get("glyph")
+        .map(|g| g.as_str().map(|s| s.chars().collect::<Vec<_>>()))
+    {
+        None => 'X',
+        Some(Some(g)) if g.len() == 1 && g[0].is_ascii_graphic() => g[0],
+        _ => return Err("the glyph must be one ascii character".to_string()),
+    };
+    Ok((piece, Material::new(piece_type, colour, glyph)))
+}
+
+impl PieceSet {
+    pub fn parse(text: &str) -> Result<PieceSet, String> {
+        let value = text.parse::<toml::Value>().map_err(|e| e.to_string())?;
+        let name = value
+            .get("name")
+            .and_then(|n| n.as_str())
+            .ok_or("no name")?
+            .to_string();
+        let tables = value
+            .get("piece")
+            .and_then(|p| p.as_array())
+            .filter(|a| !a.is_empty())
+            .ok_or("no pieces")?;
+        let mut set = PieceSet {
+            name,
+            pieces: vec![],
+            materials: vec![],
+        };
+        for t in tables {
+            let t = t.as_table().ok_or("a piece must be a table")?;
+            let (piece, material) = parse_piece(t)?;
+            let t = piece.piece_type;
+            if set.pieces.iter().any(|p| p.piece_type == t) {
+                return Err(format!("two pieces are {}", t.to_char()));
+            }
+            set.pieces.push(piece);
+            set.materials.push(material);
+        }
+        Ok(set)
+    }
+
+    pub fn load(path: &Path) -> Result<PieceSet, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        PieceSet::parse(&text)
+    }
+
+    /// The piece sets that come with the game.
+    pub fn bundled() -> Vec<PieceSet> {
+        BUNDLED
+            .iter()
+            .map(|text| PieceSet::parse(text).expect("bad bundled piece set"))
+            .collect()
+    }
+
+    /// A factory that deals the set's pieces.
+    pub fn factory(&self, seed: u64) -> PieceFactory {
+        PieceFactory::with_pieces(self.pieces.clone(), seed)
+    }
+
+    /// The built in materials and the set's.
+    pub fn registry(&self) -> Registry {
+        let mut r = Registry::default();
+        for m in &self.materials {
+            r.register(m.clone())
+                .expect("piece sets only have lowercase letters");
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Board;
+    use crate::model::Game;
+    use crate::pieceset::*;
+
+    const TROMINOES: &str = r##"
+        name = "Trominoes"
+
+        [[piece]]
+        letter = "i"
+        colour = "#00f0f0"
+        shape = ["XXX"]
+
+        [[piece]]
+        letter = "l"
+        colour = "#f0a000"
+        rotations = [["X.", "XX"], ["XX", "X."], ["XX", ".X"], [".X", "XX"]]
+        origins = [[0, 1], [0, 0], [1, 0], [1, 1]]
+        spawn = [0, 1]
+    "##;
+
+    #[test]
+    fn parses_a_set() {
+        let set = PieceSet::parse(TROMINOES).unwrap();
+        assert_eq!(set.name, "Trominoes");
+        assert_eq!(set.pieces.len(), 2);
+        let l = PieceType::custom('l').unwrap();
+        let r = set.registry();
+        assert_eq!(r.get(l).map(|m| m.rgb), Some((240, 160, 0)));
+        assert_eq!(r.get(l).map(|m| m.glyph), Some('X'));
+        assert_eq!(r.materials().len(), 10);
+        // The straight piece got its upright rotation made for it.
+        let i = &set.pieces[0];
+        assert_eq!(i.rotations(), 2);
+        let mut upright = i.clone();
+        upright.next();
+        assert_eq!(upright.curr().blocks(), vec![(0, 0), (0, 1), (0, 2)]);
+        assert_eq!(upright.curr().offset(), (0, 1));
+        assert_eq!(set.pieces[1].rotations(), 4);
+        assert_eq!(set.pieces[1].spawn, (0, 1));
+    }
+
+    #[test]
+    fn turns_away_bad_sets() {
+        let bad = |piece: &str| {
+            PieceSet::parse(&format!("name = \"Bad\"\n[[piece]]\n{}", piece))
+                .err()
+                .unwrap()
+        };
+        assert_eq!(
+            PieceSet::parse("name = \"Bad\"").err().unwrap(),
+            "no pieces"
+        );
+        assert_eq!(
+            bad("letter = \"I\"\ncolour = \"#000000\"\nshape = [\"X\"]"),
+            "bad letter: I, use one of a-z"
+        );
+        assert_eq!(
+            bad("letter = \"i\"\ncolour = \"red\"\nshape = [\"X\"]"),
+            "bad colour: red"
+        );
+        assert_eq!(
+            bad("letter = \"i\"\ncolour = \"#ff0000\"\nshape = [\"XXXXXX\"]"),
+            "a shape larger than 5x5"
+        );
+        assert_eq!(
+            bad("letter = \"i\"\ncolour = \"#ff0000\"\nshape = [\"X.\", \"..\"]"),
+            "empty edges in \"X./..\""
+        );
+        assert_eq!(
+            bad("letter = \"i\"\ncolour = \"#ff0000\"\nshape = [\"X\"]\norigin = [1, 0]"),
+            "origin (1, 0) outside the shape"
+        );
+        assert_eq!(
+            bad("letter = \"i\"\ncolour = \"#ff0000\"\nshape = [\"X\"]\nglyph = \"##\""),
+            "the glyph must be one ascii character"
+        );
+    }
+
+    #[test]
+    fn pentominoes_are_played() {
+        let sets = PieceSet::bundled();
+        let p = &sets[0];
+        assert_eq!(p.name, "Pentominoes");
+        assert_eq!(p.pieces.len(), 12);
+        let rotations = |c: char| {
+            let t = PieceType::custom(c).unwrap();
+            p.pieces
+                .iter()
+                .find(|p| p.piece_type == t)
+                .unwrap()
+                .rotations()
+        };
+        assert_eq!(rotations('x'), 1);
+        assert_eq!(rotations('i'), 2);
+        assert_eq!(rotations('z'), 2);
+        assert_eq!(rotations('f'), 4);
+        for piece in &p.pieces {
+            assert!(piece.curr().blocks().len() == 5);
+        }
+
+        let factory = p.factory(1);
+        assert_eq!(factory.preview_size(), (5, 3));
+        let mut g = Game::new(Board::new(10, 20), factory);
+        g.draw();
+        for _ in 0..30 {
+            g.rotate_ccw();
+            g.fall();
+        }
+        assert!(g.game_over);
+        assert_eq!(g.stats.counts[0].0, PieceType::custom('f').unwrap());
+        assert_eq!(
+            g.stats.counts.iter().map(|(_, n)| n).sum::<u32>(),
+            g.stats.pieces
+        );
+    }
+}
@@ -0,0 +1,353 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Puzzles are boards with a fixed set of pieces and a goal to reach
+//! with them.
+//!
+//! They are written in text files like this:
+//!
+//! ```text
+//! # Comments start with a hash.
+//! name = Fill the gap
+//! goal = lines 4
+//! pieces = O O I O O
+//! board =
+//! XXXXX.....
+//! XXXXX.....
+//! ```
+//!
+//! The goal is either `lines N`, clear N lines, or `tspin N`, clear N
+//! lines with a T-spin. N can also be written as single, double or
+//! triple. The board rows are put at the bottom of the game's board.
+//!
+//! Instead of the rows, the board can be given as a fumen string:
+//!
+//! ```text
+//! fumen = v115@9gI8AeI8AeI8AeI8KeAgH
+//! ```
+
+use crate::fumen;
+use crate::model::Board;
+use crate::model::Game;
+use crate::model::Lock;
+use crate::model::PieceFactory;
+use crate::model::PieceType;
+
+const BUNDLED: [&str; 4] = [
+    include_str!("../puzzles/tspin-single.txt"),
+    include_str!("../puzzles/fill-the-gap.txt"),
+    include_str!("../puzzles/twin-towers.txt"),
+    include_str!("../puzzles/tspin-double.txt"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Goal {
+    /// Clear this many lines.
+    Lines(usize),
+    /// Clear this many lines with one T-spin.
+    TSpin(usize),
+}
+
+#[derive(Clone, Debug)]
+pub struct Puzzle {
+    pub name: String,
+    pub goal: Goal,
+    pub pieces: Vec<PieceType>,
+    pub rows: Vec<String>,
+    /// The board as a fumen, instead of the rows.
+    pub fumen: Option<fumen::Page>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Playing,
+    Solved,
+    Failed,
+}
+
+/// A puzzle being played.
+pub struct Attempt {
+    pub puzzle: Puzzle,
+    pub placed: usize,
+    pub lines: usize,
+    pub status: Status,
+}
+
+fn parse_count(s: &str) -> Result<usize, String> {
+    match s {
+        "single" => Ok(1),
+        "double" => Ok(2),
+        "triple" => Ok(3),
+        _ => s.parse().map_err(|_| format!("bad count: {}", s)),
+    }
+}
+
+impl Goal {
+    fn parse(s: &str) -> Result<Goal, String> {
+        let mut words = s.split_whitespace();
+        let kind = words.next().unwrap_or("");
+        let count = parse_count(words.next().unwrap_or(""))?;
+        match kind {
+            "lines" => Ok(Goal::Lines(count)),
+            "tspin" => Ok(Goal::TSpin(count)),
+            _ => Err(format!("unknown goal: {}", s)),
+        }
+    }
+}
+
+impl Puzzle {
+    pub fn parse(text: &str) -> Result<Puzzle, String> {
+        let mut name = None;
+        let mut goal = None;
+        let mut pieces = None;
+        let mut rows = vec![];
+        let mut fumen = None;
+        let mut in_board = false;
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if in_board {
+                rows.push(line.to_string());
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+                None => return Err(format!("expected key = value: {}", line)),
+            };
+            match key {
+                "name" => name = Some(value.to_string()),
+                "goal" => goal = Some(Goal::parse(value)?),
+                "pieces" => {
+                    pieces = Some(
+                        value
+                            .split_whitespace()
+                            .map(|p| match p.chars().next().and_then(PieceType::from_char) {
+                                Some(t) if PieceType::PIECES.contains(&t) => Ok(t),
+                                _ => Err(format!("unknown piece: {}", p)),
+                            })
+                            .collect::<Result<Vec<_>, _>>()?,
+                    )
+                }
+                "board" => in_board = true,
+                "fumen" => fumen = Some(fumen::decode(value)?.remove(0)),
+                _ => return Err(format!("unknown key: {}", key)),
+            }
+        }
+        let pieces = pieces.ok_or("no pieces")?;
+        if pieces.is_empty() {
+            return Err("no pieces".to_string());
+        }
+        let p = Puzzle {
+            name: name.ok_or("no name")?,
+            goal: goal.ok_or("no goal")?,
+            pieces,
+            rows,
+            fumen,
+        };
+        if p.fumen.is_some() && !p.rows.is_empty() {
+            return Err("both a board and a fumen".to_string());
+        }
+        // Catch bad rows already when loading.
+        p.board(20)?;
+        Ok(p)
+    }
+
+    /// The puzzles that come with the game.
+    pub fn bundled() -> Vec<Puzzle> {
+        BUNDLED
+            .iter()
+            .map(|text| Puzzle::parse(text).expect("bad bundled puzzle"))
+            .collect()
+    }
+
+    pub fn width(&self) -> u16 {
+        self.rows
+            .first()
+            .map_or(10, |r| u16::try_from(r.chars().count()).unwrap_or(u16::MAX))
+    }
+
+    fn board(&self, height: u16) -> Result<Board, String> {
+        if let Some(page) = &self.fumen {
+            return page.board(height);
+        }
+        let rows = self.rows.iter().map(String::as_str).collect::<Vec<_>>();
+        Board::from_rows(self.width(), height, &rows)
+    }
+
+    pub fn new_game(&self, height: u16) -> Result<Game, String> {
+        let b = self.board(height)?;
+        Ok(Game::new(
+            b,
+            PieceFactory::from_sequence(self.pieces.clone()),
+        ))
+    }
+
+    pub fn describe(&self) -> String {
+        let n = self.pieces.len();
+        let pieces = if n == 1 { "piece" } else { "pieces" };
+        match self.goal {
+            Goal::Lines(l) => format!("Clear {} lines with {} {}", l, n, pieces),
+            Goal::TSpin(l) => {
+                let kind = match l {
+                    1 => "single".to_string(),
+                    2 => "double".to_string(),
+                    3 => "triple".to_string(),
+                    l => format!("with {} lines", l),
+                };
+                format!("T-spin {} with {} {}", kind, n, pieces)
+            }
+        }
+    }
+}
+
+impl Attempt {
+    pub fn new(puzzle: Puzzle) -> Self {
+        Attempt {
+            puzzle,
+            placed: 0,
+            lines: 0,
+            status: Status::Playing,
+        }
+    }
+
+    pub fn pieces_left(&self) -> usize {
+        self.puzzle.pieces.len().saturating_sub(self.placed)
+    }
+
+    /// Updates the status after a piece got stuck.
+    pub fn on_lock(&mut self, lock: &Lock) {
+        if self.status != Status::Playing {
+            return;
+        }
+        self.placed += 1;
+        self.lines += lock.lines;
+        let solved = match self.puzzle.goal {
+            Goal::Lines(l) => self.lines >= l,
+            Goal::TSpin(l) => lock.tspin && lock.lines == l,
+        };
+        if solved {
+            self.status = Status::Solved;
+        } else if self.pieces_left() == 0 {
+            self.status = Status::Failed;
+        }
+    }
+
+    /// The game is over before the puzzle was solved.
+    pub fn on_game_over(&mut self) {
+        if self.status == Status::Playing {
+            self.status = Status::Failed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Event;
+    use crate::puzzle::*;
+
+    fn play_events(a: &mut Attempt, g: &mut Game) {
+        for e in g.events.drain(..) {
+            if let Event::Locked(lock) = e {
+                a.on_lock(&lock);
+            }
+        }
+    }
+
+    #[test]
+    fn bundled_puzzles_load() {
+        let puzzles = Puzzle::bundled();
+        assert_eq!(puzzles.len(), BUNDLED.len());
+        assert_eq!(puzzles[0].goal, Goal::TSpin(1));
+        assert_eq!(puzzles[1].pieces.len(), 5);
+        assert_eq!(puzzles[1].describe(), "Clear 4 lines with 5 pieces");
+    }
+
+    #[test]
+    fn bad_puzzles() {
+        assert!(Puzzle::parse("name = x\ngoal = lines 1\nboard =\nXX").is_err());
+        assert!(Puzzle::parse("name = x\ngoal = spin 1\npieces = T").is_err());
+        assert!(Puzzle::parse("name = x\ngoal = lines 1\npieces = Q").is_err());
+        assert!(Puzzle::parse("name = x\ngoal = lines 1\npieces = T\nboard =\nXX\nX").is_err());
+        assert!(Puzzle::parse("name = x\ngoal = lines 1\npieces = T\nboard =\nXY").is_err());
+    }
+
+    #[test]
+    fn fumen_board() {
+        let p = Puzzle::parse(
+            "name = x\ngoal = lines 4\npieces = I\nfumen = v115@9gI8AeI8AeI8AeI8KeAgH",
+        )
+        .unwrap();
+        let mut g = p.new_game(20).unwrap();
+        let mut a = Attempt::new(p);
+        g.draw();
+        g.soft_drop();
+        g.soft_drop();
+        g.up();
+        for _ in 0..5 {
+            g.right();
+        }
+        g.fall();
+        play_events(&mut a, &mut g);
+        assert_eq!(a.status, Status::Solved);
+    }
+
+    #[test]
+    fn board_at_bottom() {
+        let p = &Puzzle::bundled()[1];
+        let g = p.new_game(20).unwrap();
+        assert!(!g.board.is_set(5, 15));
+        assert!(g.board.is_set(4, 16));
+        assert!(g.board.is_set(0, 19));
+        assert!(!g.board.is_set(5, 19));
+    }
+
+    #[test]
+    fn tspin_single_solved() {
+        let p = Puzzle::bundled()[0].clone();
+        let mut g = p.new_game(20).unwrap();
+        g.draw();
+        let mut a = Attempt::new(p);
+        g.up();
+        g.left();
+        for _ in 0..15 {
+            g.soft_drop();
+        }
+        g.up();
+        g.fall();
+        play_events(&mut a, &mut g);
+        assert_eq!(a.lines, 1);
+        assert_eq!(a.status, Status::Solved);
+    }
+
+    #[test]
+    fn tspin_double_solved() {
+        let p = Puzzle::bundled()[3].clone();
+        let mut g = p.new_game(20).unwrap();
+        g.draw();
+        let mut a = Attempt::new(p);
+        g.rotate_ccw();
+        g.left();
+        for _ in 0..16 {
+            g.soft_drop();
+        }
+        g.up();
+        g.fall();
+        play_events(&mut a, &mut g);
+        assert_eq!(a.lines, 2);
+        assert_eq!(a.status, Status::Solved);
+    }
+
+    #[test]
+    fn plain_drop_is_no_tspin() {
+        let p = Puzzle::bundled()[0].clone();
+        let mut g = p.new_game(20).unwrap();
+        g.draw();
+        let mut a = Attempt::new(p);
+        g.fall();
+        play_events(&mut a, &mut g);
+        assert_eq!(a.placed, 1);
+        assert_eq!(a.status, Status::Failed);
+    }
+}
@@ -0,0 +1,151 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! How the blocks in the cells look: the colours and glyphs of the
+//! seven pieces, garbage and the pieces of piece sets.
+
+use crate::model::PieceType;
+
+/// A colour as red, green and blue.
+pub type Rgb = (u8, u8, u8);
+
+/// The looks of the blocks of a piece, or of garbage.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub piece_type: PieceType,
+    /// The true colour.
+    pub rgb: Rgb,
+    /// The colour in a 256 colour palette.
+    pub ansi256: u8,
+    /// One of the eight basic colours, for terminals without more.
+    pub basic: u8,
+    /// Drawn twice for each block when the glyphs are ascii.
+    pub glyph: char,
+}
+
+/// The built in materials, with their colours picked by hand: the
+/// letter, the true, 256 and basic colour. There's no basic orange,
+/// the L is white.
+const BUILT_IN: [(char, Rgb, u8, u8); 8] = [
+    ('I', (0, 240, 240), 51, 6),
+    ('J', (0, 0, 240), 21, 4),
+    ('L', (240, 160, 0), 208, 7),
+    ('O', (240, 240, 0), 226, 3),
+    ('S', (0, 240, 0), 46, 2),
+    ('T', (160, 0, 240), 129, 5),
+    ('Z', (240, 0, 0), 196, 1),
+    ('X', (128, 128, 128), 244, 7),
+];
+
+/// The colour in the 6x6x6 cube of a 256 colour palette closest to
+/// `rgb`.
+pub fn nearest_ansi256(rgb: Rgb) -> u8 {
+    let (r, g, b) = rgb;
+    let level = |c: u8| (u16::from(c) * 5 + 127) / 255;
+    (16 + 36 * level(r) + 6 * level(g) + level(b)) as u8
+}
+
+/// The basic colour closest to `rgb`, in the curses order: black, red,
+/// green, yellow, blue, magenta, cyan and white.
+pub fn nearest_basic(rgb: Rgb) -> u8 {
+    let (r, g, b) = rgb;
+    let on = |c: u8, bit: u8| if c >= 128 { bit } else { 0 };
+    match on(r, 1) | on(g, 2) | on(b, 4) {
+        // Black blocks wouldn't be seen.
+        0 => 7,
+        n => n,
+    }
+}
+
+impl Material {
+    /// A material in the colour `rgb`, or the closest one there is.
+    pub fn new(piece_type: PieceType, rgb: Rgb, glyph: char) -> Self {
+        Material {
+            piece_type,
+            rgb,
+            ansi256: nearest_ansi256(rgb),
+            basic: nearest_basic(rgb),
+            glyph,
+        }
+    }
+}
+
+/// The materials that can be in the cells.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Registry {
+    materials: Vec<Material>,
+}
+
+impl Default for Registry {
+    /// The seven pieces and garbage.
+    fn default() -> Self {
+        Registry {
+            materials: BUILT_IN
+                .iter()
+                .map(|&(c, rgb, ansi256, basic)| Material {
+                    piece_type: PieceType::from_char(c).expect("a built in piece"),
+                    rgb,
+                    ansi256,
+                    basic,
+                    glyph: 'X',
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Registry {
+    /// Adds a material, if its piece type has none yet.
+    pub fn register(&mut self, m: Material) -> Result<(), String> {
+        if self.get(m.piece_type).is_some() {
+            return Err(format!("{} is already taken", m.piece_type.to_char()));
+        }
+        self.materials.push(m);
+        Ok(())
+    }
+
+    pub fn get(&self, t: PieceType) -> Option<&Material> {
+        self.materials.iter().find(|m| m.piece_type == t)
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::registry::*;
+
+    #[test]
+    fn built_ins_are_there() {
+        let r = Registry::default();
+        assert_eq!(r.materials().len(), 8);
+        assert_eq!(r.get(PieceType::L).map(|m| m.ansi256), Some(208));
+        assert_eq!(r.get(PieceType::GARBAGE).map(|m| m.glyph), Some('X'));
+        assert_eq!(r.get(PieceType::NONE), None);
+    }
+
+    #[test]
+    fn registers_new_materials() {
+        let mut r = Registry::default();
+        let f = PieceType::custom('f').unwrap();
+        assert_eq!(r.register(Material::new(f, (240, 96, 0), '#')), Ok(()));
+        assert_eq!(r.get(f).map(|m| m.glyph), Some('#'));
+        assert_eq!(
+            r.register(Material::new(PieceType::I, (0, 0, 0), 'X')),
+            Err("I is already taken".to_string())
+        );
+    }
+
+    #[test]
+    fn colours_are_near() {
+        let orange = (240, 160, 0);
+        assert_eq!(nearest_ansi256(orange), 214);
+        // Yellow.
+        assert_eq!(nearest_basic(orange), 3);
+        // White, not black.
+        assert_eq!(nearest_basic((0, 0, 0)), 7);
+    }
+}
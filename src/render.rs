@@ -0,0 +1,99 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! What the view draws on and the game reads its keys from.
+//!
+//! The curses backend is built with the default `curses` feature, the
+//! crossterm backend with the `crossterm` feature. The latter needs no
+//! C libraries.
+
+/// A colour in the terminal's palette, or a true colour.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Colour {
+    Index(u8),
+    Rgb(u8, u8, u8),
+}
+
+pub const BLACK: Colour = Colour::Index(0);
+pub const YELLOW: Colour = Colour::Index(3);
+pub const WHITE: Colour = Colour::Index(7);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Style {
+    pub fg: Colour,
+    pub bg: Colour,
+}
+
+pub const TEXT: Style = Style {
+    fg: WHITE,
+    bg: BLACK,
+};
+
+/// The borders and most texts.
+pub const DECORATION: Style = Style {
+    fg: YELLOW,
+    bg: BLACK,
+};
+
+/// The selected menu item and banners.
+pub const SELECTED: Style = Style {
+    fg: BLACK,
+    bg: YELLOW,
+};
+
+/// The keys, named after the curses keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Character(char),
+    KeyUp,
+    KeyDown,
+    KeyLeft,
+    KeyRight,
+    KeyEnter,
+    KeyBackspace,
+    KeyDC,
+    KeyIC,
+    KeyHome,
+    KeyEnd,
+    KeyPPage,
+    KeyNPage,
+    KeyF(u8),
+    /// The terminal changed its size.
+    KeyResize,
+    // Curses gives the focus changes as escapes.
+    #[cfg_attr(not(feature = "crossterm"), allow(dead_code))]
+    FocusLost,
+    #[cfg_attr(not(feature = "crossterm"), allow(dead_code))]
+    FocusGained,
+}
+
+/// A terminal to draw on. The drawing is shown at `present`.
+pub trait Renderer {
+    /// The size in columns and lines.
+    fn size(&self) -> (i32, i32);
+
+    /// The number of colours in the palette.
+    fn colours(&self) -> i32;
+
+    /// If true colours can be shown.
+    fn can_change_colour(&self) -> bool;
+
+    /// Clears the screen.
+    fn erase(&self);
+
+    /// Writes `s` from column `x` on line `y`. What doesn't fit on the
+    /// line is cut.
+    fn put(&self, x: i32, y: i32, s: &str, style: Style);
+
+    /// Clears line `y` from column `x` to its end.
+    fn clear_line(&self, x: i32, y: i32);
+
+    fn present(&self);
+
+    /// The next key, without waiting for it.
+    fn read_key(&self) -> Option<Input>;
+
+    /// Gives the terminal back as it was.
+    fn end(&self);
+}
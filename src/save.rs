@@ -0,0 +1,418 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Saving and resuming games.
+//!
+//! A game is saved as text, one `key value` per line, after a first
+//! line with the format's version. Old versions must always be
+//! possible to load, so change VERSION and keep reading the old
+//! format if anything needs to change.
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::model;
+use crate::model::Board;
+use crate::model::Game;
+use crate::model::Piece;
+use crate::model::PieceFactory;
+use crate::model::PieceType;
+use crate::model::Random;
+use crate::model::SoftDrop;
+use crate::paths;
+use crate::stats::Stats;
+
+pub const VERSION: u32 = 1;
+
+const MAGIC: &str = "tetris-save";
+
+/// How a saved game is played.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Play {
+    /// The mode's name, as in the high scores.
+    pub mode: String,
+    /// Whether a finesse fault starts a new game.
+    pub restart_on_fault: bool,
+}
+
+/// Where the game is saved when quitting.
+pub fn default_path() -> Option<PathBuf> {
+    paths::data_dir().map(|d| d.join("save"))
+}
+
+/// Takes `p` off the board at the game's position. It must be drawn
+/// there, all of it on the board.
+fn take_off(g: &mut Game, p: &Piece) -> Result<(), String> {
+    let (x, y) = g.pos;
+    let cells = g.board.cells(p, x, y);
+    if cells.len() != p.curr().blocks().len()
+        || cells
+            .iter()
+            .any(|&(x, y)| g.board.cell(x, y) != p.piece_type)
+    {
+        return Err("the piece isn't on the board".to_string());
+    }
+    g.board.clear(p, x, y);
+    Ok(())
+}
+
+/// Takes the piece off the board, where it's drawn in the save, and
+/// checks that it fits there.
+fn put_back_piece(g: &mut Game) -> Result<(), String> {
+    let p = g.piece.clone();
+    take_off(g, &p)?;
+    if !g.fits() {
+        return Err("the piece doesn't fit".to_string());
+    }
+    g.draw();
+    Ok(())
+}
+
+fn piece_to_string(p: &Piece) -> String {
+    format!("{} {}", p.piece_type.to_char(), p.rotation())
+}
+
+pub fn save(g: &Game, play: &Play) -> String {
+    let f = &g.piece_factory;
+    let mut out = format!("{} {}\n", MAGIC, VERSION);
+    out.push_str(&format!("mode {}\n", play.mode));
+    out.push_str(&format!("restart {}\n", u8::from(play.restart_on_fault)));
+    out.push_str(&format!("score {}\n", g.score));
+    out.push_str(&format!("lines {}\n", g.lines));
+    out.push_str(&format!("time {}\n", g.time.as_millis()));
+    out.push_str(&format!("pos {} {}\n", g.pos.0, g.pos.1));
+    out.push_str(&format!("spun {}\n", u8::from(g.spun)));
+    out.push_str(&format!("piece {}\n", piece_to_string(&g.piece)));
+    out.push_str(&format!("next {}\n", piece_to_string(&g.next_piece)));
+    match &g.hold {
+        Some(p) => out.push_str(&format!("hold {}\n", piece_to_string(p))),
+        None => out.push_str("hold -\n"),
+    }
+    out.push_str(&format!("held {}\n", u8::from(g.held)));
+    out.push_str(&format!("stats {}\n", g.stats.to_line()));
+    let c = &g.stats.clears;
+    out.push_str(&format!(
+        "clears {} {} {} {} {}\n",
+        c[0], c[1], c[2], c[3], g.stats.tspins
+    ));
+    out.push_str(&format!("seed {}\n", f.seed));
+    out.push_str(&format!("random {}\n", f.rng.state));
+    let sequence = f.sequence.iter().map(|t| t.to_char()).collect::<String>();
+    out.push_str(&format!("sequence {}\n", sequence));
+    out.push_str(&format!("dealt {}\n", f.dealt));
+    out.push_str(&format!("size {} {}\n", g.board.width(), g.board.height()));
+    let width = g.board.width() as usize;
+    for row in g.board.map.chunks(width) {
+        let row = row.iter().map(|t| t.to_char()).collect::<String>();
+        out.push_str(&format!("row {}\n", row));
+    }
+    out
+}
+
+/// The values of a saved game, by key.
+struct Values<'a> {
+    lines: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Values<'a> {
+    fn get(&self, key: &str) -> Result<&'a str, String> {
+        self.lines
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| *v)
+            .ok_or_else(|| format!("missing {}", key))
+    }
+
+    fn numbers<T: std::str::FromStr>(&self, key: &str) -> Result<Vec<T>, String> {
+        self.get(key)?
+            .split_whitespace()
+            .map(|n| n.parse().map_err(|_| format!("bad {}: {}", key, n)))
+            .collect()
+    }
+
+    fn number<T: std::str::FromStr>(&self, key: &str) -> Result<T, String> {
+        self.get(key)?.parse().map_err(|_| format!("bad {}", key))
+    }
+
+    fn pair<T: std::str::FromStr + Copy>(&self, key: &str) -> Result<(T, T), String> {
+        match self.numbers(key)?[..] {
+            [a, b] => Ok((a, b)),
+            _ => Err(format!("bad {}", key)),
+        }
+    }
+
+    fn piece(&self, key: &str, factory: &PieceFactory) -> Result<Piece, String> {
+        let v = self.get(key)?;
+        let mut words = v.split_whitespace();
+        let mut p = words
+            .next()
+            .and_then(|t| t.chars().next())
+            .and_then(PieceType::from_char)
+            .and_then(|t| factory.piece(t))
+            .ok_or_else(|| format!("bad {}: {}", key, v))?;
+        let rotation = words
+            .next()
+            .and_then(|r| r.parse::<usize>().ok())
+            .filter(|r| *r < p.rotations())
+            .ok_or_else(|| format!("bad {} rotation: {}", key, v))?;
+        p.set_rotation(rotation);
+        Ok(p)
+    }
+}
+
+pub fn load(text: &str) -> Result<(Game, Play), String> {
+    let mut lines = text.lines();
+    let header = lines.next().unwrap_or("");
+    let version = match header.split_once(' ') {
+        Some((MAGIC, v)) => v.parse::<u32>().map_err(|_| "bad version".to_string())?,
+        _ => return Err("not a saved game".to_string()),
+    };
+    if version != VERSION {
+        return Err(format!("can't load version {} games", version));
+    }
+    let values = Values {
+        lines: lines
+            .filter(|l| !l.is_empty())
+            .map(|l| l.split_once(' ').unwrap_or((l, "")))
+            .collect(),
+    };
+
+    let play = Play {
+        mode: match values.get("mode")? {
+            "" => return Err("bad mode".to_string()),
+            mode => mode.to_string(),
+        },
+        restart_on_fault: values.number::<u8>("restart")? != 0,
+    };
+
+    let mut factory = PieceFactory::new();
+    factory.rng = Random::new(values.number("random")?);
+    factory.seed = values.number("seed")?;
+    factory.sequence = values
+        .get("sequence")?
+        .chars()
+        .map(|c| match PieceType::from_char(c) {
+            Some(PieceType::NONE) | Some(PieceType::GARBAGE) | None => {
+                Err(format!("bad piece in sequence: {}", c))
+            }
+            Some(t) => Ok(t),
+        })
+        .collect::<Result<_, _>>()?;
+    factory.dealt = values.number("dealt")?;
+
+    let (width, height) = values.pair::<u16>("size")?;
+    let rows = values
+        .lines
+        .iter()
+        .filter(|(k, _)| *k == "row")
+        .map(|(_, v)| *v)
+        .collect::<Vec<_>>();
+    if rows.len() != usize::from(height) {
+        return Err(format!("expected {} rows, got {}", height, rows.len()));
+    }
+    let board = Board::from_rows(width, height, &rows)?;
+
+    let mut stats = Stats::from_line(values.get("stats")?)?;
+    match values.numbers::<u32>("clears")?[..] {
+        [a, b, c, d, tspins] => {
+            stats.clears = [a, b, c, d];
+            stats.tspins = tspins;
+        }
+        _ => return Err("bad clears".to_string()),
+    }
+
+    let piece = values.piece("piece", &factory)?;
+    let next_piece = values.piece("next", &factory)?;
+    let mut g = Game {
+        piece,
+        next_piece,
+        board,
+        score: values.number("score")?,
+        lines: values.number("lines")?,
+        pos: values.pair("pos")?,
+        game_over: false,
+        moves: vec![],
+        spun: values.number::<u8>("spun")? != 0,
+        events: vec![],
+        time: Duration::from_millis(values.number("time")?),
+        gravity: model::GRAVITY,
+        fall_time: Duration::ZERO,
+        paused: false,
+        soft_drop_speed: SoftDrop::default(),
+        soft_dropping: Duration::ZERO,
+        line_clear_delay: Duration::ZERO,
+        clearing: Duration::ZERO,
+        hold: if values.get("hold")? != "-" {
+            Some(values.piece("hold", &factory)?)
+        } else {
+            None
+        },
+        held: values.number::<u8>("held")? != 0,
+        dirty: true,
+        stats,
+        piece_factory: factory,
+    };
+    put_back_piece(&mut g)?;
+    Ok((g, play))
+}
+
+/// Saves the game, without ever leaving a half written file behind.
+pub fn save_to(g: &Game, play: &Play, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    let mut f = fs::File::create(&tmp)?;
+    f.write_all(save(g, play).as_bytes())?;
+    f.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+pub fn load_from(path: &Path) -> Result<(Game, Play), String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    load(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot;
+    use crate::save::*;
+
+    fn marathon() -> Play {
+        Play {
+            mode: "marathon".to_string(),
+            restart_on_fault: false,
+        }
+    }
+
+    fn played_game() -> Game {
+        let mut g = Game::new(Board::new(10, 20), PieceFactory::with_seed(7));
+        g.draw();
+        bot::play(&mut g, &bot::Weights::default(), 30);
+        g
+    }
+
+    #[test]
+    fn round_trip() {
+        let g = played_game();
+        let text = save(&g, &marathon());
+        let g2 = load(&text).unwrap().0;
+        assert_eq!(save(&g2, &marathon()), text);
+        assert_eq!(g2.score, g.score);
+        assert_eq!(g2.lines, g.lines);
+        assert_eq!(g2.pos, g.pos);
+        assert_eq!(g2.board.map, g.board.map);
+    }
+
+    #[test]
+    fn play_round_trip() {
+        let play = Play {
+            mode: "finesse".to_string(),
+            restart_on_fault: true,
+        };
+        let text = save(&played_game(), &play);
+        assert!(text.starts_with("tetris-save 1\nmode finesse\nrestart 1\n"));
+        assert_eq!(load(&text).unwrap().1, play);
+        assert_eq!(
+            load(&save(&played_game(), &marathon())).unwrap().1,
+            marathon()
+        );
+    }
+
+    #[test]
+    fn resumed_game_continues_the_same() {
+        let mut g = played_game();
+        let mut g2 = load(&save(&g, &marathon())).unwrap().0;
+        bot::play(&mut g, &bot::Weights::default(), 30);
+        bot::play(&mut g2, &bot::Weights::default(), 30);
+        assert_eq!(save(&g2, &marathon()), save(&g, &marathon()));
+    }
+
+    #[test]
+    fn puzzle_sequence_round_trip() {
+        let p = crate::puzzle::Puzzle::bundled()[1].clone();
+        let mut g = p.new_game(20).unwrap();
+        g.draw();
+        g.fall();
+        let g2 = load(&save(&g, &marathon())).unwrap().0;
+        assert_eq!(g2.piece_factory.sequence, p.pieces);
+        assert_eq!(g2.piece_factory.dealt, 3);
+        assert_eq!(save(&g2, &marathon()), save(&g, &marathon()));
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let dir = std::env::temp_dir().join(format!("tetris-save-test-{}", std::process::id()));
+        let path = dir.join("save");
+        let g = played_game();
+        save_to(&g, &marathon(), &path).unwrap();
+        let g2 = load_from(&path).unwrap().0;
+        assert_eq!(save(&g2, &marathon()), save(&g, &marathon()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hold_round_trip() {
+        let mut g = played_game();
+        g.hold();
+        let g2 = load(&save(&g, &marathon())).unwrap().0;
+        let held = |g: &Game| g.hold.as_ref().map(|p| p.piece_type);
+        assert_eq!(held(&g2), held(&g));
+        assert!(g2.held);
+    }
+
+    #[test]
+    fn bad_saves() {
+        let text = save(&played_game(), &marathon());
+        assert!(load("").is_err());
+        assert!(load(&text.replace("tetris-save 1", "tetris-save 2")).is_err());
+        assert_eq!(
+            load(&text.replace("tetris-save 1", "tetris-save 0")).err(),
+            Some("can't load version 0 games".to_string())
+        );
+        assert!(load(&text.replace("mode marathon", "mode ")).is_err());
+        assert!(load(&text.replace("restart 0", "restart x")).is_err());
+        assert!(load(&text.replace("seed ", "sead ")).is_err());
+        assert!(load(&text.replace("held ", "hild ")).is_err());
+        assert!(load(&text.replace("score", "scare")).is_err());
+        assert!(load(&text.replace("size 10 20", "size 10 21")).is_err());
+        assert!(load(&text.replace("piece ", "piece Q")).is_err());
+        assert!(load(&text.replace("stats ", "stats x")).is_err());
+
+        // The piece must be drawn where it is, on the board.
+        let (x, y) = played_game().pos;
+        let pos = format!("pos {} {}", x, y);
+        let moved = |x: i32, y: i32| load(&text.replace(&pos, &format!("pos {} {}", x, y)));
+        assert!(moved(x, y).is_ok());
+        assert_eq!(
+            moved(-5, y).err(),
+            Some("the piece isn't on the board".to_string())
+        );
+        assert!(moved(x, y + 1).is_err());
+        assert!(moved(x, 19).is_err());
+        assert!(moved(x, 1000).is_err());
+    }
+
+    #[test]
+    fn stats_round_trip() {
+        let g = played_game();
+        assert_eq!(g.stats.pieces, 30);
+        assert_eq!(load(&save(&g, &marathon())).unwrap().0.stats, g.stats);
+    }
+
+    #[test]
+    fn clears_and_seed_round_trip() {
+        let g = played_game();
+        assert_eq!(g.seed(), 7);
+        assert_ne!(g.stats.clears, [0; 4]);
+        let g2 = load(&save(&g, &marathon())).unwrap().0;
+        assert_eq!(g2.seed(), 7);
+        assert_eq!(g2.stats.clears, g.stats.clears);
+        assert!(load(&save(&g, &marathon()).replace("clears ", "clears 1 ")).is_err());
+    }
+}
@@ -0,0 +1,1452 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The screens of the game and how to get between them.
+//!
+//! Every screen is a scene. Each turn of the main loop the current
+//! scene gets the pressed key, if any, and returns the next scene,
+//! which is then drawn.
+
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+
+use tetris::config;
+use tetris::config::Action;
+use tetris::config::Bindings;
+use tetris::config::Colours;
+use tetris::config::Config;
+use tetris::config::Glyphs;
+use tetris::config::Key as GameKey;
+use tetris::finesse;
+use tetris::fumen;
+use tetris::highscore;
+use tetris::history;
+use tetris::model::Board;
+use tetris::model::Event;
+use tetris::model::Game;
+use tetris::model::PieceFactory;
+use tetris::model::SoftDrop;
+#[cfg(feature = "music")]
+use tetris::music;
+use tetris::pieceset::PieceSet;
+use tetris::puzzle;
+use tetris::puzzle::Attempt;
+use tetris::puzzle::Puzzle;
+use tetris::registry::Registry;
+use tetris::save;
+use tetris::sound;
+
+use crate::layout::Layout;
+use crate::render::Input;
+use crate::render::Renderer;
+use crate::theme::Theme;
+use crate::view;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Marathon,
+    Finesse,
+}
+
+impl Mode {
+    /// The name of the mode's high score table.
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Marathon => "marathon",
+            Mode::Finesse => "finesse",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Mode> {
+        [Mode::Marathon, Mode::Finesse]
+            .into_iter()
+            .find(|m| m.name() == name)
+    }
+}
+
+/// The smallest board that can be played on.
+pub const MIN_WIDTH: u16 = 4;
+pub const MIN_HEIGHT: u16 = 10;
+
+pub struct Settings {
+    pub mode: Mode,
+    pub restart_on_fault: bool,
+    pub width: u16,
+    pub height: u16,
+    /// Other pieces to play with than the usual seven. Those games
+    /// aren't saved and have no high scores.
+    pub pieces: Option<PieceSet>,
+}
+
+/// The theme for the configured looks and the pieces played with.
+fn new_theme(config: &Config, settings: &Settings, screen: &dyn Renderer) -> Theme {
+    let registry = match &settings.pieces {
+        Some(set) => set.registry(),
+        None => Registry::default(),
+    };
+    Theme::new(config.glyphs, config.colours, &registry, screen)
+}
+
+/// The modes the current game is played in.
+pub struct Session {
+    pub trainer: Option<finesse::Trainer>,
+    pub puzzle: Option<Attempt>,
+    /// Positions exported as fumen strings.
+    pub exported: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Scene {
+    Title {
+        selected: usize,
+    },
+    /// The options, from the pause menu if `paused`.
+    Options {
+        selected: usize,
+        paused: bool,
+    },
+    /// The key bindings, waiting for a key to bind if `waiting`.
+    Keys {
+        selected: usize,
+        waiting: bool,
+        paused: bool,
+    },
+    Puzzles {
+        selected: usize,
+    },
+    Game,
+    Pause {
+        selected: usize,
+    },
+    GameOver,
+    NameEntry {
+        name: String,
+    },
+    HighScores {
+        mode: Mode,
+    },
+    /// The player's bests and how the last games went.
+    History,
+    Quit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TitleItem {
+    Play,
+    Resume,
+    Puzzles,
+    Options,
+    HighScores,
+    Statistics,
+    Quit,
+}
+
+const PAUSE_ITEMS: [&str; 4] = ["Resume", "Restart", "Settings", "Quit"];
+
+/// What a key means in a menu.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MenuKey {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Back,
+}
+
+const OPTIONS: usize = 13;
+const KEYS_OPTION: usize = 11;
+/// The soft drop speeds to choose from.
+const SOFT_DROPS: [SoftDrop; 6] = [
+    SoftDrop::Factor(2),
+    SoftDrop::Factor(5),
+    SoftDrop::Factor(10),
+    SoftDrop::Factor(20),
+    SoftDrop::Factor(40),
+    SoftDrop::Sonic,
+];
+/// The actions, then reset and back.
+const KEY_ITEMS: usize = Action::ALL.len() + 2;
+
+/// What came from the terminal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Key {
+    Pressed(Input),
+    /// Nothing was pressed.
+    Idle,
+    FocusLost,
+    FocusGained,
+}
+
+/// Picks out the focus events, ESC [ I and ESC [ O, that terminals
+/// send when asked to.
+#[derive(Default)]
+struct FocusFilter {
+    pending: Vec<Input>,
+}
+
+impl FocusFilter {
+    /// Returns the keys to handle. Keys that could be the start of a
+    /// focus event are held until the next key, or until there are no
+    /// more.
+    fn feed(&mut self, input: Option<Input>) -> Vec<Key> {
+        let input = match input {
+            Some(i) => i,
+            None => {
+                let mut keys = self.flush();
+                keys.push(Key::Idle);
+                return keys;
+            }
+        };
+        self.pending.push(input);
+        match self.pending.as_slice() {
+            [Input::Character('\x1b')] | [Input::Character('\x1b'), Input::Character('[')] => {
+                vec![]
+            }
+            [Input::Character('\x1b'), Input::Character('['), Input::Character('O')] => {
+                self.pending.clear();
+                vec![Key::FocusLost]
+            }
+            [Input::Character('\x1b'), Input::Character('['), Input::Character('I')] => {
+                self.pending.clear();
+                vec![Key::FocusGained]
+            }
+            // Some backends tell it without the escapes.
+            [.., Input::FocusLost] => {
+                self.pending.pop();
+                let mut keys = self.flush();
+                keys.push(Key::FocusLost);
+                keys
+            }
+            [.., Input::FocusGained] => {
+                self.pending.pop();
+                let mut keys = self.flush();
+                keys.push(Key::FocusGained);
+                keys
+            }
+            _ => self.flush(),
+        }
+    }
+
+    fn flush(&mut self) -> Vec<Key> {
+        self.pending.drain(..).map(Key::Pressed).collect()
+    }
+}
+
+fn menu_key(input: &Input) -> Option<MenuKey> {
+    match input {
+        Input::Character('k') | Input::KeyUp => Some(MenuKey::Up),
+        Input::Character('j') | Input::KeyDown => Some(MenuKey::Down),
+        Input::Character('h') | Input::KeyLeft => Some(MenuKey::Left),
+        Input::Character('l') | Input::KeyRight => Some(MenuKey::Right),
+        Input::Character('\n') | Input::Character(' ') | Input::KeyEnter => Some(MenuKey::Select),
+        Input::Character('q') | Input::Character('\x1b') => Some(MenuKey::Back),
+        _ => None,
+    }
+}
+
+/// The key the input is, if it can be bound.
+fn game_key(input: Input) -> Option<GameKey> {
+    Some(match input {
+        Input::Character('\n') => GameKey::Enter,
+        Input::Character('\x1b') => GameKey::Esc,
+        Input::Character('\t') => GameKey::Tab,
+        Input::Character('\x7f') | Input::Character('\x08') => GameKey::Backspace,
+        Input::Character(c) if !c.is_control() => GameKey::Char(c),
+        Input::KeyLeft => GameKey::Left,
+        Input::KeyRight => GameKey::Right,
+        Input::KeyUp => GameKey::Up,
+        Input::KeyDown => GameKey::Down,
+        Input::KeyEnter => GameKey::Enter,
+        Input::KeyBackspace => GameKey::Backspace,
+        Input::KeyDC => GameKey::Delete,
+        Input::KeyIC => GameKey::Insert,
+        Input::KeyHome => GameKey::Home,
+        Input::KeyEnd => GameKey::End,
+        Input::KeyPPage => GameKey::PageUp,
+        Input::KeyNPage => GameKey::PageDown,
+        Input::KeyF(n) if (1..=12).contains(&n) => GameKey::F(n),
+        _ => return None,
+    })
+}
+
+/// Moves the selection in a menu with `len` items.
+fn move_selection(key: MenuKey, selected: usize, len: usize) -> usize {
+    match key {
+        MenuKey::Up => (selected + len - 1) % len,
+        MenuKey::Down => (selected + 1) % len,
+        _ => selected,
+    }
+}
+
+/// The value after or before `v` in `all`, stopping at the ends.
+fn step_through<T: Copy + PartialEq>(all: &[T], v: T, up: bool) -> T {
+    let i = all.iter().position(|a| *a == v).unwrap_or(0);
+    if up {
+        all[(i + 1).min(all.len() - 1)]
+    } else {
+        all[i.saturating_sub(1)]
+    }
+}
+
+/// A setting, and what "auto" turned out to be.
+fn shown(setting: &str, used: &str) -> String {
+    if setting == used {
+        setting.to_string()
+    } else {
+        format!("{} ({})", setting, used)
+    }
+}
+
+pub struct App {
+    pub screen: Box<dyn Renderer>,
+    pub scene: Scene,
+    pub settings: Settings,
+    pub config: Config,
+    pub session: Session,
+    pub game: Option<Game>,
+    puzzles: Vec<Puzzle>,
+    last_tick: Instant,
+    focus: FocusFilter,
+    animations: view::Animations,
+    theme: Theme,
+    sound: sound::Player,
+    #[cfg(feature = "music")]
+    music: Option<music::Background>,
+    /// The game must be drawn even if it didn't change.
+    redraw: bool,
+    /// Something to tell the player on the title screen.
+    message: Option<String>,
+    /// What to tell when the game ends.
+    pub errors: Vec<String>,
+}
+
+impl App {
+    pub fn new(screen: Box<dyn Renderer>, settings: Settings, config: Config) -> Self {
+        App {
+            scene: Scene::Title { selected: 0 },
+            animations: view::Animations::new(!config.fast),
+            theme: new_theme(&config, &settings, &*screen),
+            screen,
+            sound: sound::Player::new(config.muted, config.volume),
+            #[cfg(feature = "music")]
+            music: None,
+            settings,
+            config,
+            session: Session {
+                trainer: None,
+                puzzle: None,
+                exported: vec![],
+            },
+            game: None,
+            puzzles: Puzzle::bundled(),
+            last_tick: Instant::now(),
+            focus: FocusFilter::default(),
+            redraw: true,
+            message: None,
+            errors: vec![],
+        }
+    }
+
+    fn title_items(&self) -> Vec<TitleItem> {
+        let mut items = vec![TitleItem::Play];
+        let saved = save::default_path().map_or(false, |p| p.exists());
+        if saved && self.settings.pieces.is_none() {
+            items.push(TitleItem::Resume);
+        }
+        items.extend([
+            TitleItem::Puzzles,
+            TitleItem::Options,
+            TitleItem::HighScores,
+            TitleItem::Statistics,
+            TitleItem::Quit,
+        ]);
+        items
+    }
+
+    /// Trains finesse if the settings' mode is the finesse mode.
+    fn set_trainer(&mut self) {
+        if self.settings.mode != Mode::Finesse {
+            self.session.trainer = None;
+        } else if self.session.trainer.is_none() {
+            self.session.trainer = Some(finesse::Trainer::new(self.settings.restart_on_fault));
+        }
+        if let Some(t) = &mut self.session.trainer {
+            t.restart_on_fault = self.settings.restart_on_fault;
+        }
+    }
+
+    /// Starts a game in the current settings' mode.
+    pub fn start(&mut self, puzzle: Option<Puzzle>) -> Scene {
+        self.set_trainer();
+        self.session.puzzle = puzzle.map(Attempt::new);
+        self.animations.skip();
+        self.game = Some(self.new_game());
+        self.enter_game()
+    }
+
+    /// Continues a saved game.
+    pub fn resume(&mut self) -> Scene {
+        let path = match save::default_path() {
+            Some(p) => p,
+            None => return self.title_message("Can't find the saved game, is $HOME set?"),
+        };
+        self.resume_from(&path)
+    }
+
+    /// Continues the game saved at `path`, in the mode it was played in.
+    fn resume_from(&mut self, path: &Path) -> Scene {
+        let loaded = save::load_from(path).and_then(|(g, play)| {
+            Mode::from_name(&play.mode)
+                .map(|mode| (g, mode, play.restart_on_fault))
+                .ok_or_else(|| format!("unknown mode {}", play.mode))
+        });
+        match loaded {
+            Ok((g, mode, restart_on_fault)) => {
+                self.settings.mode = mode;
+                self.settings.restart_on_fault = restart_on_fault;
+                self.set_trainer();
+                self.session.puzzle = None;
+                self.game = Some(g);
+                self.enter_game()
+            }
+            Err(e) => self.title_message(&format!("Can't resume: {}", e)),
+        }
+    }
+
+    /// How the current game is played, to save with it.
+    fn play(&self) -> save::Play {
+        let restart_on_fault = match &self.session.trainer {
+            Some(t) => t.restart_on_fault,
+            None => self.settings.restart_on_fault,
+        };
+        save::Play {
+            mode: self.mode().unwrap_or(self.settings.mode).name().to_string(),
+            restart_on_fault,
+        }
+    }
+
+    fn title_message(&mut self, message: &str) -> Scene {
+        self.message = Some(message.to_string());
+        Scene::Title { selected: 0 }
+    }
+
+    fn enter_game(&mut self) -> Scene {
+        if let Some(g) = &mut self.game {
+            g.resume();
+            g.soft_drop_speed = self.config.soft_drop;
+            g.line_clear_delay = self.config.clear_delay();
+        }
+        self.reset_view();
+        self.animations.set_enabled(!self.config.fast);
+        self.sound.muted = self.config.muted;
+        self.sound.volume = self.config.volume;
+        self.last_tick = Instant::now();
+        Scene::Game
+    }
+
+    fn enter_pause(&mut self, selected: usize) -> Scene {
+        if let Some(g) = &mut self.game {
+            g.pause();
+        }
+        self.reset_view();
+        Scene::Pause { selected }
+    }
+
+    /// Where the game goes on the screen, or the size the screen needs
+    /// to be when it doesn't fit.
+    fn layout(&self, g: &Game) -> Result<Layout, (i32, i32)> {
+        // Only the next piece is shown.
+        let queue = 1;
+        Layout::new(
+            g.board.width(),
+            g.board.height(),
+            queue,
+            g.preview_size(),
+            self.theme.geometry(),
+            self.config.stats,
+            self.screen.size(),
+        )
+    }
+
+    /// Clears the screen and draws the board's decoration again.
+    fn reset_view(&mut self) {
+        self.redraw = true;
+        if let Some(g) = &self.game {
+            match self.layout(g) {
+                Ok(l) => view::reset(&*self.screen, &self.theme, &l),
+                Err(needed) => view::draw_too_small(&*self.screen, needed),
+            }
+        }
+    }
+
+    /// Lays the game out again for the new size of the terminal.
+    fn resized(&mut self) {
+        if matches!(self.scene, Scene::Game | Scene::Pause { .. }) {
+            self.reset_view();
+        }
+    }
+
+    fn new_game(&mut self) -> Game {
+        let (width, height) = (self.settings.width, self.settings.height);
+        let mut g = match &mut self.session.puzzle {
+            Some(a) => {
+                *a = Attempt::new(a.puzzle.clone());
+                a.puzzle
+                    .new_game(height)
+                    .expect("puzzle was checked when loaded")
+            }
+            None => {
+                let factory = match &self.settings.pieces {
+                    Some(set) => set.factory(rand::random()),
+                    None => PieceFactory::new(),
+                };
+                Game::new(Board::new(width, height), factory)
+            }
+        };
+        g.draw();
+        g
+    }
+
+    fn mode(&self) -> Option<Mode> {
+        if self.session.puzzle.is_some() || self.settings.pieces.is_some() {
+            None
+        } else if self.session.trainer.is_some() {
+            Some(Mode::Finesse)
+        } else {
+            Some(Mode::Marathon)
+        }
+    }
+
+    /// Handles a key, or the lack of one, and moves to the next scene.
+    pub fn update(&mut self, input: Option<Input>) {
+        let scene = std::mem::replace(&mut self.scene, Scene::Quit);
+        self.scene = match scene {
+            Scene::Title { selected } => self.update_title(input, selected),
+            Scene::Options { selected, paused } => self.update_options(input, selected, paused),
+            Scene::Keys {
+                selected,
+                waiting,
+                paused,
+            } => self.update_keys(input, selected, waiting, paused),
+            Scene::Puzzles { selected } => self.update_puzzles(input, selected),
+            Scene::Game => self.update_game(input),
+            Scene::Pause { selected } => self.update_pause(input, selected),
+            Scene::GameOver => self.update_game_over(input),
+            Scene::NameEntry { name } => self.update_name_entry(input, name),
+            Scene::HighScores { mode } => self.update_high_scores(input, mode),
+            Scene::History => self.update_history(input),
+            Scene::Quit => Scene::Quit,
+        };
+    }
+
+    fn update_title(&mut self, input: Option<Input>, selected: usize) -> Scene {
+        let items = self.title_items();
+        let selected = selected.min(items.len() - 1);
+        match input.as_ref().and_then(menu_key) {
+            Some(MenuKey::Select) => {
+                self.message = None;
+                match items[selected] {
+                    TitleItem::Play => self.start(None),
+                    TitleItem::Resume => self.resume(),
+                    TitleItem::Puzzles => Scene::Puzzles { selected: 0 },
+                    TitleItem::Options => Scene::Options {
+                        selected: 0,
+                        paused: false,
+                    },
+                    TitleItem::HighScores => Scene::HighScores {
+                        mode: self.settings.mode,
+                    },
+                    TitleItem::Statistics => Scene::History,
+                    TitleItem::Quit => Scene::Quit,
+                }
+            }
+            Some(MenuKey::Back) => Scene::Quit,
+            Some(k) => Scene::Title {
+                selected: move_selection(k, selected, items.len()),
+            },
+            None => Scene::Title { selected },
+        }
+    }
+
+    fn change_option(&mut self, selected: usize, up: bool) {
+        let s = &mut self.settings;
+        let step = |v: u16, min: u16, max: u16| {
+            if up {
+                v.saturating_add(1).min(max)
+            } else {
+                v.saturating_sub(1).max(min)
+            }
+        };
+        match selected {
+            0 => {
+                s.mode = match s.mode {
+                    Mode::Marathon => Mode::Finesse,
+                    Mode::Finesse => Mode::Marathon,
+                }
+            }
+            1 => s.restart_on_fault = !s.restart_on_fault,
+            // Boards that don't fit show how big the terminal must be.
+            2 => s.width = step(s.width, MIN_WIDTH, u16::MAX),
+            3 => s.height = step(s.height, MIN_HEIGHT, u16::MAX),
+            4 => {
+                let i = SOFT_DROPS
+                    .iter()
+                    .position(|d| *d == self.config.soft_drop)
+                    .unwrap_or(0);
+                let i = if up {
+                    (i + 1).min(SOFT_DROPS.len() - 1)
+                } else {
+                    i.saturating_sub(1)
+                };
+                self.config.soft_drop = SOFT_DROPS[i];
+            }
+            5 => self.config.fast = !self.config.fast,
+            6 => self.config.muted = !self.config.muted,
+            7 => {
+                self.config.volume = if up {
+                    (self.config.volume + 1).min(sound::MAX_VOLUME)
+                } else {
+                    self.config.volume.saturating_sub(1)
+                }
+            }
+            8 => {
+                self.config.glyphs = step_through(&Glyphs::ALL, self.config.glyphs, up);
+                self.theme = new_theme(&self.config, &self.settings, &*self.screen);
+            }
+            9 => {
+                self.config.colours = step_through(&Colours::ALL, self.config.colours, up);
+                self.theme = new_theme(&self.config, &self.settings, &*self.screen);
+            }
+            10 => self.config.stats = !self.config.stats,
+            _ => (),
+        }
+    }
+
+    fn update_options(&mut self, input: Option<Input>, selected: usize, paused: bool) -> Scene {
+        let key = input.as_ref().and_then(menu_key);
+        let back =
+            key == Some(MenuKey::Back) || (key == Some(MenuKey::Select) && selected == OPTIONS - 1);
+        if back {
+            self.save_config();
+            return if paused {
+                self.enter_pause(2)
+            } else {
+                Scene::Title { selected: 0 }
+            };
+        }
+        match key {
+            Some(MenuKey::Select) if selected == KEYS_OPTION => {
+                return Scene::Keys {
+                    selected: 0,
+                    waiting: false,
+                    paused,
+                }
+            }
+            Some(MenuKey::Select) | Some(MenuKey::Right) => self.change_option(selected, true),
+            Some(MenuKey::Left) => self.change_option(selected, false),
+            Some(k) => {
+                return Scene::Options {
+                    selected: move_selection(k, selected, OPTIONS),
+                    paused,
+                }
+            }
+            None => (),
+        }
+        Scene::Options { selected, paused }
+    }
+
+    fn update_keys(
+        &mut self,
+        input: Option<Input>,
+        selected: usize,
+        waiting: bool,
+        paused: bool,
+    ) -> Scene {
+        let keys = |selected, waiting| Scene::Keys {
+            selected,
+            waiting,
+            paused,
+        };
+        let input = match input {
+            Some(i) => i,
+            None => return keys(selected, waiting),
+        };
+        if waiting {
+            if let Some(key) = game_key(input) {
+                let action = Action::ALL[selected];
+                self.message = self.config.bindings.bind(action, key).err();
+                return keys(selected, false);
+            }
+            return keys(selected, true);
+        }
+        self.message = None;
+        let back = selected == KEY_ITEMS - 1;
+        match menu_key(&input) {
+            Some(MenuKey::Back) => self.leave_keys(paused),
+            Some(MenuKey::Select) if back => self.leave_keys(paused),
+            Some(MenuKey::Select) if selected == KEY_ITEMS - 2 => {
+                self.config.bindings = Bindings::default();
+                keys(selected, false)
+            }
+            Some(MenuKey::Select) => keys(selected, true),
+            Some(k) => keys(move_selection(k, selected, KEY_ITEMS), false),
+            None => {
+                if input == Input::Character('d') && selected < Action::ALL.len() {
+                    self.config.bindings.unbind(Action::ALL[selected]);
+                }
+                keys(selected, false)
+            }
+        }
+    }
+
+    /// Saves the settings that are kept between games.
+    fn save_config(&mut self) {
+        let result = match config::default_path() {
+            Some(path) => self
+                .config
+                .save_to(&path)
+                .map_err(|e| format!("Can't save {}: {}", path.display(), e)),
+            None => Err("Can't find where to save the settings, is $HOME set?".to_string()),
+        };
+        self.message = result.err();
+    }
+
+    /// Saves the bindings and goes back to the options.
+    fn leave_keys(&mut self, paused: bool) -> Scene {
+        self.save_config();
+        Scene::Options {
+            selected: KEYS_OPTION,
+            paused,
+        }
+    }
+
+    fn update_puzzles(&mut self, input: Option<Input>, selected: usize) -> Scene {
+        match input.as_ref().and_then(menu_key) {
+            Some(MenuKey::Select) => self.start(Some(self.puzzles[selected].clone())),
+            Some(MenuKey::Back) => Scene::Title { selected: 0 },
+            Some(k) => Scene::Puzzles {
+                selected: move_selection(k, selected, self.puzzles.len()),
+            },
+            None => Scene::Puzzles { selected },
+        }
+    }
+
+    fn update_game(&mut self, input: Option<Input>) -> Scene {
+        let g = self.game.as_ref().expect("a game is played");
+        // The game can't be played blind.
+        if !g.game_over && self.layout(g).is_err() {
+            return self.enter_pause(0);
+        }
+        let g = self.game.as_mut().expect("a game is played");
+        g.tick(self.last_tick.elapsed());
+        self.last_tick = Instant::now();
+        // The game is over, the keys only skip the last animation.
+        if g.game_over && input.is_some() {
+            self.animations.skip();
+        }
+        let action = input
+            .filter(|_| !g.game_over)
+            .and_then(game_key)
+            .and_then(|k| self.config.bindings.action(k));
+        if let Some(action) = action {
+            match action {
+                Action::Quit => return self.quit_game(),
+                Action::Pause => return self.enter_pause(0),
+                Action::Restart => return self.restart(),
+                Action::MoveLeft => g.left(),
+                Action::MoveRight => g.right(),
+                Action::RotateCw => g.up(),
+                Action::RotateCcw => g.rotate_ccw(),
+                Action::Rotate180 => g.rotate_180(),
+                Action::SoftDrop => g.soft_drop(),
+                Action::HardDrop => g.fall(),
+                Action::Hold => g.hold(),
+                Action::Mute => {
+                    self.config.muted = !self.config.muted;
+                    self.sound.muted = self.config.muted;
+                }
+                Action::Export => {
+                    let s = fumen::export(g).unwrap_or_else(|e| format!("Can't export: {}", e));
+                    self.session.exported.push(s);
+                    self.redraw = true;
+                }
+                Action::Stats => self.config.stats = !self.config.stats,
+            }
+        }
+        if action == Some(Action::Mute) || action == Some(Action::Stats) {
+            self.save_config();
+        }
+        // The board moves over for the stats.
+        if action == Some(Action::Stats) {
+            self.reset_view();
+        }
+        self.handle_events();
+
+        let g = self.game.as_ref().expect("a game is played");
+        let puzzle_done = self
+            .session
+            .puzzle
+            .as_ref()
+            .map_or(false, |a| a.status != puzzle::Status::Playing);
+        if (g.game_over || puzzle_done) && !self.animations.is_running() {
+            if g.game_over {
+                if let Some(a) = &mut self.session.puzzle {
+                    a.on_game_over();
+                }
+            }
+            self.remove_save();
+            self.record_history();
+            return Scene::GameOver;
+        }
+        Scene::Game
+    }
+
+    fn handle_events(&mut self) {
+        let g = self.game.as_mut().expect("a game is played");
+        let mut restart = false;
+        for e in std::mem::take(&mut g.events) {
+            self.animations.on_event(&e, g);
+            self.sound.on_event(&e);
+            if let Event::Locked(lock) = e {
+                if let Some(t) = &mut self.session.trainer {
+                    restart |= t.check(&lock, g.board.width()) && t.restart_on_fault;
+                }
+                if let Some(a) = &mut self.session.puzzle {
+                    a.on_lock(&lock);
+                }
+            }
+        }
+        if restart {
+            self.animations.skip();
+            self.game = Some(self.new_game());
+        }
+    }
+
+    /// Leaves the game, saving it if it can be resumed.
+    fn quit_game(&mut self) -> Scene {
+        if let Some(g) = &mut self.game {
+            // Don't save the full rows, they were as good as gone.
+            if g.is_clearing() {
+                g.finish_clearing();
+            }
+            if g.game_over {
+                self.remove_save();
+                self.game = None;
+            }
+        }
+        if self.session.puzzle.is_none() && self.settings.pieces.is_none() {
+            if let Some(g) = &self.game {
+                let result = match save::default_path() {
+                    Some(path) => save::save_to(g, &self.play(), &path)
+                        .map_err(|e| format!("Can't save to {}: {}", path.display(), e)),
+                    None => Err("Can't find where to save the game, is $HOME set?".to_string()),
+                };
+                if let Err(e) = result {
+                    return self.title_message(&e);
+                }
+            }
+        }
+        self.game = None;
+        Scene::Title { selected: 0 }
+    }
+
+    /// A finished game can't be resumed.
+    fn remove_save(&mut self) {
+        if self.session.puzzle.is_some() || self.settings.pieces.is_some() {
+            return;
+        }
+        if let Some(path) = save::default_path() {
+            if path.exists() {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    self.errors
+                        .push(format!("Can't remove {}: {}", path.display(), e));
+                }
+            }
+        }
+    }
+
+    fn update_pause(&mut self, input: Option<Input>, selected: usize) -> Scene {
+        let action = input
+            .and_then(game_key)
+            .and_then(|k| self.config.bindings.action(k));
+        match action {
+            Some(Action::Pause) => return self.enter_game(),
+            Some(Action::Quit) => return self.quit_game(),
+            _ => (),
+        }
+        // Only Esc backs out of the menu, q leaves the game everywhere.
+        let escape = input == Some(Input::Character('\x1b'));
+        match input.as_ref().and_then(menu_key) {
+            Some(MenuKey::Back) if escape => self.enter_game(),
+            Some(MenuKey::Back) => Scene::Pause { selected },
+            Some(MenuKey::Select) => match selected {
+                0 => self.enter_game(),
+                1 => self.restart(),
+                2 => Scene::Options {
+                    selected: 0,
+                    paused: true,
+                },
+                _ => self.quit_game(),
+            },
+            Some(k) => Scene::Pause {
+                selected: move_selection(k, selected, PAUSE_ITEMS.len()),
+            },
+            None => Scene::Pause { selected },
+        }
+    }
+
+    /// Starts the game over, in the same puzzle if there is one.
+    fn restart(&mut self) -> Scene {
+        let puzzle = self.session.puzzle.as_ref().map(|a| a.puzzle.clone());
+        self.start(puzzle)
+    }
+
+    /// Pauses the game when the player looks elsewhere.
+    fn focus_lost(&mut self) {
+        if self.scene == Scene::Game {
+            self.scene = self.enter_pause(0);
+        }
+    }
+
+    fn update_game_over(&mut self, input: Option<Input>) -> Scene {
+        // Not space, it's easy to press it too many times when the
+        // game ends.
+        let done = matches!(
+            input,
+            Some(Input::Character('\n')) | Some(Input::KeyEnter) | Some(Input::Character('q'))
+        );
+        if !done {
+            return Scene::GameOver;
+        }
+        let g = self.game.as_ref().expect("a game was played");
+        let mode = match self.mode() {
+            Some(mode) => mode,
+            None => return Scene::Title { selected: 0 },
+        };
+        match self.high_scores() {
+            Ok(h) => {
+                let (width, height) = (g.board.width(), g.board.height());
+                if h.qualifies(mode.name(), width, height, g.score) {
+                    Scene::NameEntry {
+                        name: std::env::var("USER").unwrap_or_default(),
+                    }
+                } else {
+                    Scene::HighScores { mode }
+                }
+            }
+            Err(e) => self.title_message(&e),
+        }
+    }
+
+    fn high_scores(&self) -> Result<highscore::HighScores, String> {
+        let path = highscore::default_path().ok_or("Can't find the high scores, is $HOME set?")?;
+        highscore::HighScores::load(&path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn record_high_score(&mut self, name: String) -> Scene {
+        let g = self.game.as_ref().expect("a game was played");
+        let mode = self.mode().expect("a mode with high scores");
+        let entry = highscore::Entry {
+            mode: mode.name().to_string(),
+            width: g.board.width(),
+            height: g.board.height(),
+            name,
+            score: g.score,
+            lines: g.lines,
+            level: g.level(),
+            seconds: g.time.as_secs(),
+            date: highscore::today(),
+        };
+        let result = match highscore::default_path() {
+            Some(path) => {
+                highscore::record(&path, entry).map_err(|e| format!("{}: {}", path.display(), e))
+            }
+            None => Err("Can't find the high scores, is $HOME set?".to_string()),
+        };
+        match result {
+            Ok(_) => Scene::HighScores { mode },
+            Err(e) => self.title_message(&e),
+        }
+    }
+
+    fn update_name_entry(&mut self, input: Option<Input>, mut name: String) -> Scene {
+        match input {
+            Some(Input::Character('\n')) | Some(Input::KeyEnter) => {
+                return self.record_high_score(name)
+            }
+            Some(Input::KeyBackspace)
+            | Some(Input::Character('\x7f'))
+            | Some(Input::Character('\x08')) => {
+                name.pop();
+            }
+            Some(Input::Character(c)) if !c.is_control() && name.chars().count() < 16 => {
+                name.push(c)
+            }
+            _ => (),
+        }
+        Scene::NameEntry { name }
+    }
+
+    fn update_high_scores(&mut self, input: Option<Input>, mode: Mode) -> Scene {
+        match input.as_ref().and_then(menu_key) {
+            Some(MenuKey::Left) | Some(MenuKey::Right) => Scene::HighScores {
+                mode: match mode {
+                    Mode::Marathon => Mode::Finesse,
+                    Mode::Finesse => Mode::Marathon,
+                },
+            },
+            Some(MenuKey::Select) | Some(MenuKey::Back) => {
+                self.game = None;
+                Scene::Title { selected: 0 }
+            }
+            _ => Scene::HighScores { mode },
+        }
+    }
+
+    /// Adds the finished game to the history.
+    fn record_history(&mut self) {
+        let g = self.game.as_ref().expect("a game was played");
+        let mode = match (&self.session.puzzle, &self.settings.pieces, self.mode()) {
+            (None, Some(set), _) => set.name.to_lowercase().replace(char::is_whitespace, "-"),
+            (_, _, mode) => mode.map_or("puzzle", |m| m.name()).to_string(),
+        };
+        let result = match history::default_path() {
+            Some(path) => history::record(&path, &history::Entry::new(g, &mode))
+                .map_err(|e| format!("Can't add the game to {}: {}", path.display(), e)),
+            None => Err("Can't find the history, is $HOME set?".to_string()),
+        };
+        if let Err(e) = result {
+            self.errors.push(e);
+        }
+    }
+
+    fn history(&self) -> Result<history::History, String> {
+        let path = history::default_path().ok_or("Can't find the history, is $HOME set?")?;
+        history::History::load(&path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn update_history(&mut self, input: Option<Input>) -> Scene {
+        match input.as_ref().and_then(menu_key) {
+            Some(MenuKey::Select) | Some(MenuKey::Back) => Scene::Title { selected: 0 },
+            _ => Scene::History,
+        }
+    }
+
+    fn option_items(&self) -> Vec<String> {
+        let s = &self.settings;
+        let mode = match s.mode {
+            Mode::Marathon => "Marathon",
+            Mode::Finesse => "Finesse trainer",
+        };
+        vec![
+            format!("Mode             < {} >", mode),
+            format!(
+                "Restart on fault < {} >",
+                if s.restart_on_fault { "on" } else { "off" }
+            ),
+            format!("Board width      < {} >", s.width),
+            format!("Board height     < {} >", s.height),
+            format!(
+                "Soft drop        < {} >",
+                match self.config.soft_drop {
+                    SoftDrop::Factor(f) => format!("{}x", f),
+                    SoftDrop::Sonic => "sonic".to_string(),
+                }
+            ),
+            format!(
+                "Animations       < {} >",
+                if self.config.fast { "off (fast)" } else { "on" }
+            ),
+            format!(
+                "Sound            < {} >",
+                if self.config.muted { "off" } else { "on" }
+            ),
+            format!("Volume           < {} >", self.config.volume),
+            format!(
+                "Glyphs           < {} >",
+                shown(self.config.glyphs.name(), self.theme.glyphs.name())
+            ),
+            format!(
+                "Colours          < {} >",
+                shown(self.config.colours.name(), self.theme.colours.name())
+            ),
+            format!(
+                "Stats            < {} >",
+                if self.config.stats { "on" } else { "off" }
+            ),
+            "Key bindings".to_string(),
+            "Back".to_string(),
+        ]
+    }
+
+    fn draw_game(&self) {
+        let g = self.game.as_ref().expect("a game is played");
+        let l = match self.layout(g) {
+            Ok(l) => l,
+            Err(needed) => return view::draw_too_small(&*self.screen, needed),
+        };
+        view::draw_in_win(g, &*self.screen, &self.theme, &l);
+        self.animations.draw(g, &*self.screen, &self.theme, &l);
+        if let Some(t) = &self.session.trainer {
+            view::draw_finesse(t, &*self.screen, &l);
+        }
+        if let Some(a) = &self.session.puzzle {
+            view::draw_puzzle(a, &*self.screen, &l);
+        }
+        if let Some(r) = l.stats {
+            view::draw_stats(g, &*self.screen, r);
+        }
+        if let Some(s) = self.session.exported.last() {
+            view::draw_export(s, &*self.screen, &l);
+        }
+    }
+
+    fn game_summary(&self) -> Vec<String> {
+        let g = self.game.as_ref().expect("a game was played");
+        let mut lines = vec![
+            format!("Score  {}", g.score),
+            format!("Lines  {}", g.lines),
+            format!("Level  {}", g.level()),
+            format!("Time   {}", highscore::format_time(g.time.as_secs())),
+            String::new(),
+        ];
+        lines.extend(g.stats.report(g.time));
+        if let Some(a) = &self.session.puzzle {
+            lines.push(String::new());
+            lines.push(format!(
+                "{}: {}",
+                a.puzzle.name,
+                match a.status {
+                    puzzle::Status::Solved => "solved!",
+                    _ => "failed",
+                }
+            ));
+        }
+        if let Some(t) = &self.session.trainer {
+            lines.push(String::new());
+            lines.push(t.report());
+        }
+        lines
+    }
+
+    pub fn draw(&self) {
+        let screen = &*self.screen;
+        match &self.scene {
+            Scene::Title { selected } => {
+                let items = self
+                    .title_items()
+                    .iter()
+                    .map(|i| {
+                        match i {
+                            TitleItem::Play => "Play",
+                            TitleItem::Resume => "Resume",
+                            TitleItem::Puzzles => "Puzzles",
+                            TitleItem::Options => "Options",
+                            TitleItem::HighScores => "High scores",
+                            TitleItem::Statistics => "Statistics",
+                            TitleItem::Quit => "Quit",
+                        }
+                        .to_string()
+                    })
+                    .collect::<Vec<_>>();
+                view::draw_title(screen, &items, *selected, self.message.as_deref());
+            }
+            Scene::Options { selected, .. } => {
+                view::draw_menu(screen, "Options", &self.option_items(), *selected);
+                view::draw_message(screen, OPTIONS, self.message.as_deref());
+            }
+            Scene::Keys {
+                selected, waiting, ..
+            } => {
+                let mut items = Action::ALL
+                    .iter()
+                    .map(|a| {
+                        let keys = self.config.bindings.keys(*a);
+                        let keys = keys.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+                        format!("{:24} {}", a.describe(), keys.join(", "))
+                    })
+                    .collect::<Vec<_>>();
+                items.push("Reset to defaults".to_string());
+                items.push("Back".to_string());
+                view::draw_menu(screen, "Key bindings", &items, *selected);
+                let help = if *waiting {
+                    "Press the key to add"
+                } else {
+                    "enter adds a key, d removes the keys, q goes back"
+                };
+                view::draw_message(screen, KEY_ITEMS, Some(help));
+                view::draw_message(screen, KEY_ITEMS + 1, self.message.as_deref());
+            }
+            Scene::Puzzles { selected } => {
+                let names = self
+                    .puzzles
+                    .iter()
+                    .map(|p| format!("{} - {}", p.name, p.describe()))
+                    .collect::<Vec<_>>();
+                view::draw_menu(screen, "Choose a puzzle", &names, *selected);
+            }
+            Scene::Game => self.draw_game(),
+            Scene::Pause { selected } => {
+                let g = self.game.as_ref().expect("a game is paused");
+                let items = PAUSE_ITEMS
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>();
+                match self.layout(g) {
+                    Ok(l) => view::draw_pause(g, screen, &self.theme, &l, &items, *selected),
+                    Err(needed) => view::draw_too_small(screen, needed),
+                }
+            }
+            Scene::GameOver => view::draw_game_over(screen, &self.game_summary()),
+            Scene::NameEntry { name } => view::draw_name_entry(screen, name),
+            Scene::HighScores { mode } => {
+                let scores = self.high_scores().unwrap_or_default();
+                let (width, height) = match &self.game {
+                    Some(g) => (g.board.width(), g.board.height()),
+                    None => (self.settings.width, self.settings.height),
+                };
+                view::draw_high_scores(
+                    screen,
+                    mode.name(),
+                    &scores.table(mode.name(), width, height),
+                );
+            }
+            Scene::History => match self.history() {
+                Ok(h) => view::draw_history(screen, &h.report()),
+                Err(e) => view::draw_history(screen, &[e]),
+            },
+            Scene::Quit => (),
+        }
+    }
+
+    /// Plays the music while a game is played, faster as it gets
+    /// harder, and stops it when the game is left.
+    #[cfg(feature = "music")]
+    fn update_music(&mut self) {
+        let on = self.config.music && !self.config.muted && self.config.volume > 0;
+        let g = match &self.game {
+            Some(g) => g,
+            None => {
+                self.music = None;
+                return;
+            }
+        };
+        if on && self.scene == Scene::Game && !g.game_over {
+            let m = self
+                .music
+                .get_or_insert_with(|| music::Background::start(&music::Song::korobeiniki()));
+            m.set_volume(f32::from(self.config.volume) / f32::from(sound::MAX_VOLUME));
+            m.set_speed(music::speed_for(g));
+            m.resume();
+        } else if let Some(m) = &self.music {
+            m.pause();
+        }
+    }
+
+    /// Runs scenes until the player quits.
+    pub fn run(&mut self) {
+        use std::thread;
+
+        while self.scene != Scene::Quit {
+            let input = self.screen.read_key();
+            if input.is_none() {
+                thread::sleep(Duration::from_millis(5));
+            }
+            for key in self.focus.feed(input) {
+                match key {
+                    Key::Pressed(Input::KeyResize) => self.resized(),
+                    Key::Pressed(i) => self.update(Some(i)),
+                    Key::Idle => self.update(None),
+                    Key::FocusLost => self.focus_lost(),
+                    Key::FocusGained => (),
+                }
+            }
+            #[cfg(feature = "music")]
+            self.update_music();
+            // The game is only drawn again when something changed, the
+            // menus are cheap.
+            let dirty = self.game.as_ref().map_or(false, |g| g.dirty);
+            let animating = self.animations.is_running();
+            if self.scene != Scene::Game || dirty || animating || self.redraw {
+                self.draw();
+                self.screen.present();
+            }
+            // One more time after the animations, without them.
+            self.redraw = animating;
+            if let Some(g) = &mut self.game {
+                g.dirty = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::Memory;
+    use crate::scene::*;
+
+    fn app(config: Config) -> App {
+        let settings = Settings {
+            mode: Mode::Marathon,
+            restart_on_fault: false,
+            width: 10,
+            height: 20,
+            pieces: None,
+        };
+        App::new(Box::new(Memory::new(80, 24)), settings, config)
+    }
+
+    #[test]
+    fn settings_stop_at_the_ends() {
+        assert_eq!(
+            step_through(&Glyphs::ALL, Glyphs::Auto, false),
+            Glyphs::Auto
+        );
+        assert_eq!(
+            step_through(&Glyphs::ALL, Glyphs::Auto, true),
+            Glyphs::Ascii
+        );
+        assert_eq!(
+            step_through(&Colours::ALL, Colours::TrueColour, true),
+            Colours::TrueColour
+        );
+    }
+
+    #[test]
+    fn selection_wraps() {
+        assert_eq!(2, move_selection(MenuKey::Up, 0, 3));
+        assert_eq!(0, move_selection(MenuKey::Down, 2, 3));
+        assert_eq!(1, move_selection(MenuKey::Left, 1, 3));
+    }
+
+    #[test]
+    fn resetting_the_keys_keeps_the_settings() {
+        let config = Config {
+            fast: true,
+            muted: true,
+            volume: 3,
+            stats: true,
+            ..Config::default()
+        };
+        let mut a = app(config.clone());
+        a.config.bindings.unbind(Action::Hold);
+        let scene = a.update_keys(Some(Input::Character('\n')), KEY_ITEMS - 2, false, false);
+        assert_eq!(
+            scene,
+            Scene::Keys {
+                selected: KEY_ITEMS - 2,
+                waiting: false,
+                paused: false
+            }
+        );
+        assert_eq!(a.config, config);
+    }
+
+    #[test]
+    fn the_pause_key_unpauses() {
+        let mut a = app(Config::default());
+        a.config.bindings.unbind(Action::Pause);
+        a.config
+            .bindings
+            .bind(Action::Pause, GameKey::Char('x'))
+            .unwrap();
+        let paused = Scene::Pause { selected: 1 };
+        assert_eq!(a.update_pause(Some(Input::Character('p')), 1), paused);
+        assert_eq!(a.update_pause(Some(Input::Character('x')), 1), Scene::Game);
+    }
+
+    #[test]
+    fn boards_can_be_big() {
+        let mut a = app(Config::default());
+        a.settings.width = 30;
+        a.settings.height = 40;
+        a.change_option(2, true);
+        a.change_option(3, true);
+        assert_eq!((a.settings.width, a.settings.height), (31, 41));
+        a.settings.width = 1000;
+        a.settings.height = 1000;
+        assert_eq!(a.start(None), Scene::Game);
+        a.update(None);
+        a.draw();
+    }
+
+    #[test]
+    fn quit_leaves_the_pause_menu() {
+        let mut a = app(Config::default());
+        let paused = Scene::Pause { selected: 0 };
+        assert_eq!(
+            a.update_pause(Some(Input::Character('q')), 0),
+            Scene::Title { selected: 0 }
+        );
+        assert_eq!(
+            a.update_pause(Some(Input::Character('\x1b')), 0),
+            Scene::Game
+        );
+        a.config.bindings.unbind(Action::Quit);
+        assert_eq!(a.update_pause(Some(Input::Character('q')), 0), paused);
+    }
+
+    #[test]
+    fn resumed_games_keep_their_mode() {
+        let dir = std::env::temp_dir().join(format!("tetris-resume-test-{}", std::process::id()));
+        let path = dir.join("save");
+        let mut a = app(Config::default());
+        a.settings.mode = Mode::Finesse;
+        a.settings.restart_on_fault = true;
+        a.start(None);
+        let g = a.game.as_ref().unwrap();
+        save::save_to(g, &a.play(), &path).unwrap();
+
+        // Resumed after a marathon, it's still trained.
+        let mut b = app(Config::default());
+        b.start(None);
+        assert_eq!(b.resume_from(&path), Scene::Game);
+        assert_eq!(b.mode(), Some(Mode::Finesse));
+        assert!(b.session.trainer.as_ref().unwrap().restart_on_fault);
+
+        // And a marathon resumed after it isn't.
+        let g = b.game.as_ref().unwrap();
+        let marathon = save::Play {
+            mode: "marathon".to_string(),
+            restart_on_fault: false,
+        };
+        save::save_to(g, &marathon, &path).unwrap();
+        assert_eq!(b.resume_from(&path), Scene::Game);
+        assert_eq!(b.mode(), Some(Mode::Marathon));
+        assert!(b.session.trainer.is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn focus_events_are_picked_out() {
+        let mut f = FocusFilter::default();
+        assert_eq!(f.feed(Some(Input::Character('\x1b'))), vec![]);
+        assert_eq!(f.feed(Some(Input::Character('['))), vec![]);
+        assert_eq!(f.feed(Some(Input::Character('O'))), vec![Key::FocusLost]);
+        assert_eq!(
+            f.feed(Some(Input::Character('h'))),
+            vec![Key::Pressed(Input::Character('h'))]
+        );
+    }
+
+    #[test]
+    fn escape_alone_is_a_key() {
+        let mut f = FocusFilter::default();
+        assert_eq!(f.feed(Some(Input::Character('\x1b'))), vec![]);
+        assert_eq!(
+            f.feed(None),
+            vec![Key::Pressed(Input::Character('\x1b')), Key::Idle]
+        );
+        f.feed(Some(Input::Character('\x1b')));
+        assert_eq!(
+            f.feed(Some(Input::Character('q'))),
+            vec![
+                Key::Pressed(Input::Character('\x1b')),
+                Key::Pressed(Input::Character('q'))
+            ]
+        );
+    }
+}
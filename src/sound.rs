@@ -0,0 +1,197 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sound effects for what happens in the game.
+//!
+//! With the `audio` feature the samples in the `sounds` directory are
+//! played on the sound card. Without it, or without a sound card, the
+//! terminal bell rings for the bigger events instead.
+
+use std::io::Write;
+
+use crate::model::Event;
+use crate::model::Move;
+
+pub const MAX_VOLUME: u8 = 10;
+pub const DEFAULT_VOLUME: u8 = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sound {
+    Move,
+    Rotate,
+    Lock,
+    /// One to four lines were cleared.
+    Clear(usize),
+    TSpin,
+    LevelUp,
+    GameOver,
+}
+
+impl Sound {
+    /// The sounds that go with an event.
+    pub fn for_event(e: &Event) -> Vec<Sound> {
+        match e {
+            Event::Moved(Move::Left) | Event::Moved(Move::Right) => vec![Sound::Move],
+            Event::Moved(_) => vec![Sound::Rotate],
+            Event::Locked(lock) if lock.tspin => vec![Sound::Lock, Sound::TSpin],
+            Event::Locked(_) => vec![Sound::Lock],
+            Event::Cleared(rows) => vec![Sound::Clear(rows.len().min(4))],
+            Event::LevelUp(_) => vec![Sound::LevelUp],
+            Event::GameOver => vec![Sound::GameOver],
+            Event::HardDropped { .. } => vec![],
+        }
+    }
+
+    /// Moves and locks come too often to ring the bell for.
+    fn rings_bell(self) -> bool {
+        !matches!(self, Sound::Move | Sound::Rotate | Sound::Lock)
+    }
+}
+
+#[cfg(feature = "audio")]
+mod device {
+    use std::io::Cursor;
+
+    use rodio::Decoder;
+    use rodio::OutputStream;
+    use rodio::OutputStreamHandle;
+    use rodio::Sink;
+
+    use crate::sound::Sound;
+
+    /// The sound card.
+    pub struct Device {
+        // The sound stops when the stream is dropped.
+        _stream: OutputStream,
+        handle: OutputStreamHandle,
+    }
+
+    fn sample(s: Sound) -> &'static [u8] {
+        match s {
+            Sound::Move => include_bytes!("../sounds/move.wav"),
+            Sound::Rotate => include_bytes!("../sounds/rotate.wav"),
+            Sound::Lock => include_bytes!("../sounds/lock.wav"),
+            Sound::Clear(1) => include_bytes!("../sounds/clear1.wav"),
+            Sound::Clear(2) => include_bytes!("../sounds/clear2.wav"),
+            Sound::Clear(3) => include_bytes!("../sounds/clear3.wav"),
+            Sound::Clear(_) => include_bytes!("../sounds/clear4.wav"),
+            Sound::TSpin => include_bytes!("../sounds/tspin.wav"),
+            Sound::LevelUp => include_bytes!("../sounds/levelup.wav"),
+            Sound::GameOver => include_bytes!("../sounds/gameover.wav"),
+        }
+    }
+
+    impl Device {
+        pub fn open() -> Option<Device> {
+            let (stream, handle) = OutputStream::try_default().ok()?;
+            Some(Device {
+                _stream: stream,
+                handle,
+            })
+        }
+
+        /// Plays the sound beside the others, volume is from 0 to 1.
+        pub fn play(&self, s: Sound, volume: f32) {
+            let source = match Decoder::new(Cursor::new(sample(s))) {
+                Ok(source) => source,
+                Err(_) => return,
+            };
+            if let Ok(sink) = Sink::try_new(&self.handle) {
+                sink.set_volume(volume);
+                sink.append(source);
+                sink.detach();
+            }
+        }
+    }
+}
+
+enum Output {
+    Bell,
+    #[cfg(feature = "audio")]
+    Device(device::Device),
+}
+
+/// Plays the sounds, as well as it can.
+pub struct Player {
+    pub muted: bool,
+    /// From 0 to MAX_VOLUME.
+    pub volume: u8,
+    output: Output,
+}
+
+impl Player {
+    /// Opens the sound card if there is one, or else uses the bell.
+    pub fn new(muted: bool, volume: u8) -> Self {
+        #[cfg(feature = "audio")]
+        let output = device::Device::open().map_or(Output::Bell, Output::Device);
+        #[cfg(not(feature = "audio"))]
+        let output = Output::Bell;
+        Player {
+            muted,
+            volume,
+            output,
+        }
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.muted || self.volume == 0
+    }
+
+    pub fn play(&self, s: Sound) {
+        if self.is_silent() {
+            return;
+        }
+        match &self.output {
+            Output::Bell => {
+                if s.rings_bell() {
+                    let mut out = std::io::stdout();
+                    let _ = out.write_all(b"\x07");
+                    let _ = out.flush();
+                }
+            }
+            #[cfg(feature = "audio")]
+            Output::Device(d) => d.play(s, f32::from(self.volume) / f32::from(MAX_VOLUME)),
+        }
+    }
+
+    /// Plays the sounds that go with an event.
+    pub fn on_event(&self, e: &Event) {
+        for s in Sound::for_event(e) {
+            self.play(s);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Board;
+    use crate::model::Game;
+    use crate::model::PieceFactory;
+    use crate::sound::*;
+
+    #[test]
+    fn events_make_sounds() {
+        let mut g = Game::new(Board::new(10, 20), PieceFactory::with_seed(1));
+        g.left();
+        g.up();
+        g.fall();
+        let sounds = g
+            .events
+            .iter()
+            .flat_map(Sound::for_event)
+            .collect::<Vec<_>>();
+        assert_eq!(sounds, vec![Sound::Move, Sound::Rotate, Sound::Lock]);
+        assert_eq!(
+            Sound::for_event(&Event::Cleared(vec![19, 18])),
+            vec![Sound::Clear(2)]
+        );
+    }
+
+    #[test]
+    fn silent_when_muted() {
+        assert!(Player::new(true, 5).is_silent());
+        assert!(Player::new(false, 0).is_silent());
+        assert!(!Player::new(false, 5).is_silent());
+    }
+}
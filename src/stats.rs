@@ -0,0 +1,262 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! How fast and how well a game is played.
+//!
+//! The rates are per the game's time, which stands still while paused.
+
+use std::time::Duration;
+
+use crate::finesse;
+use crate::model::Lock;
+use crate::model::PieceType;
+
+/// The pieces in the order they are counted and shown.
+const PIECES: &str = "IJLOSTZ";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    /// The pieces that got stuck.
+    pub pieces: u32,
+    /// The keys pressed to move, drop and hold the pieces.
+    pub keys: u32,
+    /// The lines the clears would send to an opponent.
+    pub attack: u32,
+    /// Key presses that weren't needed, see the finesse module.
+    pub faults: u32,
+    /// The singles, doubles, triples and tetrises.
+    pub clears: [u32; 4],
+    /// The T-spins that cleared lines.
+    pub tspins: u32,
+    /// The stuck pieces of each type.
+    pub counts: Vec<(PieceType, u32)>,
+    /// Pieces since the last I piece.
+    pub drought: u32,
+    pub longest_drought: u32,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats::new(PIECES.chars().filter_map(PieceType::from_char).collect())
+    }
+}
+
+/// The lines sent for clearing `lines` lines, as in most versus games.
+pub fn attack(lines: usize, tspin: bool) -> u32 {
+    match (lines, tspin) {
+        (0, _) => 0,
+        (n, true) => 2 * n as u32,
+        (4, false) => 4,
+        (n, false) => n as u32 - 1,
+    }
+}
+
+/// Something per second of `time`, none before any time has passed.
+pub fn per_second(n: u32, time: Duration) -> f64 {
+    if time.is_zero() {
+        return 0.0;
+    }
+    f64::from(n) / time.as_secs_f64()
+}
+
+impl Stats {
+    /// Stats that count the pieces of `types`, shown in the order of
+    /// their letters.
+    pub fn new(mut types: Vec<PieceType>) -> Self {
+        types.sort_by_key(|t| t.to_char());
+        Stats {
+            pieces: 0,
+            keys: 0,
+            attack: 0,
+            faults: 0,
+            clears: [0; 4],
+            tspins: 0,
+            counts: types.into_iter().map(|t| (t, 0)).collect(),
+            drought: 0,
+            longest_drought: 0,
+        }
+    }
+
+    /// Counts a piece that got stuck on a board `width` wide.
+    pub fn on_lock(&mut self, lock: &Lock, width: u16) {
+        self.pieces += 1;
+        self.keys += lock.moves.len() as u32;
+        self.attack += attack(lock.lines, lock.tspin);
+        if (1..=4).contains(&lock.lines) {
+            self.clears[lock.lines - 1] += 1;
+            if lock.tspin {
+                self.tspins += 1;
+            }
+        }
+        if let Some(f) = finesse::analyse(lock, width) {
+            self.faults += f.faults as u32;
+        }
+        if let Some((_, n)) = self
+            .counts
+            .iter_mut()
+            .find(|(t, _)| *t == lock.piece.piece_type)
+        {
+            *n += 1;
+        }
+        if lock.piece.piece_type == PieceType::I {
+            self.drought = 0;
+        } else {
+            self.drought += 1;
+            self.longest_drought = self.longest_drought.max(self.drought);
+        }
+    }
+
+    /// Pieces per second.
+    pub fn pps(&self, time: Duration) -> f64 {
+        per_second(self.pieces, time)
+    }
+
+    /// Attack per minute.
+    pub fn apm(&self, time: Duration) -> f64 {
+        60.0 * per_second(self.attack, time)
+    }
+
+    /// Keys per piece.
+    pub fn kpp(&self) -> f64 {
+        if self.pieces == 0 {
+            return 0.0;
+        }
+        f64::from(self.keys) / f64::from(self.pieces)
+    }
+
+    /// The stuck pieces of each type, like "I 3  J 5".
+    pub fn describe_counts(&self) -> String {
+        self.counts
+            .iter()
+            .map(|(t, n)| format!("{} {}", t.to_char(), n))
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+
+    /// The stats of a game played for `time`, for after the game.
+    pub fn report(&self, time: Duration) -> Vec<String> {
+        vec![
+            format!("Pieces {}, {:.2} a second", self.pieces, self.pps(time)),
+            format!("Attack {}, {:.1} a minute", self.attack, self.apm(time)),
+            format!("Keys   {}, {:.2} a piece", self.keys, self.kpp()),
+            format!("Faults {}", self.faults),
+            format!("Longest I drought {}", self.longest_drought),
+            self.describe_clears(),
+            self.describe_counts(),
+        ]
+    }
+
+    /// The lines cleared at once, like "Singles 3  Doubles 1".
+    pub fn describe_clears(&self) -> String {
+        format!(
+            "Singles {}  Doubles {}  Triples {}  Tetrises {}  T-spins {}",
+            self.clears[0], self.clears[1], self.clears[2], self.clears[3], self.tspins
+        )
+    }
+
+    /// The stats in one line, as they are saved. The clears are saved
+    /// apart, they came later.
+    pub fn to_line(&self) -> String {
+        let mut numbers = vec![
+            self.pieces,
+            self.keys,
+            self.attack,
+            self.faults,
+            self.drought,
+            self.longest_drought,
+        ];
+        numbers.extend(self.counts.iter().map(|(_, n)| n));
+        numbers
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn from_line(line: &str) -> Result<Stats, String> {
+        let numbers = line
+            .split_whitespace()
+            .map(|n| n.parse::<u32>().map_err(|_| format!("bad stats: {}", n)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut s = Stats::default();
+        if numbers.len() != 6 + s.counts.len() {
+            return Err(format!("bad stats: {}", line));
+        }
+        s.pieces = numbers[0];
+        s.keys = numbers[1];
+        s.attack = numbers[2];
+        s.faults = numbers[3];
+        s.drought = numbers[4];
+        s.longest_drought = numbers[5];
+        for ((_, n), m) in s.counts.iter_mut().zip(&numbers[6..]) {
+            *n = *m;
+        }
+        Ok(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Board;
+    use crate::model::Game;
+    use crate::model::Lock;
+    use crate::model::PieceFactory;
+    use crate::stats::*;
+
+    #[test]
+    fn attack_table() {
+        assert_eq!(attack(1, false), 0);
+        assert_eq!(attack(2, false), 1);
+        assert_eq!(attack(3, false), 2);
+        assert_eq!(attack(4, false), 4);
+        assert_eq!(attack(2, true), 4);
+        assert_eq!(attack(0, true), 0);
+    }
+
+    #[test]
+    fn counts_the_clears() {
+        let t = PieceFactory::new().piece(PieceType::T).unwrap();
+        let lock = |lines: usize, tspin: bool| Lock {
+            piece: t.clone(),
+            pos: (4, 18),
+            moves: vec![],
+            lines,
+            tspin,
+        };
+        let mut s = Stats::default();
+        for (lines, tspin) in [(1, false), (4, false), (2, true), (0, true)] {
+            s.on_lock(&lock(lines, tspin), 10);
+        }
+        assert_eq!(s.clears, [1, 1, 0, 1]);
+        assert_eq!(s.tspins, 1);
+        assert_eq!(s.attack, 8);
+    }
+
+    #[test]
+    fn counts_the_stuck_pieces() {
+        let t = [PieceType::O, PieceType::T, PieceType::I];
+        let mut g = Game::new(Board::new(10, 20), PieceFactory::from_sequence(t.to_vec()));
+        g.draw();
+        // Two presses more than needed for the O.
+        g.left();
+        g.right();
+        g.fall();
+        g.fall();
+        g.hold();
+        g.fall();
+        let s = &g.stats;
+        assert_eq!(s.pieces, 3);
+        assert_eq!(s.keys, 6);
+        assert_eq!(s.faults, 2);
+        assert_eq!(s.drought, 3);
+        assert_eq!(s.longest_drought, 3);
+        let count = |t: PieceType| s.counts.iter().find(|(c, _)| *c == t).unwrap().1;
+        assert_eq!(count(PieceType::O), 2);
+        assert_eq!(count(PieceType::T), 1);
+        assert_eq!(count(PieceType::I), 0);
+        assert!((s.kpp() - 2.0).abs() < 1e-9);
+        assert!((s.pps(Duration::from_secs(2)) - 1.5).abs() < 1e-9);
+        assert_eq!(Stats::from_line(&s.to_line()), Ok(s.clone()));
+    }
+}
@@ -0,0 +1,267 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The crossterm backend, written in Rust all the way down.
+
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::io::BufWriter;
+use std::io::Stdout;
+use std::io::Write;
+use std::time::Duration;
+
+use crossterm::cursor;
+use crossterm::event;
+use crossterm::event::Event;
+use crossterm::event::KeyCode;
+use crossterm::event::KeyEventKind;
+use crossterm::event::KeyModifiers;
+use crossterm::style;
+use crossterm::terminal;
+use crossterm::terminal::ClearType;
+use crossterm::QueueableCommand;
+
+use crate::memory::Memory;
+use crate::render::Colour;
+use crate::render::Input;
+use crate::render::Renderer;
+use crate::render::Style;
+
+/// Draws on a terminal, sending only what changed since the last
+/// frame.
+pub struct Terminal<W: Write> {
+    out: RefCell<W>,
+    colours: i32,
+    size: Cell<(i32, i32)>,
+    /// The frame being drawn.
+    next: RefCell<Memory>,
+    /// What the terminal shows.
+    shown: RefCell<Memory>,
+    /// The terminal must be cleared and drawn all over.
+    stale: Cell<bool>,
+}
+
+fn colour(c: Colour) -> style::Color {
+    use style::Color;
+
+    match c {
+        Colour::Index(0) => Color::Black,
+        Colour::Index(1) => Color::DarkRed,
+        Colour::Index(2) => Color::DarkGreen,
+        Colour::Index(3) => Color::DarkYellow,
+        Colour::Index(4) => Color::DarkBlue,
+        Colour::Index(5) => Color::DarkMagenta,
+        Colour::Index(6) => Color::DarkCyan,
+        Colour::Index(7) => Color::Grey,
+        Colour::Index(n) => Color::AnsiValue(n),
+        Colour::Rgb(r, g, b) => Color::Rgb { r, g, b },
+    }
+}
+
+fn input(e: Event) -> Option<Input> {
+    let key = match e {
+        Event::Key(key) if key.kind != KeyEventKind::Release => key,
+        Event::Resize(..) => return Some(Input::KeyResize),
+        Event::FocusLost => return Some(Input::FocusLost),
+        Event::FocusGained => return Some(Input::FocusGained),
+        _ => return None,
+    };
+    Some(match key.code {
+        // Like the control characters curses gives.
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) && c.is_ascii() => {
+            Input::Character((c.to_ascii_lowercase() as u8 & 0x1f) as char)
+        }
+        KeyCode::Char(c) => Input::Character(c),
+        KeyCode::Enter => Input::KeyEnter,
+        KeyCode::Esc => Input::Character('\x1b'),
+        KeyCode::Tab => Input::Character('\t'),
+        KeyCode::Backspace => Input::KeyBackspace,
+        KeyCode::Up => Input::KeyUp,
+        KeyCode::Down => Input::KeyDown,
+        KeyCode::Left => Input::KeyLeft,
+        KeyCode::Right => Input::KeyRight,
+        KeyCode::Delete => Input::KeyDC,
+        KeyCode::Insert => Input::KeyIC,
+        KeyCode::Home => Input::KeyHome,
+        KeyCode::End => Input::KeyEnd,
+        KeyCode::PageUp => Input::KeyPPage,
+        KeyCode::PageDown => Input::KeyNPage,
+        KeyCode::F(n) => Input::KeyF(n),
+        _ => return None,
+    })
+}
+
+impl Terminal<BufWriter<Stdout>> {
+    /// Takes over the terminal.
+    pub fn new() -> Result<Self, String> {
+        terminal::enable_raw_mode().map_err(|e| format!("Can't use the terminal: {}", e))?;
+        let env = |v: &str| std::env::var(v).unwrap_or_default();
+        let colours = if env("COLORTERM") == "truecolor"
+            || env("COLORTERM") == "24bit"
+            || env("TERM").contains("256color")
+        {
+            256
+        } else {
+            8
+        };
+        let (columns, lines) = terminal::size().unwrap_or((80, 24));
+        let t = Terminal::with_writer(
+            BufWriter::new(std::io::stdout()),
+            colours,
+            (i32::from(columns), i32::from(lines)),
+        );
+        {
+            let mut out = t.out.borrow_mut();
+            let _ = out.queue(terminal::EnterAlternateScreen);
+            let _ = out.queue(cursor::Hide);
+            let _ = out.queue(event::EnableFocusChange);
+            let _ = out.flush();
+        }
+        Ok(t)
+    }
+}
+
+impl<W: Write> Terminal<W> {
+    /// Draws on `out`, as a terminal of `size` columns and lines.
+    pub fn with_writer(out: W, colours: i32, size: (i32, i32)) -> Self {
+        Terminal {
+            out: RefCell::new(out),
+            colours,
+            size: Cell::new(size),
+            next: RefCell::new(Memory::new(size.0, size.1)),
+            shown: RefCell::new(Memory::new(size.0, size.1)),
+            stale: Cell::new(true),
+        }
+    }
+
+    /// Sends the whole frame at the next `present`, not only what
+    /// changed.
+    pub fn redraw_all(&self) {
+        self.stale.set(true);
+    }
+
+    /// What has been written so far, it's written on from scratch.
+    pub fn take_output(&self) -> W
+    where
+        W: Default,
+    {
+        std::mem::take(&mut *self.out.borrow_mut())
+    }
+
+    fn resize(&self, columns: u16, lines: u16) {
+        let size = (i32::from(columns), i32::from(lines));
+        self.size.set(size);
+        self.next.replace(Memory::new(size.0, size.1));
+        self.shown.replace(Memory::new(size.0, size.1));
+        self.stale.set(true);
+    }
+}
+
+impl<W: Write> Renderer for Terminal<W> {
+    fn size(&self) -> (i32, i32) {
+        self.size.get()
+    }
+
+    fn colours(&self) -> i32 {
+        self.colours
+    }
+
+    fn can_change_colour(&self) -> bool {
+        // The true colours are sent as they are.
+        true
+    }
+
+    fn erase(&self) {
+        self.next.borrow().erase();
+    }
+
+    fn put(&self, x: i32, y: i32, s: &str, style: Style) {
+        self.next.borrow().put(x, y, s, style);
+    }
+
+    fn clear_line(&self, x: i32, y: i32) {
+        self.next.borrow().clear_line(x, y);
+    }
+
+    /// Terminals that can, show the whole frame at once instead of as it
+    /// is drawn, so it doesn't flicker.
+    fn present(&self) {
+        let (next, shown) = (self.next.borrow(), self.shown.borrow());
+        let stale = self.stale.replace(false);
+        if stale {
+            shown.erase();
+        }
+        let changes = next.changes(&shown);
+        if !stale && changes.is_empty() {
+            return;
+        }
+        let mut out = self.out.borrow_mut();
+        let _ = out.queue(terminal::BeginSynchronizedUpdate);
+        if stale {
+            let _ = out.queue(style::ResetColor);
+            let _ = out.queue(terminal::Clear(ClearType::All));
+        }
+        let mut colours = None;
+        for (x, y, text, s) in changes {
+            let _ = out.queue(cursor::MoveTo(x as u16, y as u16));
+            if colours != Some(s) {
+                let c = style::Colors::new(colour(s.fg), colour(s.bg));
+                let _ = out.queue(style::SetColors(c));
+                colours = Some(s);
+            }
+            let _ = out.queue(style::Print(text));
+        }
+        let _ = out.queue(terminal::EndSynchronizedUpdate);
+        let _ = out.flush();
+        shown.copy_from(&next);
+    }
+
+    fn read_key(&self) -> Option<Input> {
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            let e = match event::read() {
+                Ok(e) => e,
+                Err(_) => return None,
+            };
+            if let Event::Resize(columns, lines) = e {
+                self.resize(columns, lines);
+            }
+            if let Some(i) = input(e) {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    fn end(&self) {
+        let mut out = self.out.borrow_mut();
+        let _ = out.queue(style::ResetColor);
+        let _ = out.queue(event::DisableFocusChange);
+        let _ = out.queue(cursor::Show);
+        let _ = out.queue(terminal::LeaveAlternateScreen);
+        let _ = out.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render;
+    use crate::terminal::*;
+
+    #[test]
+    fn sends_only_the_changes() {
+        let t = Terminal::with_writer(vec![], 8, (20, 5));
+        t.put(0, 0, "hello", render::TEXT);
+        t.present();
+        let first = t.take_output();
+        assert!(String::from_utf8_lossy(&first).contains("hello"));
+        t.put(0, 0, "jello", render::TEXT);
+        t.present();
+        let second = String::from_utf8(t.take_output()).unwrap();
+        assert!(second.contains('j') && !second.contains("ello"));
+        // Nothing changed, nothing is sent.
+        t.present();
+        assert!(t.take_output().is_empty());
+    }
+}
@@ -0,0 +1,335 @@
+// SPDX-FileCopyrightText: 2022 Sebastian Andersson <sebastian@bittr.nu>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! How the board looks: the glyphs for the cells and the borders and
+//! the colours, picked after what the terminal can show.
+
+use std::collections::HashMap;
+use tetris::config::Colours;
+use tetris::config::Glyphs;
+
+use tetris::model::PieceType;
+use tetris::registry::Registry;
+
+use crate::render;
+use crate::render::Colour;
+use crate::render::Renderer;
+use crate::render::Style;
+
+/// What a cell shows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tile {
+    Empty,
+    Block(PieceType),
+    /// A cell lit up by an animation.
+    Flash,
+    /// Where a hard dropped piece fell through.
+    Trail,
+    /// Nothing at all, when the board is hidden.
+    Hidden,
+}
+
+/// The glyphs of a border.
+pub struct Border {
+    pub horizontal: &'static str,
+    pub vertical: &'static str,
+    pub top_left: &'static str,
+    pub top_right: &'static str,
+    pub bottom_left: &'static str,
+    pub bottom_right: &'static str,
+    /// Where a line meets the border from the right.
+    pub tee_left: &'static str,
+    /// Where a line meets the border from the left.
+    pub tee_right: &'static str,
+}
+
+const ASCII_BORDER: Border = Border {
+    horizontal: "-",
+    vertical: "|",
+    top_left: "+",
+    top_right: "+",
+    bottom_left: "+",
+    bottom_right: "+",
+    tee_left: "+",
+    tee_right: "+",
+};
+
+const BOX_BORDER: Border = Border {
+    horizontal: "─",
+    vertical: "│",
+    top_left: "┌",
+    top_right: "┐",
+    bottom_left: "└",
+    bottom_right: "┘",
+    tee_left: "├",
+    tee_right: "┤",
+};
+
+/// What the terminal can do.
+#[derive(Clone, Copy, Debug)]
+pub struct Caps {
+    pub colours: i32,
+    pub can_change_colour: bool,
+    /// $COLORTERM says the terminal shows 24 bit colours.
+    pub truecolour: bool,
+    pub utf8: bool,
+}
+
+/// The colours of the empty cells, the flashes and the trails. The
+/// blocks get theirs from the registry.
+const BASIC: [Colour; 3] = [render::BLACK, render::WHITE, render::YELLOW];
+
+const ANSI_256: [u8; 3] = [16, 231, 240];
+
+const RGB: [(u8, u8, u8); 3] = [(0, 0, 0), (255, 255, 255), (64, 64, 64)];
+
+/// The curses backend puts the true colours in the palette from here
+/// on.
+const FIRST_RGB: i32 = 16;
+
+/// The true colours needed for the seven pieces, garbage and the
+/// tiles above.
+const RGB_COLOURS: i32 = 11;
+
+/// Picks the glyphs and colours to use, `Auto` never comes back.
+pub fn resolve(glyphs: Glyphs, colours: Colours, caps: Caps) -> (Glyphs, Colours) {
+    let glyphs = match glyphs {
+        Glyphs::Auto if caps.utf8 => Glyphs::Blocks,
+        Glyphs::Auto => Glyphs::Ascii,
+        g => g,
+    };
+    let true_ok = caps.can_change_colour && caps.colours >= FIRST_RGB + RGB_COLOURS;
+    let colours = match colours {
+        Colours::Auto if caps.truecolour && true_ok => Colours::TrueColour,
+        Colours::TrueColour if true_ok => Colours::TrueColour,
+        Colours::Auto | Colours::TrueColour | Colours::Ansi256 if caps.colours >= 256 => {
+            Colours::Ansi256
+        }
+        _ => Colours::Basic,
+    };
+    (glyphs, colours)
+}
+
+/// How much of the screen the cells take.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Geometry {
+    /// Two rows in each line and one column for each cell.
+    pub half_blocks: bool,
+}
+
+impl Geometry {
+    /// The terminal columns `cells` cells take.
+    pub fn columns(self, cells: u16) -> i32 {
+        if self.half_blocks {
+            i32::from(cells)
+        } else {
+            i32::from(cells) * 2
+        }
+    }
+
+    /// The terminal lines `rows` rows take.
+    pub fn lines(self, rows: u16) -> i32 {
+        if self.half_blocks {
+            (i32::from(rows) + 1) / 2
+        } else {
+            i32::from(rows)
+        }
+    }
+}
+
+/// The glyphs and colours, set up for the terminal.
+pub struct Theme {
+    pub glyphs: Glyphs,
+    pub colours: Colours,
+    palette: [Colour; 3],
+    /// The colour and ascii glyph of the blocks of each piece type.
+    blocks: HashMap<PieceType, (Colour, String)>,
+}
+
+impl Theme {
+    /// The theme for the terminal, with the blocks looking as in the
+    /// `registry`.
+    pub fn new(glyphs: Glyphs, colours: Colours, registry: &Registry, r: &dyn Renderer) -> Self {
+        let utf8 = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .filter_map(|v| std::env::var(v).ok())
+            .find(|v| !v.is_empty())
+            .map_or(false, |v| {
+                let v = v.to_lowercase();
+                v.contains("utf-8") || v.contains("utf8")
+            });
+        let caps = Caps {
+            colours: r.colours(),
+            can_change_colour: r.can_change_colour(),
+            truecolour: std::env::var("COLORTERM")
+                .map_or(false, |v| v == "truecolor" || v == "24bit"),
+            utf8,
+        };
+        let (glyphs, colours) = resolve(glyphs, colours, caps);
+        let palette = match colours {
+            Colours::TrueColour => RGB.map(|(r, g, b)| Colour::Rgb(r, g, b)),
+            Colours::Ansi256 => ANSI_256.map(Colour::Index),
+            _ => BASIC,
+        };
+        let blocks = registry
+            .materials()
+            .iter()
+            .map(|m| {
+                let (r, g, b) = m.rgb;
+                let colour = match colours {
+                    Colours::TrueColour => Colour::Rgb(r, g, b),
+                    Colours::Ansi256 => Colour::Index(m.ansi256),
+                    _ => Colour::Index(m.basic),
+                };
+                (m.piece_type, (colour, m.glyph.to_string().repeat(2)))
+            })
+            .collect();
+        Theme {
+            glyphs,
+            colours,
+            palette,
+            blocks,
+        }
+    }
+
+    fn colour(&self, t: Tile) -> Colour {
+        match t {
+            Tile::Block(PieceType::NONE) | Tile::Empty | Tile::Hidden => self.palette[0],
+            Tile::Block(p) => self.blocks.get(&p).map_or(render::WHITE, |b| b.0),
+            Tile::Flash => self.palette[1],
+            Tile::Trail => self.palette[2],
+        }
+    }
+
+    /// The glyph of a block when the glyphs are ascii.
+    fn glyph(&self, t: Tile) -> &str {
+        match t {
+            Tile::Block(p) => self.blocks.get(&p).map_or("XX", |b| &b.1),
+            _ => "XX",
+        }
+    }
+
+    pub fn border(&self) -> &'static Border {
+        match self.glyphs {
+            Glyphs::Ascii | Glyphs::Auto => &ASCII_BORDER,
+            _ => &BOX_BORDER,
+        }
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        Geometry {
+            half_blocks: self.glyphs == Glyphs::HalfBlocks,
+        }
+    }
+
+    /// Draws `width` by `height` cells with their top left corner at
+    /// x, y on the screen.
+    pub fn draw_tiles(
+        &self,
+        r: &dyn Renderer,
+        x: i32,
+        y: i32,
+        width: u16,
+        height: u16,
+        tile: &dyn Fn(i32, i32) -> Tile,
+    ) {
+        let black = self.colour(Tile::Empty);
+        let style = |fg, bg| Style { fg, bg };
+        if self.glyphs == Glyphs::HalfBlocks {
+            for line in 0..self.geometry().lines(height) {
+                for cx in 0..i32::from(width) {
+                    let top = tile(cx, 2 * line);
+                    let bottom = if 2 * line + 1 < i32::from(height) {
+                        tile(cx, 2 * line + 1)
+                    } else {
+                        Tile::Hidden
+                    };
+                    let s = style(self.colour(top), self.colour(bottom));
+                    r.put(x + cx, y + line, "▀", s);
+                }
+            }
+            return;
+        }
+        for row in 0..i32::from(height) {
+            for cx in 0..i32::from(width) {
+                let t = tile(cx, row);
+                let c = self.colour(t);
+                let (glyph, s) = match (self.glyphs, t) {
+                    (_, Tile::Hidden) => ("  ", style(c, black)),
+                    (Glyphs::Ascii, Tile::Empty) => ("..", render::TEXT),
+                    (Glyphs::Ascii, Tile::Flash) => ("##", style(black, c)),
+                    (Glyphs::Ascii, Tile::Trail) => ("::", style(c, black)),
+                    (Glyphs::Ascii, _) => (self.glyph(t), style(c, c)),
+                    (_, Tile::Empty) => (" ·", style(self.colour(Tile::Trail), black)),
+                    (_, Tile::Trail) => ("░░", style(c, black)),
+                    (_, _) => ("██", style(c, black)),
+                };
+                r.put(x + 2 * cx, y + row, glyph, s);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::theme::*;
+
+    const CAPS: Caps = Caps {
+        colours: 8,
+        can_change_colour: false,
+        truecolour: false,
+        utf8: false,
+    };
+
+    #[test]
+    fn auto_picks_what_the_terminal_can() {
+        assert_eq!(
+            resolve(Glyphs::Auto, Colours::Auto, CAPS),
+            (Glyphs::Ascii, Colours::Basic)
+        );
+        let caps = Caps {
+            colours: 256,
+            utf8: true,
+            ..CAPS
+        };
+        assert_eq!(
+            resolve(Glyphs::Auto, Colours::Auto, caps),
+            (Glyphs::Blocks, Colours::Ansi256)
+        );
+        let caps = Caps {
+            can_change_colour: true,
+            truecolour: true,
+            ..caps
+        };
+        assert_eq!(
+            resolve(Glyphs::Auto, Colours::Auto, caps).1,
+            Colours::TrueColour
+        );
+    }
+
+    #[test]
+    fn falls_back_to_fewer_colours() {
+        assert_eq!(
+            resolve(Glyphs::Auto, Colours::TrueColour, CAPS).1,
+            Colours::Basic
+        );
+        let caps = Caps {
+            colours: 256,
+            ..CAPS
+        };
+        assert_eq!(
+            resolve(Glyphs::Auto, Colours::TrueColour, caps).1,
+            Colours::Ansi256
+        );
+        assert_eq!(
+            resolve(Glyphs::Auto, Colours::Basic, caps).1,
+            Colours::Basic
+        );
+        // Asked for, they are used even without UTF-8.
+        assert_eq!(
+            resolve(Glyphs::HalfBlocks, Colours::Auto, CAPS).0,
+            Glyphs::HalfBlocks
+        );
+    }
+}
@@ -2,217 +2,768 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::finesse::Trainer;
+use crate::highscore;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::layout::Layout;
+use crate::layout::Rect;
 use crate::model::Board;
+use crate::model::Event;
 use crate::model::Game;
 use crate::model::PieceType;
-use pancurses::Window;
-
-const OFFSET_X: u8 = 2;
-const OFFSET_Y: u8 = 2;
-
-const LINES_OFFSET_X: u8 = 1;
-const LINES_OFFSET_Y: u8 = 1;
-const LINES_WIDTH: u8 = 7;
-
-const SCORE_OFFSET_X: u8 = 1;
-const SCORE_OFFSET_Y: u8 = 3;
-const SCORE_WIDTH: u8 = 7;
-
-const PIECE_OFFSET_X: u8 = 1;
-const PIECE_OFFSET_Y: u8 = 5;
-const PIECE_WIDTH: u8 = 7;
-const PIECE_HEIGHT: u8 = 5;
-
-fn init_colours() {
-    pancurses::start_color();
-
-    pancurses::init_pair(0, pancurses::COLOR_WHITE, pancurses::COLOR_BLACK);
-    // I CYAN
-    pancurses::init_pair(1, pancurses::COLOR_CYAN, pancurses::COLOR_CYAN);
-    // J BLUE
-    pancurses::init_pair(2, pancurses::COLOR_BLUE, pancurses::COLOR_BLUE);
-    // L ORANGE
-    pancurses::init_pair(3, pancurses::COLOR_WHITE, pancurses::COLOR_WHITE);
-    // O YELLOW
-    pancurses::init_pair(4, pancurses::COLOR_YELLOW, pancurses::COLOR_YELLOW);
-    // S LIME
-    pancurses::init_pair(5, pancurses::COLOR_GREEN, pancurses::COLOR_GREEN);
-    // T PURPLE
-    pancurses::init_pair(6, pancurses::COLOR_MAGENTA, pancurses::COLOR_MAGENTA);
-    // Z RED
-    pancurses::init_pair(7, pancurses::COLOR_RED, pancurses::COLOR_RED);
-
-    // Board decoration colour
-    pancurses::init_pair(8, pancurses::COLOR_YELLOW, pancurses::COLOR_BLACK);
-}
-
-fn add_line(win: &Window) {
-    // Should really use a width parameter.
-    win.addstr("+--------+");
-}
-
-fn draw_board_decoration(win: &Window, width: u8, height: u8) {
-    win.color_set(8);
-    for y in (OFFSET_Y)..(OFFSET_Y + height) {
-        win.mvaddch(i32::from(y), i32::from(OFFSET_X - 1), '|');
-        win.mvaddch(i32::from(y), i32::from(OFFSET_X + width * 2), '|');
-    }
-    for x in (OFFSET_X)..(OFFSET_X + width * 2) {
-        win.mvaddch(i32::from(OFFSET_Y - 1), i32::from(x), '-');
-        win.mvaddch(i32::from(OFFSET_Y + height), i32::from(x), '-');
-    }
-    win.mvaddch(i32::from(OFFSET_Y - 1), i32::from(OFFSET_X - 1), '+');
-    win.mvaddch(
-        i32::from(OFFSET_Y - 1),
-        i32::from(OFFSET_X + width * 2),
-        '+',
+use crate::puzzle::Attempt;
+use crate::puzzle::Status;
+use crate::render::Renderer;
+use crate::render::DECORATION;
+use crate::render::SELECTED;
+use crate::theme::Theme;
+use crate::theme::Tile;
+
+const MENU_OFFSET_X: i32 = 4;
+const MENU_OFFSET_Y: i32 = 2;
+
+/// Draws a line `width` long across the panels, from the board's right
+/// border at x, y to `end`.
+fn add_line(screen: &dyn Renderer, theme: &Theme, x: i32, y: i32, width: usize, end: &str) {
+    let b = theme.border();
+    let line = format!("{}{}{}", b.tee_left, b.horizontal.repeat(width), end);
+    screen.put(x, y, &line, DECORATION);
+}
+
+fn draw_board_decoration(screen: &dyn Renderer, theme: &Theme, l: &Layout) {
+    let b = theme.border();
+    let (left, right) = (l.board.x - 1, l.board.right());
+    let (top, bottom) = (l.board.y - 1, l.board.bottom());
+    for y in l.board.y..bottom {
+        screen.put(left, y, b.vertical, DECORATION);
+        screen.put(right, y, b.vertical, DECORATION);
+    }
+    let horizontal = b.horizontal.repeat(l.board.width as usize);
+    screen.put(
+        left,
+        top,
+        &format!("{}{}{}", b.top_left, horizontal, b.top_right),
+        DECORATION,
     );
-    win.mvaddch(i32::from(OFFSET_Y + height), i32::from(OFFSET_X - 1), '+');
-    win.mvaddch(
-        i32::from(OFFSET_Y + height),
-        i32::from(OFFSET_X + width * 2),
-        '+',
+    screen.put(
+        left,
+        bottom,
+        &format!("{}{}{}", b.bottom_left, horizontal, b.bottom_right),
+        DECORATION,
+    );
+
+    let width = l.panel.width as usize;
+    add_line(screen, theme, right, l.panel.y - 1, width, b.top_right);
+    add_line(screen, theme, right, l.score.y - 1, width, b.tee_right);
+    add_line(screen, theme, right, l.score.bottom(), width, b.tee_right);
+    add_line(screen, theme, right, l.hold.y - 1, width, b.tee_right);
+    add_line(
+        screen,
+        theme,
+        right,
+        l.panel.bottom(),
+        width,
+        b.bottom_right,
     );
 
-    win.mv(
-        i32::from(OFFSET_Y + LINES_OFFSET_Y),
-        i32::from(OFFSET_X + width * 2 + LINES_OFFSET_X - 1),
+    let boxes = [l.lines, l.score, l.hold];
+    for r in boxes.iter().chain(&l.queue) {
+        for y in r.y..r.bottom() {
+            screen.put(l.panel.right(), y, b.vertical, DECORATION);
+        }
+    }
+}
+
+/// Clears the screen and draws the decoration for a new board.
+pub fn reset(screen: &dyn Renderer, theme: &Theme, l: &Layout) {
+    screen.erase();
+    draw_board_decoration(screen, theme, l);
+}
+
+/// Shown in place of the game when it doesn't fit on the screen.
+pub fn draw_too_small(screen: &dyn Renderer, needed: (i32, i32)) {
+    let (width, _) = screen.size();
+    screen.erase();
+    let line = Rect {
+        x: 0,
+        y: 0,
+        width,
+        height: 1,
+    };
+    add_clipped(screen, line, 0, "Terminal too small");
+    add_clipped(
+        screen,
+        line,
+        1,
+        &format!("Make it {}x{}", needed.0, needed.1),
     );
-    add_line(win);
+}
+
+/// Writes `s` on line `y` of `r`, cut to fit, and clears the rest of the
+/// screen's line.
+fn add_clipped(screen: &dyn Renderer, r: Rect, y: i32, s: &str) {
+    screen.clear_line(r.x, r.y + y);
+    let s = s.chars().take(r.width.max(0) as usize).collect::<String>();
+    screen.put(r.x, r.y + y, &s, DECORATION);
+}
+
+pub fn draw_menu(screen: &dyn Renderer, title: &str, items: &[String], selected: usize) {
+    screen.erase();
+    screen.put(MENU_OFFSET_X, MENU_OFFSET_Y, title, DECORATION);
+    for (i, item) in items.iter().enumerate() {
+        let style = if i == selected { SELECTED } else { DECORATION };
+        screen.put(MENU_OFFSET_X, MENU_OFFSET_Y + 2 + i as i32, item, style);
+    }
+}
+
+fn draw_lines(g: &Game, screen: &dyn Renderer, l: &Layout) {
+    screen.put(l.lines.x, l.lines.y, &g.lines.to_string(), DECORATION);
+}
+
+fn draw_score(g: &Game, screen: &dyn Renderer, l: &Layout) {
+    screen.put(l.score.x, l.score.y, &g.score.to_string(), DECORATION);
+}
+
+fn board_tile(board: &Board, x: i32, y: i32) -> Tile {
+    match board.cell(x, y) {
+        PieceType::NONE => Tile::Empty,
+        t => Tile::Block(t),
+    }
+}
+
+fn draw_board(screen: &dyn Renderer, theme: &Theme, board: &Board, r: Rect) {
+    theme.draw_tiles(screen, r.x, r.y, board.width(), board.height(), &|x, y| {
+        board_tile(board, x, y)
+    });
+}
 
-    win.mvaddch(
-        i32::from(OFFSET_Y + LINES_OFFSET_Y + 1),
-        i32::from(OFFSET_X + width * 2 + LINES_OFFSET_X + LINES_WIDTH + 1),
-        '|',
+fn draw_next_piece(g: &Game, screen: &dyn Renderer, theme: &Theme, l: &Layout) {
+    let size = u16::from(l.piece_size);
+    let mut piece_board = Board::new(size, size);
+    let middle = i32::from(size / 2);
+    piece_board.draw(&g.next_piece, middle, middle);
+    draw_board(screen, theme, &piece_board, l.queue[0]);
+}
+
+/// Draws the held piece, on its flattest side to fit in the box.
+fn draw_hold(g: &Game, screen: &dyn Renderer, theme: &Theme, l: &Layout) {
+    let mut hold_board = Board::new(u16::from(l.piece_size), u16::from(l.hold_rows));
+    if let Some(p) = &g.hold {
+        let p = p.flattest();
+        let middle = i32::from(l.piece_size / 2);
+        hold_board.draw(&p, middle, i32::from(p.curr().offset().1));
+    }
+    draw_board(screen, theme, &hold_board, l.hold);
+}
+
+// Move to different place.
+pub fn draw_in_win(g: &Game, screen: &dyn Renderer, theme: &Theme, l: &Layout) {
+    draw_board(screen, theme, &g.board, l.board);
+
+    draw_lines(g, screen, l);
+    draw_score(g, screen, l);
+    draw_next_piece(g, screen, theme, l);
+    draw_hold(g, screen, theme, l);
+}
+
+pub fn draw_finesse(t: &Trainer, screen: &dyn Renderer, l: &Layout) {
+    add_clipped(
+        screen,
+        l.finesse,
+        0,
+        &format!("Finesse {:.0}%", t.accuracy()),
     );
+    add_clipped(screen, l.finesse, 1, &format!("Faults {}", t.faults));
+    let optimal = match &t.last {
+        Some(f) if f.faults > 0 => crate::finesse::describe(&f.optimal),
+        _ => String::new(),
+    };
+    add_clipped(screen, l.finesse, 2, &optimal);
+}
+
+/// Draws the stats in `r`, left of the board.
+pub fn draw_stats(g: &Game, screen: &dyn Renderer, r: Rect) {
+    let s = &g.stats;
+    let mut lines = vec![
+        format!("Time    {}", highscore::format_time(g.time.as_secs())),
+        format!("Pieces  {}", s.pieces),
+        format!("PPS     {:.2}", s.pps(g.time)),
+        format!("APM     {:.1}", s.apm(g.time)),
+        format!("KPP     {:.2}", s.kpp()),
+        format!("Faults  {}", s.faults),
+        format!("Drought {}", s.drought),
+        String::new(),
+    ];
+    for pair in s.counts.chunks(2) {
+        let count = |(t, n): &(PieceType, u32)| format!("{} {}", t.to_char(), n);
+        lines.push(match pair {
+            [a, b] => format!("{:7}{}", count(a), count(b)),
+            _ => count(&pair[0]),
+        });
+    }
+    for (y, line) in lines.iter().take(r.height as usize).enumerate() {
+        // Padded, not cleared to the end of the line, the board is
+        // to the right.
+        let line = format!("{:1$}", line, r.width as usize);
+        let line = line.chars().take(r.width as usize).collect::<String>();
+        screen.put(r.x, r.y + y as i32, &line, DECORATION);
+    }
+}
 
-    win.mv(
-        i32::from(OFFSET_Y + SCORE_OFFSET_Y),
-        i32::from(OFFSET_X + width * 2 + SCORE_OFFSET_X - 1),
+pub fn draw_puzzle(a: &Attempt, screen: &dyn Renderer, l: &Layout) {
+    add_clipped(screen, l.puzzle, 0, &a.puzzle.name);
+    add_clipped(screen, l.puzzle, 1, &a.puzzle.describe());
+    add_clipped(
+        screen,
+        l.puzzle,
+        2,
+        &format!("Pieces left {}", a.pieces_left()),
     );
-    add_line(win);
+    let status = match a.status {
+        Status::Playing => "",
+        Status::Solved => "Solved!",
+        Status::Failed => "Failed.",
+    };
+    add_clipped(screen, l.puzzle, 3, status);
+}
+
+pub fn draw_title(screen: &dyn Renderer, items: &[String], selected: usize, message: Option<&str>) {
+    draw_menu(screen, "rust-teris", items, selected);
+    draw_message(screen, items.len(), message);
+}
 
-    win.mvaddch(
-        i32::from(OFFSET_Y + SCORE_OFFSET_Y + 1),
-        i32::from(OFFSET_X + width * 2 + SCORE_OFFSET_X + SCORE_WIDTH + 1),
-        '|',
+/// Shows a message below a menu, `line` lines below its first item.
+pub fn draw_message(screen: &dyn Renderer, line: usize, message: Option<&str>) {
+    let y = MENU_OFFSET_Y + 3 + line as i32;
+    screen.clear_line(MENU_OFFSET_X, y);
+    if let Some(m) = message {
+        screen.put(MENU_OFFSET_X, y, m, DECORATION);
+    }
+}
+
+/// Draws a board's area empty.
+fn draw_concealed(screen: &dyn Renderer, theme: &Theme, width: u16, height: u16, r: Rect) {
+    theme.draw_tiles(screen, r.x, r.y, width, height, &|_, _| Tile::Hidden);
+}
+
+/// Draws the pause menu in place of the board and the next piece, so
+/// they can't be studied while the time stands still.
+pub fn draw_pause(
+    g: &Game,
+    screen: &dyn Renderer,
+    theme: &Theme,
+    l: &Layout,
+    items: &[String],
+    selected: usize,
+) {
+    draw_concealed(screen, theme, g.board.width(), g.board.height(), l.board);
+    let size = u16::from(l.piece_size);
+    draw_concealed(screen, theme, size, size, l.queue[0]);
+    draw_concealed(screen, theme, size, u16::from(l.hold_rows), l.hold);
+
+    draw_lines(g, screen, l);
+    draw_score(g, screen, l);
+
+    // Narrow boards get the menu closer to the border.
+    let x = l.board.x + 2.min((l.board.width - 8).max(0) / 2);
+    let y = l.board.y + 2;
+    screen.put(x, y, "PAUSED", DECORATION);
+    for (i, item) in items.iter().enumerate() {
+        let style = if i == selected { SELECTED } else { DECORATION };
+        screen.put(x, y + 2 + i as i32, item, style);
+    }
+}
+
+pub fn draw_game_over(screen: &dyn Renderer, summary: &[String]) {
+    screen.erase();
+    screen.put(MENU_OFFSET_X, MENU_OFFSET_Y, "Game over", DECORATION);
+    for (i, line) in summary.iter().enumerate() {
+        screen.put(
+            MENU_OFFSET_X,
+            MENU_OFFSET_Y + 2 + i as i32,
+            line,
+            DECORATION,
+        );
+    }
+    screen.put(
+        MENU_OFFSET_X,
+        MENU_OFFSET_Y + 3 + summary.len() as i32,
+        "Press enter",
+        DECORATION,
     );
+}
 
-    win.mv(
-        i32::from(OFFSET_Y + SCORE_OFFSET_Y + 2),
-        i32::from(OFFSET_X + width * 2 + SCORE_OFFSET_X - 1),
+pub fn draw_history(screen: &dyn Renderer, report: &[String]) {
+    screen.erase();
+    screen.put(MENU_OFFSET_X, MENU_OFFSET_Y, "Statistics", DECORATION);
+    for (i, line) in report.iter().enumerate() {
+        screen.put(
+            MENU_OFFSET_X,
+            MENU_OFFSET_Y + 2 + i as i32,
+            line,
+            DECORATION,
+        );
+    }
+    screen.put(
+        MENU_OFFSET_X,
+        MENU_OFFSET_Y + 3 + report.len() as i32,
+        "q to go back",
+        DECORATION,
     );
-    add_line(win);
+}
 
-    win.mv(
-        i32::from(OFFSET_Y + PIECE_OFFSET_Y + PIECE_HEIGHT),
-        i32::from(OFFSET_X + width * 2 + PIECE_OFFSET_X - 1),
+pub fn draw_name_entry(screen: &dyn Renderer, name: &str) {
+    screen.erase();
+    screen.put(MENU_OFFSET_X, MENU_OFFSET_Y, "New high score!", DECORATION);
+    screen.put(
+        MENU_OFFSET_X,
+        MENU_OFFSET_Y + 2,
+        &format!("Name: {}_", name),
+        DECORATION,
     );
-    add_line(win);
+}
 
-    for y in 1..PIECE_HEIGHT {
-        win.mvaddch(
-            i32::from(OFFSET_Y + PIECE_OFFSET_Y + y),
-            i32::from(OFFSET_X + width * 2 + PIECE_OFFSET_X + PIECE_WIDTH + 1),
-            '|',
+pub fn draw_high_scores(screen: &dyn Renderer, mode: &str, table: &[&highscore::Entry]) {
+    screen.erase();
+    let x = MENU_OFFSET_X;
+    screen.put(
+        x,
+        MENU_OFFSET_Y,
+        &format!("High scores - {}", mode),
+        DECORATION,
+    );
+    screen.put(
+        x,
+        MENU_OFFSET_Y + 2,
+        &format!(
+            "   {:16} {:>7} {:>5} {:>5} {:>6}  {}",
+            "Name", "Score", "Lines", "Level", "Time", "Date"
+        ),
+        DECORATION,
+    );
+    for (i, e) in table.iter().enumerate() {
+        screen.put(
+            x,
+            MENU_OFFSET_Y + 3 + i as i32,
+            &format!(
+                "{:2} {:16} {:>7} {:>5} {:>5} {:>6}  {}",
+                i + 1,
+                e.name,
+                e.score,
+                e.lines,
+                e.level,
+                highscore::format_time(e.seconds),
+                e.date
+            ),
+            DECORATION,
         );
     }
+    screen.put(
+        x,
+        MENU_OFFSET_Y + 4 + highscore::TABLE_SIZE as i32,
+        "h/l to change mode, q to go back",
+        DECORATION,
+    );
 }
 
-/// Inits the curses.
-pub fn init(width: u8, height: u8) -> Window {
-    let win = pancurses::initscr();
-    win.nodelay(true);
-    win.scrollok(false);
-    pancurses::cbreak();
-    pancurses::noecho();
-    pancurses::curs_set(0);
+/// Shows an exported position below the board.
+pub fn draw_export(s: &str, screen: &dyn Renderer, l: &Layout) {
+    add_clipped(screen, l.export, 0, s);
+}
 
-    init_colours();
-    draw_board_decoration(&win, width, height);
+const LOCK_FLASH: Duration = Duration::from_millis(100);
+const TRAIL: Duration = Duration::from_millis(150);
+const BANNER: Duration = Duration::from_secs(1);
+const TOP_OUT: Duration = Duration::from_secs(1);
 
-    win
+/// What an animation shows on the board.
+enum Effect {
+    /// Full rows that flash and then dissolve from the middle out.
+    Clear(Vec<i32>),
+    /// A piece that just locked.
+    Lock(Vec<(i32, i32)>),
+    /// The cells a hard dropped piece fell through.
+    Trail(Vec<(i32, i32)>),
+    LevelUp(u16),
+    /// The board fills up from the bottom.
+    TopOut,
 }
 
-fn draw_lines(g: &Game, win: &Window, width: u8) {
-    win.mv(
-        i32::from(OFFSET_Y + LINES_OFFSET_Y + 1),
-        i32::from(width * 2u8 + OFFSET_X + LINES_OFFSET_X + 1),
-    );
-    let lines = g.lines.to_string();
-    win.addstr(lines);
+struct Animation {
+    effect: Effect,
+    start: Instant,
+    length: Duration,
 }
 
-fn draw_score(g: &Game, win: &Window, width: u8) {
-    win.mv(
-        i32::from(OFFSET_Y + SCORE_OFFSET_Y + 1),
-        i32::from(width * 2u8 + OFFSET_X + SCORE_OFFSET_X + 1),
-    );
-    let score = g.score.to_string();
-    win.addstr(score);
-}
-
-fn set_color(win: &Window, c: &PieceType) {
-    let cp = match *c {
-        PieceType::None => 0,
-        PieceType::I => 1,
-        PieceType::J => 2,
-        PieceType::L => 3,
-        PieceType::O => 4,
-        PieceType::S => 5,
-        PieceType::T => 6,
-        PieceType::Z => 7,
-    };
-    win.color_set(cp);
-}
-
-fn draw_board(win: &Window, board: &Board, x_pos: u8, y_pos: u8) {
-    let width = board.width() as usize;
-    for y in 0..board.height() {
-        win.mv(i32::from(y + y_pos), i32::from(x_pos));
-        for x in 0..width {
-            let o = match board.map[x + y as usize * width].clone() {
-                PieceType::None => {
-                    set_color(win, &PieceType::None);
-                    '.'
-                }
-                c => {
-                    set_color(win, &c);
-                    'X'
-                }
-            };
-            win.addch(o);
-            win.addch(o);
+impl Animation {
+    /// How far it has come, from 0 to 1.
+    fn progress(&self) -> f64 {
+        if self.length == Duration::ZERO {
+            return 1.0;
         }
+        (self.start.elapsed().as_secs_f64() / self.length.as_secs_f64()).min(1.0)
     }
 }
 
-fn draw_next_piece(g: &Game, win: &Window, width: u8) {
-    let mut piece_board = Board::new(4, 4);
-    piece_board.draw(&g.next_piece, 2, 2);
-    draw_board(
-        win,
-        &piece_board,
-        width * 2 + OFFSET_X + PIECE_OFFSET_X,
-        1 + OFFSET_Y + PIECE_OFFSET_Y,
-    );
+/// The animations drawn on top of the board. They never hold up the
+/// game, they are only drawn while it goes on.
+pub struct Animations {
+    enabled: bool,
+    running: Vec<Animation>,
 }
 
-// Move to different place.
-pub fn draw_in_win(g: &Game, win: &Window) {
-    let width = g.board.width();
-    draw_board(win, &g.board, OFFSET_X, OFFSET_Y);
+impl Animations {
+    pub fn new(enabled: bool) -> Self {
+        Animations {
+            enabled,
+            running: vec![],
+        }
+    }
 
-    win.color_set(8);
-    draw_lines(g, win, width);
-    draw_score(g, win, width);
-    draw_next_piece(g, win, width);
-}
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.skip();
+        }
+    }
+
+    /// Starts the animations that go with something in the game.
+    pub fn on_event(&mut self, e: &Event, g: &Game) {
+        if !self.enabled {
+            return;
+        }
+        self.running.retain(|a| a.progress() < 1.0);
+        let (effect, length) = match e {
+            // The rows are already gone without a delay.
+            Event::Cleared(rows) if g.line_clear_delay > Duration::ZERO => {
+                (Effect::Clear(rows.clone()), g.line_clear_delay)
+            }
+            Event::Cleared(_) | Event::Moved(_) => return,
+            Event::Locked(lock) => (
+                Effect::Lock(g.board.cells(&lock.piece, lock.pos.0, lock.pos.1)),
+                LOCK_FLASH,
+            ),
+            Event::HardDropped { piece, x, from, to } => {
+                let end = g.board.cells(piece, *x, *to);
+                let mut trail = vec![];
+                for (cx, cy) in g.board.cells(piece, *x, *from) {
+                    let top = end.iter().filter(|c| c.0 == cx).map(|c| c.1).min();
+                    if let Some(top) = top {
+                        trail.extend((cy..top).map(|y| (cx, y)));
+                    }
+                }
+                (Effect::Trail(trail), TRAIL)
+            }
+            Event::LevelUp(level) => (Effect::LevelUp(*level), BANNER),
+            Event::GameOver => (Effect::TopOut, TOP_OUT),
+        };
+        self.running.push(Animation {
+            effect,
+            start: Instant::now(),
+            length,
+        });
+    }
+
+    /// Ends all animations at once.
+    pub fn skip(&mut self) {
+        self.running.clear();
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.iter().any(|a| a.progress() < 1.0)
+    }
 
-/// Ends the GUI.
-pub fn end() {
-    pancurses::endwin();
+    /// Draws the board again, with the animations on it.
+    pub fn draw(&self, g: &Game, screen: &dyn Renderer, theme: &Theme, l: &Layout) {
+        let running = self
+            .running
+            .iter()
+            .filter(|a| a.progress() < 1.0)
+            .collect::<Vec<_>>();
+        if running.is_empty() {
+            return;
+        }
+        let (width, height) = (i32::from(g.board.width()), i32::from(g.board.height()));
+        let mut tiles = vec![None; width as usize * height as usize];
+        let mut set = |x: i32, y: i32, t: Tile| {
+            if (0..width).contains(&x) && (0..height).contains(&y) {
+                tiles[x as usize + y as usize * width as usize] = Some(t);
+            }
+        };
+        for a in &running {
+            let t = a.progress();
+            match &a.effect {
+                Effect::Clear(rows) => {
+                    // Flash for the first half, then dissolve.
+                    let flash = (a.start.elapsed().as_millis() / 50) % 2 == 0;
+                    let gone = if t < 0.5 {
+                        0
+                    } else {
+                        ((t - 0.5) * 2.0 * f64::from(width) / 2.0).ceil() as i32
+                    };
+                    for y in rows {
+                        for x in 0..width {
+                            let middle = if x < width / 2 {
+                                width / 2 - 1 - x
+                            } else {
+                                x - width / 2
+                            };
+                            if middle < gone {
+                                set(x, *y, Tile::Hidden);
+                            } else if t < 0.5 && flash {
+                                set(x, *y, Tile::Flash);
+                            }
+                        }
+                    }
+                }
+                Effect::Lock(cells) => {
+                    for (x, y) in cells {
+                        set(*x, *y, Tile::Flash);
+                    }
+                }
+                Effect::Trail(cells) => {
+                    // The trail fades from the top.
+                    let skip = (t * cells.len() as f64) as usize;
+                    for (x, y) in cells.iter().skip(skip) {
+                        set(*x, *y, Tile::Trail);
+                    }
+                }
+                Effect::LevelUp(_) => (),
+                Effect::TopOut => {
+                    let rows = (t * f64::from(height)).ceil() as i32;
+                    for y in height - rows..height {
+                        for x in 0..width {
+                            set(x, y, Tile::Block(PieceType::GARBAGE));
+                        }
+                    }
+                }
+            }
+        }
+        theme.draw_tiles(
+            screen,
+            l.board.x,
+            l.board.y,
+            g.board.width(),
+            g.board.height(),
+            &|x, y| {
+                tiles[x as usize + y as usize * width as usize]
+                    .unwrap_or_else(|| board_tile(&g.board, x, y))
+            },
+        );
+        for a in &running {
+            if let Effect::LevelUp(level) = a.effect {
+                let text = format!(" LEVEL {} ", level);
+                let x = l.board.x + (l.board.width - text.len() as i32) / 2;
+                screen.put(x.max(0), l.board.y + l.board.height / 3, &text, SELECTED);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use tetris::config::Colours;
+    use tetris::config::Glyphs;
+    use tetris::model::PieceFactory;
+    use tetris::registry::Registry;
+
+    use crate::memory::Memory;
+    use crate::render;
+    use crate::view::*;
+
+    /// Draws the game on a screen just big enough for it.
+    fn draw(g: &Game, glyphs: Glyphs) -> Memory {
+        let screen = Memory::new(31, 24);
+        let theme = Theme::new(glyphs, Colours::Basic, &Registry::default(), &screen);
+        let l = Layout::new(
+            g.board.width(),
+            g.board.height(),
+            1,
+            g.preview_size(),
+            theme.geometry(),
+            false,
+            screen.size(),
+        )
+        .unwrap();
+        reset(&screen, &theme, &l);
+        draw_in_win(g, &screen, &theme, &l);
+        screen
+    }
+
+    fn game() -> Game {
+        Game::new(Board::new(10, 20), PieceFactory::with_seed(1))
+    }
+
+    #[test]
+    fn new_game() {
+        let screen = draw(&game(), Glyphs::Ascii);
+        let expected = "\
++--------------------+
+|....................|
+|....................+--------+
+|....................| 0      |
+|....................+--------+
+|....................| 0      |
+|....................+--------+
+|....................|........|
+|....................|..XXXX..|
+|....................|..XXXX..|
+|....................|........|
+|....................+--------+
+|....................|........|
+|....................|........|
+|....................+--------+
+|....................|
+|....................|
+|....................|
+|....................|
+|....................|
+|....................|
++--------------------+";
+        assert_eq!(screen.text(), expected);
+        assert_eq!(screen.cell(0, 0).style, render::DECORATION);
+        // The O piece in the next box is yellow on yellow.
+        let o = screen.cell(24, 8).style;
+        assert_eq!((o.fg, o.bg), (render::YELLOW, render::YELLOW));
+    }
+
+    #[test]
+    fn dropped_and_held() {
+        let mut g = game();
+        g.fall();
+        g.hold();
+        let screen = draw(&g, Glyphs::Ascii);
+        let expected = "\
++--------------------+
+|......XXXXXXXX......|
+|....................+--------+
+|....................| 0      |
+|....................+--------+
+|....................| 37     |
+|....................+--------+
+|....................|........|
+|....................|..XXXX..|
+|....................|..XXXX..|
+|....................|........|
+|....................+--------+
+|....................|..XXXX..|
+|....................|..XXXX..|
+|....................+--------+
+|....................|
+|....................|
+|....................|
+|....................|
+|..........XXXX......|
+|........XXXX........|
++--------------------+";
+        assert_eq!(screen.text(), expected);
+        // The locked S and the falling I, green and cyan.
+        assert_eq!(screen.cell(9, 20).style.fg, render::Colour::Index(2));
+        assert_eq!(screen.cell(7, 1).style.fg, render::Colour::Index(6));
+    }
+
+    #[test]
+    fn blocks_and_box_drawing() {
+        let mut g = game();
+        g.fall();
+        g.hold();
+        let screen = draw(&g, Glyphs::Blocks);
+        let panel = (2..15).map(|y| screen.line(y)).collect::<Vec<_>>();
+        let expected = [
+            "│ · · · · · · · · · ·├────────┐",
+            "│ · · · · · · · · · ·│ 0      │",
+            "│ · · · · · · · · · ·├────────┤",
+            "│ · · · · · · · · · ·│ 37     │",
+            "│ · · · · · · · · · ·├────────┤",
+            "│ · · · · · · · · · ·│ · · · ·│",
+            "│ · · · · · · · · · ·│ ·████ ·│",
+            "│ · · · · · · · · · ·│ ·████ ·│",
+            "│ · · · · · · · · · ·│ · · · ·│",
+            "│ · · · · · · · · · ·├────────┤",
+            "│ · · · · · · · · · ·│ ·████ ·│",
+            "│ · · · · · · · · · ·│ ·████ ·│",
+            "│ · · · · · · · · · ·├────────┘",
+        ];
+        assert_eq!(panel, expected);
+        assert_eq!(screen.line(1), "│ · · ·████████ · · ·│");
+    }
+
+    #[test]
+    fn paused_hides_the_board() {
+        let g = game();
+        let screen = draw(&g, Glyphs::Ascii);
+        let theme = Theme::new(Glyphs::Ascii, Colours::Basic, &Registry::default(), &screen);
+        let l = Layout::new(10, 20, 1, (4, 2), theme.geometry(), false, screen.size()).unwrap();
+        let items = ["Resume".to_string(), "Quit".to_string()];
+        draw_pause(&g, &screen, &theme, &l, &items, 1);
+        assert_eq!(screen.line(3), "|  PAUSED            | 0      |");
+        assert_eq!(screen.line(6), "|  Quit              +--------+");
+        assert_eq!(screen.cell(3, 6).style, render::SELECTED);
+        assert!(!screen.text().contains('X'));
+    }
+
+    #[test]
+    fn blocks_look_as_registered() {
+        let mut b = Board::new(3, 1);
+        let f = PieceType::custom('f').unwrap();
+        b.map = vec![PieceType::I, PieceType::NONE, f];
+        let mut registry = Registry::default();
+        let orange = tetris::registry::Material::new(f, (240, 160, 0), '#');
+        registry.register(orange).unwrap();
+        let screen = Memory::new(6, 1);
+        let theme = Theme::new(Glyphs::Ascii, Colours::Basic, &registry, &screen);
+        draw_board(
+            &screen,
+            &theme,
+            &b,
+            Rect {
+                x: 0,
+                y: 0,
+                width: 6,
+                height: 1,
+            },
+        );
+        assert_eq!(screen.line(0), "XX..##");
+        assert_eq!(screen.cell(0, 0).style.bg, render::Colour::Index(6));
+        assert_eq!(screen.cell(4, 0).style.bg, render::YELLOW);
+    }
+
+    #[test]
+    fn stats_left_of_the_board() {
+        let mut g = game();
+        g.fall();
+        g.hold();
+        g.time = Duration::from_secs(2);
+        let screen = Memory::new(45, 24);
+        let theme = Theme::new(Glyphs::Ascii, Colours::Basic, &Registry::default(), &screen);
+        let l = Layout::new(10, 20, 1, (4, 2), theme.geometry(), true, screen.size()).unwrap();
+        reset(&screen, &theme, &l);
+        draw_in_win(&g, &screen, &theme, &l);
+        draw_stats(&g, &screen, l.stats.unwrap());
+        let lines = (1..13).map(|y| screen.line(y)).collect::<Vec<_>>();
+        let expected = [
+            "Time    0:02  |......XXXXXXXX......|",
+            "Pieces  1     |....................+--------+",
+            "PPS     0.50  |....................| 0      |",
+            "APM     0.0   |....................+--------+",
+            "KPP     2.00  |....................| 37     |",
+            "Faults  0     |....................+--------+",
+            "Drought 1     |....................|........|",
+            "              |....................|..XXXX..|",
+            "I 0    J 0    |....................|..XXXX..|",
+            "L 0    O 0    |....................|........|",
+            "S 1    T 0    |....................+--------+",
+            "Z 0           |....................|..XXXX..|",
+        ];
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    fn too_small() {
+        let screen = Memory::new(20, 10);
+        draw_too_small(&screen, (31, 24));
+        assert_eq!(screen.text(), "Terminal too small\nMake it 31x24");
+    }
+}